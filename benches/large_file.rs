@@ -0,0 +1,61 @@
+//! Times scanning, parsing and resolving a generated 50k line program, the size
+//! of the files that made parsing and resolving dominate build steps
+//!
+//! Run with `cargo bench --bench large_file`
+
+use std::time::{Duration, Instant};
+
+use rust_lox::{parser::parse, resolver::resolve, scanner::scan_tokens};
+
+const LINES: usize = 50_000;
+const RUNS: usize = 5;
+
+/// Functions, classes, loops and globals, repeated with fresh names until the
+/// program is `LINES` long
+fn generate_program() -> String {
+    // Lines in each repetition
+    const BLOCK_LINES: usize = 13;
+
+    (0..LINES.div_ceil(BLOCK_LINES))
+        .map(|i| {
+            format!(
+                "fun function_{i}(a, b) {{\n    let total = a + b * {i};\n    while total > 0 {{\n        total = total - 1;\n    }}\n    return total;\n}}\nclass Class_{i} {{\n    fun method(x) {{\n        return this.value + x;\n    }}\n}}\nlet value_{i} = function_{i}(1, 2);\n"
+            )
+        })
+        .collect()
+}
+
+/// Shortest time `stage` took over every run, which is the least disturbed by
+/// whatever else the machine is doing
+fn fastest<T>(mut stage: impl FnMut() -> T) -> (T, Duration) {
+    let mut fastest = Duration::MAX;
+    let mut output = None;
+
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let result = stage();
+
+        fastest = fastest.min(start.elapsed());
+        output = Some(result);
+    }
+
+    (output.expect("there's at least one run"), fastest)
+}
+
+fn main() {
+    let program = generate_program();
+
+    let (tokens, scanning) = fastest(|| scan_tokens(&program).expect("the program scans"));
+    let (steps, parsing) = fastest(|| parse(tokens.clone()));
+    let (_, resolving) = fastest(|| resolve(&steps).expect("the program resolves"));
+
+    println!(
+        "{} lines, {} tokens, {} top level steps",
+        program.lines().count(),
+        tokens.len(),
+        steps.len()
+    );
+    println!("scanning   {scanning:?}");
+    println!("parsing    {parsing:?}");
+    println!("resolving  {resolving:?}");
+}
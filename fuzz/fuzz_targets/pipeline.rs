@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_lox::{optimizer, parser, resolver, scanner};
+
+// Scanning, parsing, resolving and optimizing must report errors for any input, never panic
+fuzz_target!(|data: &[u8]| {
+    let source = String::from_utf8_lossy(data);
+
+    if let Ok(tokens) = scanner::scan_tokens(&source) {
+        let steps = parser::parse(tokens);
+
+        if resolver::resolve(&steps).is_ok() {
+            optimizer::optimize(steps);
+        }
+    }
+});
@@ -0,0 +1,339 @@
+#![cfg(test)]
+//! Programs that must behave the same on both backends, including scoping and
+//! closure programs adapted from the test suite of Crafting Interpreters, and
+//! the setup every module's tests use to run a program
+
+use crate::{
+    errors::LoxError,
+    interpreter::{self, functions::native::capture_output, limits::Limits},
+    parser::{parse, ParsingResult},
+    resolver::resolve,
+    run_with_backend,
+    scanner::scan_tokens,
+    vm, Backend, RunStatus,
+};
+
+/// Runs a whole program, which must compile, on `backend` after passing its
+/// resolved steps through `prepare`, with "first" as the only script argument
+pub(crate) fn run_prepared(
+    source: &str,
+    backend: Backend,
+    limits: Limits,
+    prepare: fn(Vec<ParsingResult>) -> Vec<ParsingResult>,
+) -> (Result<(), LoxError>, String) {
+    let steps = parse(scan_tokens(source).unwrap());
+    let variable_map = resolve(&steps).unwrap().variable_map;
+    let steps = prepare(steps);
+    let args = vec!["first".to_owned()];
+
+    capture_output(|| match backend {
+        Backend::TreeWalker => interpreter::interpret(variable_map, &steps, args, limits),
+        Backend::Vm => vm::interpret(variable_map, &steps, args, limits),
+    })
+}
+
+/// Runs a whole program, which must compile, on `backend`
+pub(crate) fn run_on(
+    source: &str,
+    backend: Backend,
+    limits: Limits,
+) -> (Result<(), LoxError>, String) {
+    run_prepared(source, backend, limits, |steps| steps)
+}
+
+/// Runs the prepared program on both backends, checking that they print the
+/// same output and end the same way, which is returned
+pub(crate) fn run_prepared_on_both(
+    source: &str,
+    prepare: fn(Vec<ParsingResult>) -> Vec<ParsingResult>,
+) -> (Result<(), LoxError>, String) {
+    let tree_walker = run_prepared(source, Backend::TreeWalker, Limits::default(), prepare);
+    let virtual_machine = run_prepared(source, Backend::Vm, Limits::default(), prepare);
+
+    assert_eq!(tree_walker, virtual_machine, "{source:?}");
+
+    tree_walker
+}
+
+/// Runs the program on both backends, checking that they print the same output
+/// and end the same way, which is returned
+pub(crate) fn run_on_both(source: &str) -> (Result<(), LoxError>, String) {
+    run_prepared_on_both(source, |steps| steps)
+}
+
+/// Checks that both backends print the same output and end the same way
+fn assert_same_on_both(source: &str) {
+    let _ = run_on_both(source);
+}
+
+/// Runs the program on both backends, checking that both succeed and print the
+/// expected lines
+fn assert_prints(source: &str, expected_lines: &[&str]) {
+    let expected_output: String = expected_lines
+        .iter()
+        .map(|line| format!("{line}\n"))
+        .collect();
+
+    assert_eq!(run_on_both(source), (Ok(()), expected_output));
+}
+
+/// Checks that the program is rejected before either backend runs it
+fn assert_compile_error(source: &str) {
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        assert_eq!(
+            run_with_backend(source, vec![], Limits::default(), backend),
+            RunStatus::CompileError,
+            "{source:?} on {backend}"
+        );
+    }
+}
+
+#[test]
+fn test_closure_keeps_binding_from_declaration() {
+    assert_prints(
+        "let a = \"global\";\n{\n    fun showA() {\n        print(a);\n    }\n\n    showA();\n    let a = \"block\";\n    showA();\n    print(a);\n}",
+        &["\"global\"", "\"global\"", "\"block\""],
+    );
+}
+
+#[test]
+fn test_block_shadows_enclosing_variable() {
+    assert_prints(
+        "let a = \"outer\";\n{\n    let a = \"inner\";\n    print(a);\n}\nprint(a);",
+        &["\"inner\"", "\"outer\""],
+    );
+}
+
+#[test]
+fn test_nested_blocks_shadow_each_other() {
+    assert_prints(
+        "let a = \"global a\";\nlet b = \"global b\";\nlet c = \"global c\";\n{\n    let a = \"outer a\";\n    let b = \"outer b\";\n    {\n        let a = \"inner a\";\n        print(a);\n        print(b);\n        print(c);\n    }\n    print(a);\n    print(b);\n    print(c);\n}\nprint(a);\nprint(b);\nprint(c);",
+        &[
+            "\"inner a\"",
+            "\"outer b\"",
+            "\"global c\"",
+            "\"outer a\"",
+            "\"outer b\"",
+            "\"global c\"",
+            "\"global a\"",
+            "\"global b\"",
+            "\"global c\"",
+        ],
+    );
+}
+
+#[test]
+fn test_assignment_only_changes_nearest_variable() {
+    assert_prints(
+        "let a = \"global\";\n{\n    let a = \"local\";\n    a = \"assigned\";\n    print(a);\n}\nprint(a);",
+        &["\"assigned\"", "\"global\""],
+    );
+}
+
+#[test]
+fn test_assignment_reaches_enclosing_variable() {
+    assert_prints(
+        "let a = \"before\";\n{\n    {\n        a = \"after\";\n    }\n}\nprint(a);",
+        &["\"after\""],
+    );
+}
+
+#[test]
+fn test_parameter_shadows_global() {
+    assert_prints(
+        "let a = \"global\";\nfun f(a) {\n    print(a);\n    a = \"assigned\";\n    print(a);\n}\nf(\"parameter\");\nprint(a);",
+        &["\"parameter\"", "\"assigned\"", "\"global\""],
+    );
+}
+
+#[test]
+fn test_closures_share_assigned_variable() {
+    assert_prints(
+        "let f = nil;\nlet g = nil;\n{\n    let local = \"local\";\n    fun first() {\n        print(local);\n        local = \"after f\";\n        print(local);\n    }\n    f = first;\n    fun second() {\n        print(local);\n        local = \"after g\";\n        print(local);\n    }\n    g = second;\n}\nf();\ng();",
+        &["\"local\"", "\"after f\"", "\"after f\"", "\"after g\""],
+    );
+}
+
+#[test]
+fn test_closure_captures_parameter() {
+    assert_prints(
+        "fun outer(captured) {\n    fun inner() {\n        return captured;\n    }\n    return inner;\n}\nlet f = outer(\"param\");\nprint(f());",
+        &["\"param\""],
+    );
+}
+
+#[test]
+fn test_nested_closures_capture_every_level() {
+    assert_prints(
+        "let f = nil;\nfun f1() {\n    let a = \"a\";\n    fun f2() {\n        let b = \"b\";\n        fun f3() {\n            let c = \"c\";\n            fun f4() {\n                print(a);\n                print(b);\n                print(c);\n            }\n            f = f4;\n        }\n        f3();\n    }\n    f2();\n}\nf1();\nf();",
+        &["\"a\"", "\"b\"", "\"c\""],
+    );
+}
+
+#[test]
+fn test_closure_is_referenced_multiple_times() {
+    assert_prints(
+        "let f = nil;\n{\n    let a = \"a\";\n    fun f_() {\n        print(a);\n    }\n    f = f_;\n}\nf();\nf();",
+        &["\"a\"", "\"a\""],
+    );
+}
+
+#[test]
+fn test_counter_closures_are_independent() {
+    assert_prints(
+        "fun makeCounter() {\n    let i = 0;\n    fun count() {\n        i = i + 1;\n        print(i);\n    }\n    return count;\n}\nlet first = makeCounter();\nlet second = makeCounter();\nfirst();\nfirst();\nsecond();",
+        &["1.00", "2.00", "1.00"],
+    );
+}
+
+#[test]
+fn test_recursive_local_function() {
+    assert_prints(
+        "{\n    fun fib(n) {\n        if n < 2 {\n            return n;\n        }\n        return fib(n - 1) + fib(n - 2);\n    }\n    print(fib(8));\n}",
+        &["21.00"],
+    );
+}
+
+#[test]
+fn test_shadowing_inside_loop_body() {
+    assert_prints(
+        "let i = 0;\nlet total = 0;\nwhile i < 3 {\n    let total = i;\n    print(total);\n    i = i + 1;\n}\nprint(total);",
+        &["0.00", "1.00", "2.00", "0.00"],
+    );
+}
+
+#[test]
+fn test_function_calls_global_declared_after_it() {
+    assert_prints(
+        "fun f() {\n    return g();\n}\nfun g() {\n    return 1;\n}\nprint(f());\nfun increment() {\n    count = count + 1;\n}\nlet count = 0;\nincrement();\nincrement();\nprint(count);",
+        &["1.00", "2.00"],
+    );
+}
+
+#[test]
+fn test_redeclaring_a_name_in_the_same_scope_is_rejected() {
+    assert_compile_error("fun f() {}\nfun f() {}\nf();");
+    assert_compile_error("fun print(a) {}\nprint(1);");
+    assert_compile_error(
+        "fun g() {\n    fun h() {\n        return 1;\n    }\n    fun h() {\n        return 2;\n    }\n    return h();\n}\nprint(g());",
+    );
+}
+
+#[test]
+fn test_arithmetic_matches_tree_walker() {
+    assert_same_on_both(
+        "print(1 + 2 * 3);\nprint((1 + 2) * 3);\nprint(10 / 4);\nprint(\"con\" + \"cat\");",
+    );
+}
+
+#[test]
+fn test_comparison_and_logic_matches_tree_walker() {
+    assert_same_on_both(
+        "print(1 < 2);\nprint(2 <= 1);\nprint(\"a\" == \"a\");\nprint(nil != nil);\nprint(!0);\nprint(nil or \"default\");\nprint(1 and 2);\nprint(0 and 2);",
+    );
+}
+
+#[test]
+fn test_control_flow_matches_tree_walker() {
+    assert_same_on_both(
+        "let i = 0;\nlet total = 0;\nwhile i < 5 {\n    if i == 2 {\n        total = total + 10;\n    } else {\n        total = total + i;\n    }\n    i = i + 1;\n}\nprint(total);",
+    );
+
+    assert_same_on_both(
+        "fun first() {\n    let i = 0;\n    while true {\n        if i == 3 {\n            return i;\n        }\n        i = i + 1;\n    }\n}\nprint(first());",
+    );
+}
+
+#[test]
+fn test_scopes_and_closures_match_tree_walker() {
+    assert_same_on_both(
+        "fun makeCounter() {\n    let i = 0;\n    fun count() {\n        i = i + 1;\n        print(i);\n    }\n    return count;\n}\nlet counter = makeCounter();\ncounter();\ncounter();\nlet other = makeCounter();\nother();",
+    );
+}
+
+#[test]
+fn test_values_display_like_tree_walker() {
+    assert_same_on_both(
+        "fun f() {}\nclass Point {\n    fun describe() {\n        print(\"a point\");\n    }\n}\nlet point = Point();\npoint.x = 1;\nprint(f);\nprint(print);\nprint(Point);\nprint(point);\nprint(point.x);\nprint(point.describe);\npoint.describe();\nprint(args);\nprint(args.length);\nprint(args.get(0));\nprint(args.get);",
+    );
+}
+
+#[test]
+fn test_runtime_errors_match_tree_walker() {
+    assert_same_on_both("print(\"before\");\nprint(1 + nil);\nprint(\"after\");");
+    assert_same_on_both("print(\"a\" < 1);");
+    assert_same_on_both("nil();");
+    assert_same_on_both("let x = 1;\nx.y;");
+    assert_same_on_both("class A {}\nA().missing;");
+    assert_same_on_both("args.get(5);");
+    assert_same_on_both("exit(3);\nprint(\"unreachable\");");
+    assert_same_on_both(
+        "fun inner() {\n    return 1 + nil;\n}\nfun outer() {\n    inner();\n}\nouter();",
+    );
+}
+
+#[test]
+fn test_methods_bind_this_and_run_initializer() {
+    assert_same_on_both(
+        "class Counter {\n    fun init(start) {\n        this.count = start;\n    }\n    fun increment() {\n        this.count = this.count + 1;\n        return this.count;\n    }\n}\nlet counter = Counter(5);\ncounter.increment();\nlet bump = counter.increment;\nprint(bump());",
+    );
+    assert_same_on_both("class A {\n    fun init(n) {\n        print(n);\n    }\n}\nA(3);");
+    assert_same_on_both(
+        "class A {\n    fun init() {\n        return;\n    }\n}\nprint(A().init());",
+    );
+    assert_same_on_both("class A {}\nA(1, 2);");
+    assert_same_on_both("class A {\n    fun init(n) {}\n}\nA();");
+    assert_same_on_both("class A {\n    fun init() {\n        this.x = 1 + nil;\n    }\n}\nA();");
+}
+
+#[test]
+fn test_globals_match_tree_walker() {
+    assert_same_on_both("fun f() {\n    return g();\n}\nfun g() {\n    return 1;\n}\nprint(f());");
+    assert_same_on_both("print(\"before\");\nprint(undefined);");
+}
+
+#[test]
+fn test_operands_are_evaluated_before_they_are_checked() {
+    for (source, printed) in [
+        ("let n = nil;\nn.x = print(\"x\");", "\"x\"\n"),
+        ("fun x() {}\nx(print(\"hi\"));", "\"hi\"\n"),
+        ("nil(print(\"argument\"));", "\"argument\"\n"),
+    ] {
+        let (result, output) = run_on_both(source);
+
+        assert!(result.is_err(), "{source:?}");
+        assert_eq!(output, printed, "{source:?}");
+    }
+}
+
+#[test]
+fn test_step_limit_stops_both_backends_at_the_same_step() {
+    let source = "fun show(n) {\n    print(n);\n}\nlet i = 0;\nwhile i < 3 {\n    show(i);\n    i = i + 1;\n}\nclass A {\n    fun m() {\n        return this;\n    }\n}\nA().m();\nprint(\"done\");";
+
+    for max_steps in 0..30 {
+        let limits = Limits {
+            max_steps: Some(max_steps),
+            ..Limits::default()
+        };
+
+        assert_eq!(
+            run_on(source, Backend::TreeWalker, limits.clone()),
+            run_on(source, Backend::Vm, limits),
+            "stopped after {max_steps} steps"
+        );
+    }
+
+    assert_eq!(run_on_both(source).1, "0.00\n1.00\n2.00\n\"done\"\n");
+}
+
+#[test]
+fn test_variables_are_found_where_the_resolver_put_them() {
+    assert_prints(
+        "{\n    let a = \"block\";\n    class Box {\n        fun init(value) {\n            this.value = value;\n        }\n        fun getter() {\n            fun get() {\n                return this.value + a;\n            }\n            return get;\n        }\n    }\n    print(Box(\"boxed \").getter()());\n}",
+        &["\"boxed block\""],
+    );
+    assert_prints(
+        "fun outer() {\n    let a = \"outer\";\n    fun middle() {\n        fun inner() {\n            return a + later;\n        }\n        return inner;\n    }\n    return middle();\n}\nlet later = \" and later\";\nprint(outer()());",
+        &["\"outer and later\""],
+    );
+}
@@ -0,0 +1,479 @@
+use crate::{
+    errors::{LoxError, Phase, Severity},
+    span::Span,
+};
+
+const TAB_WIDTH: usize = 4;
+
+/// Calls listed under a runtime error before the rest are summarized
+const MAX_BACKTRACE_NOTES: usize = 16;
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// A problem with the source, ready to be rendered next to the code that caused it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Stable code of the error, shown next to the severity
+    pub code: Option<&'static str>,
+    pub title: String,
+    pub primary: Label,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+}
+
+/// Message attached to a span of the source, an empty message only underlines it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(title: &str, span: Span, message: &str) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            title: title.to_owned(),
+            primary: Label {
+                span,
+                message: message.to_owned(),
+            },
+            secondary: vec![],
+            notes: vec![],
+            help: vec![],
+        }
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+
+        self
+    }
+
+    pub fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: &str) -> Self {
+        self.secondary.push(Label {
+            span,
+            message: message.to_owned(),
+        });
+
+        self
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_owned());
+
+        self
+    }
+
+    pub fn with_help(mut self, help: &str) -> Self {
+        self.help.push(help.to_owned());
+
+        self
+    }
+
+    /// Renders the diagnostic with the source lines it points at, underlining
+    /// the primary span with `^` and any secondary spans with `-`
+    ///
+    /// `colored` adds ANSI escape codes, it should only be set when writing to a terminal
+    pub fn render(&self, source: &str, colored: bool) -> String {
+        let paint = |code: &str, text: &str| {
+            if colored {
+                format!("{code}{text}{RESET}")
+            } else {
+                text.to_owned()
+            }
+        };
+
+        let severity_color = match self.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+
+        let severity = match self.code {
+            Some(code) => format!("{}[{code}]", self.severity),
+            None => self.severity.to_string(),
+        };
+
+        let mut rendered = format!(
+            "{}{}",
+            paint(severity_color, &severity),
+            paint(BOLD, &format!(": {}", self.title))
+        );
+
+        // Spans that were never given a location, such as `Span::default()`, have nothing to point at
+        if self.primary.span.line == 0 {
+            rendered += &format!("\n  {} {}", paint(BLUE, "="), self.primary.message);
+        } else {
+            let snippets: Vec<(&Label, char, &str, Vec<SnippetLine>)> =
+                std::iter::once((&self.primary, '^', severity_color))
+                    .chain(self.secondary.iter().map(|label| (label, '-', BLUE)))
+                    .filter(|(label, _, _)| label.span.line != 0)
+                    .map(|(label, underline, color)| {
+                        (label, underline, color, snippet_lines(source, label.span))
+                    })
+                    .collect();
+
+            let last_line = snippets
+                .iter()
+                .flat_map(|(_, _, _, lines)| lines.iter().map(|line| line.number))
+                .max()
+                .unwrap_or(0);
+            let gutter = " ".repeat(last_line.to_string().len());
+
+            rendered += &format!("\n{gutter}{} {}", paint(BLUE, "-->"), self.primary.span);
+
+            for (label, underline, color, lines) in snippets {
+                rendered += &format!("\n{gutter} {}", paint(BLUE, "|"));
+
+                let line_count = lines.len();
+
+                for (index, line) in lines.into_iter().enumerate() {
+                    let mut underline_text = underline.to_string().repeat(line.underline_length);
+
+                    if index + 1 == line_count && !label.message.is_empty() {
+                        underline_text = format!("{underline_text} {}", label.message);
+                    }
+
+                    rendered += &format!(
+                        "\n{} {}\n{gutter} {} {}{}",
+                        paint(
+                            BLUE,
+                            &format!("{:>width$} |", line.number, width = gutter.len())
+                        ),
+                        line.text,
+                        paint(BLUE, "|"),
+                        " ".repeat(line.underline_start),
+                        paint(color, &underline_text)
+                    );
+                }
+            }
+
+            if !self.notes.is_empty() || !self.help.is_empty() {
+                rendered += &format!("\n{gutter} {}", paint(BLUE, "|"));
+            }
+        }
+
+        let notes = self.notes.iter().map(|note| format!("note: {note}"));
+        let help = self.help.iter().map(|help| format!("help: {help}"));
+
+        for line in notes.chain(help) {
+            rendered += &format!("\n  {} {}", paint(BLUE, "="), paint(BOLD, &line));
+        }
+
+        rendered
+    }
+}
+
+/// One line of source touched by a span, with tabs expanded so the underline lines up
+struct SnippetLine {
+    number: usize,
+    text: String,
+    underline_start: usize,
+    underline_length: usize,
+}
+
+/// Every line of the source the span touches
+fn snippet_lines(source: &str, span: Span) -> Vec<SnippetLine> {
+    let start = floor_char_boundary(source, span.start);
+    let end = floor_char_boundary(source, span.end.max(start));
+
+    let mut line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+    let mut line_number = span.line;
+    let mut lines = vec![];
+
+    loop {
+        let line_end = source[line_start..]
+            .find('\n')
+            .map_or(source.len(), |index| line_start + index);
+        let line = &source[line_start..line_end];
+
+        let underline_from = start.max(line_start) - line_start;
+        let underline_to = end.min(line_end).max(line_start) - line_start;
+
+        let underline_start = display_width(&line[..underline_from]);
+        // Empty spans, like the end of the file, still get a single mark
+        let underline_length = display_width(&line[underline_from..underline_to]).max(1);
+
+        lines.push(SnippetLine {
+            number: line_number,
+            text: line.replace('\t', &" ".repeat(TAB_WIDTH)),
+            underline_start,
+            underline_length,
+        });
+
+        if end <= line_end + 1 || line_end == source.len() {
+            break lines;
+        }
+
+        line_start = line_end + 1;
+        line_number += 1;
+    }
+}
+
+/// The offset, clamped to the source, moved back onto the start of a character
+/// so spans from another source never slice through one
+fn floor_char_boundary(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+
+    offset
+}
+
+fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|character| if character == '\t' { TAB_WIDTH } else { 1 })
+        .sum()
+}
+
+/// Reports the diagnostic, in color when stderr is a terminal that hasn't opted out with `NO_COLOR`
+#[cfg(not(target_family = "wasm"))]
+pub fn report_diagnostic(diagnostic: &Diagnostic, source: &str) {
+    use std::io::IsTerminal;
+
+    let colored = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+
+    eprintln!("{}", diagnostic.render(source, colored));
+}
+
+/// Reports the diagnostic to the playground's log, which can't show colors
+#[cfg(target_family = "wasm")]
+pub fn report_diagnostic(diagnostic: &Diagnostic, source: &str) {
+    crate::logging::pushToLog(&diagnostic.render(source, false));
+}
+
+impl From<&LoxError> for Diagnostic {
+    fn from(error: &LoxError) -> Self {
+        let title = match (error.phase(), error.severity()) {
+            (_, Severity::Warning) => "possible mistake",
+            (Phase::Scanning, _) => "scanning failed",
+            (Phase::Parsing, _) => "parsing failed",
+            (Phase::Resolving, _) => "resolution failed",
+            (Phase::Runtime, _) => "runtime error",
+        };
+
+        let mut diagnostic = Diagnostic::new(title, error.span, &error.message())
+            .with_code(error.code())
+            .with_severity(error.severity());
+
+        let mut frames = error.backtrace.iter().peekable();
+        let mut shown_frames = 0;
+
+        while let Some(frame) = frames.next() {
+            if shown_frames == MAX_BACKTRACE_NOTES {
+                diagnostic = diagnostic.with_note(&format!("and {} more calls", frames.len() + 1));
+
+                break;
+            }
+
+            diagnostic = diagnostic.with_note(&format!(
+                "in {}, called at {}",
+                frame.function, frame.call_span
+            ));
+            shown_frames += 1;
+
+            // Recursion repeats the same call over and over, only show it once
+            let mut repeats = 0;
+
+            while frames.next_if_eq(&frame).is_some() {
+                repeats += 1;
+            }
+
+            if repeats > 0 {
+                diagnostic =
+                    diagnostic.with_note(&format!("called {repeats} more times from there"));
+            }
+        }
+
+        match error.kind.help() {
+            Some(help) => diagnostic.with_help(help),
+            None => diagnostic,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Diagnostic;
+    use crate::{
+        errors::{ErrorKind, LoxError, StackFrame},
+        span::Span,
+    };
+
+    #[test]
+    fn diagnostic_underlines_the_primary_span() {
+        let source = "let x = 1;\nprint(x + nil);";
+        let diagnostic = Diagnostic::new(
+            "runtime error",
+            Span::new(19, 20, 2, 9),
+            "Cannot add values 1.00 and nil",
+        );
+
+        assert_eq!(
+            diagnostic.render(source, false),
+            "error: runtime error
+ --> 2:9
+  |
+2 | print(x + nil);
+  |         ^ Cannot add values 1.00 and nil"
+        );
+    }
+
+    #[test]
+    fn diagnostic_renders_secondary_labels_and_help() {
+        let source = "fun f(a, a) {}";
+        let diagnostic = Diagnostic::new(
+            "resolution failed",
+            Span::new(9, 10, 1, 10),
+            "a is already a parameter",
+        )
+        .with_secondary(Span::new(6, 7, 1, 7), "first defined here")
+        .with_help("rename one of the parameters");
+
+        assert_eq!(
+            diagnostic.render(source, false),
+            "error: resolution failed
+ --> 1:10
+  |
+1 | fun f(a, a) {}
+  |          ^ a is already a parameter
+  |
+1 | fun f(a, a) {}
+  |       - first defined here
+  |
+  = help: rename one of the parameters"
+        );
+    }
+
+    #[test]
+    fn diagnostic_underlines_every_line_of_a_multi_line_span() {
+        let source = "let s = \"one\ntwo\";";
+        let diagnostic = Diagnostic::new("parsing failed", Span::new(8, 17, 1, 9), "string");
+
+        assert_eq!(
+            diagnostic.render(source, false),
+            "error: parsing failed
+ --> 1:9
+  |
+1 | let s = \"one
+  |         ^^^^
+2 | two\";
+  | ^^^^ string"
+        );
+    }
+
+    #[test]
+    fn diagnostic_from_error_shows_its_code_and_help() {
+        let error = LoxError::new(ErrorKind::UnterminatedString, Span::new(0, 2, 1, 1));
+
+        assert_eq!(
+            Diagnostic::from(&error).render("\"a", false),
+            "error[E0102]: scanning failed
+ --> 1:1
+  |
+1 | \"a
+  | ^^ Closing quotation at end of string not found
+  |
+  = help: strings can span lines but must end with \""
+        );
+    }
+
+    #[test]
+    fn diagnostic_from_error_lists_its_backtrace() {
+        let error = LoxError::new(
+            ErrorKind::UndefinedVariable("x".to_owned()),
+            Span::new(0, 1, 1, 1),
+        )
+        .with_backtrace(vec![StackFrame {
+            function: "f".to_owned(),
+            call_span: Span::new(4, 5, 2, 2),
+        }]);
+
+        assert_eq!(
+            Diagnostic::from(&error).render("x;\nf();", false),
+            "error[E0401]: runtime error
+ --> 1:1
+  |
+1 | x;
+  | ^ Variable x not found in scope
+  |
+  = note: in f, called at 2:2"
+        );
+    }
+
+    #[test]
+    fn diagnostic_collapses_recursive_calls() {
+        let recursive_call = StackFrame {
+            function: "f".to_owned(),
+            call_span: Span::new(17, 18, 1, 18),
+        };
+
+        let mut backtrace = vec![recursive_call; 5];
+        backtrace.push(StackFrame {
+            function: "f".to_owned(),
+            call_span: Span::new(23, 24, 1, 24),
+        });
+
+        let error = LoxError::new(
+            ErrorKind::StackOverflow { limit: 6 },
+            Span::new(17, 18, 1, 18),
+        )
+        .with_backtrace(backtrace);
+
+        assert_eq!(
+            Diagnostic::from(&error).notes,
+            vec![
+                "in f, called at 1:18",
+                "called 4 more times from there",
+                "in f, called at 1:24"
+            ]
+        );
+    }
+
+    #[test]
+    fn diagnostic_without_location_only_prints_the_message() {
+        let diagnostic = Diagnostic::new("runtime error", Span::default(), "Stack overflow");
+
+        assert_eq!(
+            diagnostic.render("", false),
+            "error: runtime error\n  = Stack overflow"
+        );
+    }
+
+    #[test]
+    fn span_inside_a_character_is_moved_onto_its_start() {
+        let source = "let s = \"éé\";";
+        let diagnostic = Diagnostic::new("runtime error", Span::new(10, 11, 1, 10), "");
+
+        assert_eq!(
+            diagnostic.render(source, false),
+            "error: runtime error
+ --> 1:10
+  |
+1 | let s = \"éé\";
+  |          ^"
+        );
+    }
+
+    #[test]
+    fn colored_diagnostic_uses_escape_codes() {
+        let diagnostic = Diagnostic::new("parsing failed", Span::new(0, 1, 1, 1), "");
+
+        assert!(diagnostic.render("x", true).contains("\x1b[1;31m"));
+        assert!(!diagnostic.render("x", false).contains('\x1b'));
+    }
+}
@@ -1,69 +1,460 @@
-use std::fmt;
+use std::{fmt, time::Duration};
 
-/// Error to report issues at the raw scanning stage
-#[derive(Debug, Clone, PartialEq, Default)]
-pub struct ScanningError {
-    pub line_number: usize,
-    pub message: String,
+use crate::{span::Span, tokens::TokenType};
+
+/// Every error found at any stage of the pipeline
+///
+/// The kind decides the error's code, phase and severity, see `errors.md` for
+/// the full list of codes
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoxError {
+    pub kind: ErrorKind,
+    pub span: Span,
+    /// Calls the error was raised inside of, innermost first, empty outside of any call
+    pub backtrace: Vec<StackFrame>,
 }
 
-impl fmt::Display for ScanningError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "There was an error found in line {},\n \n {}",
-            self.line_number, self.message
-        )
-    }
+/// A call that was in progress when an error was raised
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+    /// Name of the function being called
+    pub function: String,
+    pub call_span: Span,
+}
+
+/// Stage of the pipeline an error is found in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Scanning,
+    Parsing,
+    Resolving,
+    Runtime,
 }
 
-/// Error to report issues at the raw scanning stage
-#[derive(Debug, Clone, PartialEq, Default)]
-pub struct RuntimeError {
-    pub line_number: usize,
-    pub message: String,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
 }
 
-impl fmt::Display for RuntimeError {
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    // Scanning
+    /// E0101
+    UnexpectedCharacter(char),
+    /// E0102
+    UnterminatedString,
+    /// E0103
+    InvalidNumber(String),
+
+    // Parsing
+    /// E0201, `found` is `None` when the end of the file was reached
+    ExpectedToken {
+        expected: Vec<TokenType>,
+        found: Option<TokenType>,
+    },
+    /// E0202, `context` describes what the identifier names, e.g. "class name"
+    ExpectedIdentifier {
+        context: &'static str,
+        found: TokenType,
+    },
+    /// E0203
+    ExpectedExpression(TokenType),
+    /// E0204
+    InvalidAssignmentTarget,
+    /// E0205, only possible when parsing tokens that didn't come from the scanner
+    UnexpectedEndOfFile,
+    /// E0206
+    TooDeeplyNested { limit: usize },
+
+    // Resolving
+    /// E0301
+    ReadInOwnInitializer(String),
+    /// E0302
+    AlreadyDeclared(String),
+    /// E0303
+    ReturnOutsideFunction,
+    /// E0304
+    ReturnValueFromInitializer,
+    /// E0305
+    ThisOutsideClass,
+    /// E0306
+    DuplicateParameter(String),
+    /// E0307
+    DuplicateMethod(String),
+
+    // Runtime
+    /// E0400, not a failure, the program asked to stop through the native `exit`
+    Exit(i32),
+    /// E0401
+    UndefinedVariable(String),
+    /// E0402
+    AlreadyDefined(String),
+    /// E0403, the resolver never saw the variable at all, which is always a bug
+    /// in the interpreter. Variables the resolver saw but found no declaration
+    /// for are `UndefinedVariable`
+    UnresolvedVariable(String),
+    /// E0404, `operation` is the verb, e.g. "negate"
+    InvalidOperand {
+        operation: &'static str,
+        operand: String,
+    },
+    /// E0405, `operation` is the verb, e.g. "add"
+    InvalidOperands {
+        operation: &'static str,
+        left: String,
+        right: String,
+    },
+    /// E0406
+    NotCallable(String),
+    /// E0407
+    PropertyOnNonInstance(String),
+    /// E0408
+    UndefinedProperty(String),
+    /// E0409
+    ArityMismatch { expected: usize, found: usize },
+    /// E0410
+    InvalidArgument {
+        function: &'static str,
+        expected: &'static str,
+    },
+    /// E0411
+    IndexOutOfRange { length: usize },
+    /// E0412
+    OutputFailed,
+    /// E0413
+    StackOverflow { limit: usize },
+    /// E0414
+    StepLimitExceeded { limit: u64 },
+    /// E0415
+    TimedOut(Duration),
+    /// E0416, the run was cancelled from outside the program, e.g. by Ctrl-C
+    Interrupted,
+
+    // Warnings, found while resolving
+    /// W0301
+    UnusedVariable(String),
+    /// W0302
+    UnusedParameter(String),
+    /// W0303
+    UnreachableCode,
+    /// W0304
+    ShadowedVariable(String),
+    /// W0305, `value` is whether the condition is always truthy
+    ConstantCondition { value: bool },
+}
+
+/// Codes of every warning, any of which can be allowed so that it isn't reported
+pub const WARNING_CODES: [&str; 5] = ["W0301", "W0302", "W0303", "W0304", "W0305"];
+
+impl ErrorKind {
+    /// Stable code used to document and filter errors, never reused once assigned
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::UnexpectedCharacter(_) => "E0101",
+            ErrorKind::UnterminatedString => "E0102",
+            ErrorKind::InvalidNumber(_) => "E0103",
+
+            ErrorKind::ExpectedToken { .. } => "E0201",
+            ErrorKind::ExpectedIdentifier { .. } => "E0202",
+            ErrorKind::ExpectedExpression(_) => "E0203",
+            ErrorKind::InvalidAssignmentTarget => "E0204",
+            ErrorKind::UnexpectedEndOfFile => "E0205",
+            ErrorKind::TooDeeplyNested { .. } => "E0206",
+
+            ErrorKind::ReadInOwnInitializer(_) => "E0301",
+            ErrorKind::AlreadyDeclared(_) => "E0302",
+            ErrorKind::ReturnOutsideFunction => "E0303",
+            ErrorKind::ReturnValueFromInitializer => "E0304",
+            ErrorKind::ThisOutsideClass => "E0305",
+            ErrorKind::DuplicateParameter(_) => "E0306",
+            ErrorKind::DuplicateMethod(_) => "E0307",
+
+            ErrorKind::Exit(_) => "E0400",
+            ErrorKind::UndefinedVariable(_) => "E0401",
+            ErrorKind::AlreadyDefined(_) => "E0402",
+            ErrorKind::UnresolvedVariable(_) => "E0403",
+            ErrorKind::InvalidOperand { .. } => "E0404",
+            ErrorKind::InvalidOperands { .. } => "E0405",
+            ErrorKind::NotCallable(_) => "E0406",
+            ErrorKind::PropertyOnNonInstance(_) => "E0407",
+            ErrorKind::UndefinedProperty(_) => "E0408",
+            ErrorKind::ArityMismatch { .. } => "E0409",
+            ErrorKind::InvalidArgument { .. } => "E0410",
+            ErrorKind::IndexOutOfRange { .. } => "E0411",
+            ErrorKind::OutputFailed => "E0412",
+            ErrorKind::StackOverflow { .. } => "E0413",
+            ErrorKind::StepLimitExceeded { .. } => "E0414",
+            ErrorKind::TimedOut(_) => "E0415",
+            ErrorKind::Interrupted => "E0416",
+
+            ErrorKind::UnusedVariable(_) => "W0301",
+            ErrorKind::UnusedParameter(_) => "W0302",
+            ErrorKind::UnreachableCode => "W0303",
+            ErrorKind::ShadowedVariable(_) => "W0304",
+            ErrorKind::ConstantCondition { .. } => "W0305",
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        // The first digit after the leading letter and zero says which phase the code belongs to
+        match self.code().as_bytes()[2] {
+            b'1' => Phase::Scanning,
+            b'2' => Phase::Parsing,
+            b'3' => Phase::Resolving,
+            _ => Phase::Runtime,
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        if self.code().starts_with('W') {
+            Severity::Warning
+        } else {
+            Severity::Error
+        }
+    }
+
+    /// Extra advice shown underneath the error
+    pub fn help(&self) -> Option<&'static str> {
+        match self {
+            ErrorKind::UnterminatedString => Some("strings can span lines but must end with \""),
+            ErrorKind::ReadInOwnInitializer(_) => Some("use a different name for the new variable"),
+            ErrorKind::ReturnValueFromInitializer => {
+                Some("init always returns the instance, use an empty return to stop early")
+            }
+            ErrorKind::DuplicateParameter(_) => Some("rename one of the parameters"),
+            ErrorKind::UnresolvedVariable(_) => {
+                Some("this is a bug in the resolver, every variable should have been resolved")
+            }
+            ErrorKind::InvalidArgument {
+                function: "print", ..
+            } => Some("perhaps you meant print(\"\")?"),
+            ErrorKind::StackOverflow { .. } => {
+                Some("check that every recursive function has a case that stops recursing")
+            }
+            ErrorKind::StepLimitExceeded { .. } | ErrorKind::TimedOut(_) => {
+                Some("check for loops that never stop")
+            }
+            ErrorKind::UnusedVariable(_) | ErrorKind::UnusedParameter(_) => {
+                Some("start the name with an underscore if it's meant to be unused")
+            }
+            ErrorKind::UnreachableCode => Some("remove the code or the return before it"),
+            ErrorKind::ShadowedVariable(_) => Some("rename one of the variables"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "Runtime error at {},\n \n {}",
-            self.line_number, self.message
-        )
+        match self {
+            ErrorKind::UnexpectedCharacter(character) => {
+                write!(f, "Unrecognized character {character:?}")
+            }
+            ErrorKind::UnterminatedString => {
+                write!(f, "Closing quotation at end of string not found")
+            }
+            ErrorKind::InvalidNumber(lexeme) => write!(f, "Failed to parse number {lexeme}"),
+
+            ErrorKind::ExpectedToken { expected, found } => {
+                let expected = expected
+                    .iter()
+                    .map(|token_type| format!("{token_type:?}"))
+                    .collect::<Vec<_>>()
+                    .join(" or ");
+
+                match found {
+                    Some(found) => write!(f, "Expected {expected}, found {found:?}"),
+                    None => write!(f, "Expected {expected}, reached the end of the file"),
+                }
+            }
+            ErrorKind::ExpectedIdentifier { context, found } => {
+                write!(f, "Expected {context}, found {found:?}")
+            }
+            ErrorKind::ExpectedExpression(found) => {
+                write!(f, "Expected an expression, found {found:?}")
+            }
+            ErrorKind::InvalidAssignmentTarget => {
+                write!(
+                    f,
+                    "Expected left side of assignment to be an identifier or property"
+                )
+            }
+            ErrorKind::UnexpectedEndOfFile => write!(f, "Unexpected end of file"),
+            ErrorKind::TooDeeplyNested { limit } => {
+                write!(
+                    f,
+                    "Expressions and blocks can't be nested more than {limit} deep"
+                )
+            }
+
+            ErrorKind::ReadInOwnInitializer(name) => {
+                write!(f, "Can't read local variable {name} in its own initializer")
+            }
+            ErrorKind::AlreadyDeclared(name) => {
+                write!(f, "{name} is already declared in this scope")
+            }
+            ErrorKind::ReturnOutsideFunction => {
+                write!(f, "Can't return from outside of a function")
+            }
+            ErrorKind::ReturnValueFromInitializer => {
+                write!(f, "Can't return a value from an initializer")
+            }
+            ErrorKind::ThisOutsideClass => write!(f, "Can't use this outside of a class"),
+            ErrorKind::DuplicateParameter(name) => {
+                write!(
+                    f,
+                    "Parameter {name} is already a parameter of this function"
+                )
+            }
+            ErrorKind::DuplicateMethod(name) => {
+                write!(f, "Method {name} is already defined in this class")
+            }
+
+            ErrorKind::Exit(code) => write!(f, "Exited with code {code}"),
+            ErrorKind::UndefinedVariable(name) => write!(f, "Variable {name} not found in scope"),
+            ErrorKind::AlreadyDefined(name) => write!(f, "Variable {name} already defined"),
+            ErrorKind::UnresolvedVariable(name) => write!(f, "Variable {name} was never resolved"),
+            ErrorKind::InvalidOperand { operation, operand } => {
+                write!(f, "Cannot {operation} {operand}")
+            }
+            ErrorKind::InvalidOperands {
+                operation,
+                left,
+                right,
+            } => write!(f, "Cannot {operation} values {left} and {right}"),
+            ErrorKind::NotCallable(value) => write!(
+                f,
+                "Can't call {value}, only functions, methods and classes can be called"
+            ),
+            ErrorKind::PropertyOnNonInstance(value) => {
+                write!(
+                    f,
+                    "Can only access properties on an instance, found {value}"
+                )
+            }
+            ErrorKind::UndefinedProperty(name) => write!(f, "Unable to find property {name}"),
+            ErrorKind::ArityMismatch { expected, found } => {
+                write!(f, "Expected {expected} arguments, received {found}")
+            }
+            ErrorKind::InvalidArgument { function, expected } => {
+                write!(f, "{function} expects {expected}")
+            }
+            ErrorKind::IndexOutOfRange { length } => {
+                write!(f, "List index must be a whole number below {length}")
+            }
+            ErrorKind::OutputFailed => write!(f, "Failed to write to stdout"),
+            ErrorKind::StackOverflow { limit } => {
+                write!(f, "Stack overflow: maximum call depth {limit} exceeded")
+            }
+            ErrorKind::StepLimitExceeded { limit } => {
+                write!(f, "Execution stopped after {limit} steps")
+            }
+            ErrorKind::TimedOut(timeout) => {
+                write!(f, "Execution stopped after {}ms", timeout.as_millis())
+            }
+            ErrorKind::Interrupted => write!(f, "Interrupted"),
+
+            ErrorKind::UnusedVariable(name) => write!(f, "Variable {name} is never read"),
+            ErrorKind::UnusedParameter(name) => write!(f, "Parameter {name} is never read"),
+            ErrorKind::UnreachableCode => write!(f, "Code after a return never runs"),
+            ErrorKind::ShadowedVariable(name) => {
+                write!(
+                    f,
+                    "Variable {name} shadows a variable of an enclosing scope"
+                )
+            }
+            ErrorKind::ConstantCondition { value } => {
+                write!(
+                    f,
+                    "Condition is always {}",
+                    if *value { "true" } else { "false" }
+                )
+            }
+        }
     }
 }
 
-/// Error to report issues at the parsing stage
-#[derive(Debug, Clone, PartialEq, Default)]
-pub struct ParsingError {
-    pub line_number: usize,
-    pub message: String,
+impl LoxError {
+    pub fn new(kind: ErrorKind, span: Span) -> Self {
+        LoxError {
+            kind,
+            span,
+            backtrace: vec![],
+        }
+    }
+
+    /// Attaches the backtrace, unless a deeper call already attached one
+    pub fn with_backtrace(mut self, backtrace: Vec<StackFrame>) -> Self {
+        if self.backtrace.is_empty() {
+            self.backtrace = backtrace;
+        }
+
+        self
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.kind.phase()
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.kind.severity()
+    }
+
+    pub fn message(&self) -> String {
+        self.kind.to_string()
+    }
 }
 
-impl fmt::Display for ParsingError {
+impl fmt::Display for LoxError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Parsing failed at line {}, \n {}",
-            self.line_number, self.message
+            "{} error {} at {}, \n {}",
+            self.phase(),
+            self.code(),
+            self.span,
+            self.kind
         )
     }
 }
 
-/// Error to report issues at the parsing stage
-#[derive(Debug, Clone, PartialEq, Default)]
-pub struct ResolvingError {
-    pub line_number: usize,
-    pub message: String,
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Phase::Scanning => write!(f, "scanning"),
+            Phase::Parsing => write!(f, "parsing"),
+            Phase::Resolving => write!(f, "resolution"),
+            Phase::Runtime => write!(f, "runtime"),
+        }
+    }
 }
 
-impl fmt::Display for ResolvingError {
+impl fmt::Display for Severity {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "Resolution failed at line {}, \n {}",
-            self.line_number, self.message
-        )
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ErrorKind, Phase};
+
+    #[test]
+    fn error_phase_follows_its_code() {
+        assert_eq!(ErrorKind::UnterminatedString.phase(), Phase::Scanning);
+        assert_eq!(ErrorKind::InvalidAssignmentTarget.phase(), Phase::Parsing);
+        assert_eq!(
+            ErrorKind::AlreadyDeclared("a".to_owned()).phase(),
+            Phase::Resolving
+        );
+        assert_eq!(ErrorKind::Exit(0).phase(), Phase::Runtime);
     }
 }
@@ -0,0 +1,89 @@
+use std::{
+    cell::{Cell, RefCell},
+    time::Duration,
+};
+
+use crate::{
+    errors::{ErrorKind, LoxError},
+    span::Span,
+};
+
+use super::limits::{CancellationToken, Limits};
+
+/// Steps taken between looking at the clock, reading it every step would slow down every program
+const CLOCK_CHECK_INTERVAL: u64 = 1024;
+
+thread_local! {
+    static STEPS_TAKEN: Cell<u64> = const { Cell::new(0) };
+    static MAX_STEPS: Cell<Option<u64>> = const { Cell::new(None) };
+    /// The timeout along with the time it runs out, in milliseconds on the clock below
+    static DEADLINE: Cell<Option<(Duration, f64)>> = const { Cell::new(None) };
+    static CANCELLATION: RefCell<Option<CancellationToken>> = const { RefCell::new(None) };
+}
+
+/// Starts counting steps and time for a new run of the program on this thread
+pub fn start(limits: &Limits) {
+    STEPS_TAKEN.set(0);
+    MAX_STEPS.set(limits.max_steps);
+    DEADLINE.set(
+        limits
+            .timeout
+            .map(|timeout| (timeout, now_millis() + timeout.as_millis_f64())),
+    );
+    CANCELLATION.set(limits.cancellation.clone());
+}
+
+/// Counts a step of the program, such as a statement, a call or an iteration of a
+/// loop, erroring once the step limit or the timeout is reached or the run is cancelled
+pub fn step(span: Span) -> Result<(), LoxError> {
+    let cancelled = CANCELLATION.with_borrow(|cancellation| {
+        cancellation
+            .as_ref()
+            .is_some_and(|cancellation| cancellation.is_cancelled())
+    });
+
+    if cancelled {
+        return Err(LoxError::new(ErrorKind::Interrupted, span));
+    }
+
+    let steps_taken = STEPS_TAKEN.get() + 1;
+
+    STEPS_TAKEN.set(steps_taken);
+
+    if let Some(limit) = MAX_STEPS.get() {
+        if steps_taken > limit {
+            return Err(LoxError::new(ErrorKind::StepLimitExceeded { limit }, span));
+        }
+    }
+
+    if steps_taken.is_multiple_of(CLOCK_CHECK_INTERVAL) {
+        if let Some((timeout, deadline)) = DEADLINE.get() {
+            if now_millis() >= deadline {
+                return Err(LoxError::new(ErrorKind::TimedOut(timeout), span));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Milliseconds since an arbitrary point that stays the same for the whole process
+#[cfg(not(target_family = "wasm"))]
+fn now_millis() -> f64 {
+    use std::{sync::OnceLock, time::Instant};
+
+    static CLOCK_START: OnceLock<Instant> = OnceLock::new();
+
+    CLOCK_START
+        .get_or_init(Instant::now)
+        .elapsed()
+        .as_millis_f64()
+}
+
+// `Instant` isn't available in the browser, so the page's clock is used instead
+#[cfg(target_family = "wasm")]
+#[wasm_bindgen::prelude::wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = performance, js_name = now)]
+    fn now_millis() -> f64;
+}
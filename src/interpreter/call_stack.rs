@@ -0,0 +1,59 @@
+use std::cell::{Cell, RefCell};
+
+use crate::{
+    errors::{ErrorKind, LoxError, StackFrame},
+    span::Span,
+};
+
+use super::{functions::FunctionName, limits::DEFAULT_MAX_CALL_DEPTH};
+
+thread_local! {
+    /// Name and call site of each call in progress, outermost first
+    static CALL_STACK: RefCell<Vec<(FunctionName, Span)>> = const { RefCell::new(vec![]) };
+    static MAX_CALL_DEPTH: Cell<usize> = const { Cell::new(DEFAULT_MAX_CALL_DEPTH) };
+}
+
+/// Sets how many calls can be in progress at once on this thread
+pub fn set_max_call_depth(max_call_depth: usize) {
+    MAX_CALL_DEPTH.set(max_call_depth);
+}
+
+/// Keeps a call on the call stack for as long as it's alive
+pub struct CallGuard;
+
+impl CallGuard {
+    /// Fails instead of entering the call when the stack is already as deep as allowed
+    pub fn enter(function: FunctionName, call_span: Span) -> Result<CallGuard, LoxError> {
+        CALL_STACK.with_borrow_mut(|call_stack| {
+            let limit = MAX_CALL_DEPTH.get();
+
+            if call_stack.len() >= limit {
+                return Err(LoxError::new(ErrorKind::StackOverflow { limit }, call_span));
+            }
+
+            call_stack.push((function, call_span));
+
+            Ok(CallGuard)
+        })
+    }
+}
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        CALL_STACK.with_borrow_mut(|call_stack| call_stack.pop());
+    }
+}
+
+/// Calls currently in progress, innermost first
+pub fn backtrace() -> Vec<StackFrame> {
+    CALL_STACK.with_borrow(|call_stack| {
+        call_stack
+            .iter()
+            .rev()
+            .map(|(function, call_span)| StackFrame {
+                function: function.to_string(),
+                call_span: *call_span,
+            })
+            .collect()
+    })
+}
@@ -1,15 +1,36 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, rc::Rc};
 
 use crate::{
-    errors::RuntimeError,
-    resolver::VariableMap,
+    errors::{ErrorKind, LoxError},
+    resolver::{ResolvedVariable, VariableMap},
+    span::Span,
+    symbol::Symbol,
     tree::expression::{ExpressionLiteral, ExpressionVariable},
 };
 
-#[derive(Debug, Default, Clone)]
+thread_local! {
+    /// Where the resolver put the variables of the steps being run, held once for
+    /// the whole run instead of being found through the global environment
+    static VARIABLE_MAP: RefCell<VariableMap> = RefCell::default();
+}
+
+/// Runs `f` with the variables it reaches looked up in `variable_map`, which is
+/// handed back afterwards so it can be extended for steps run later
+pub fn with_variable_map<T>(variable_map: &mut VariableMap, f: impl FnOnce() -> T) -> T {
+    VARIABLE_MAP.set(std::mem::take(variable_map));
+
+    let result = f();
+
+    *variable_map = VARIABLE_MAP.take();
+
+    result
+}
+
+/// Variables of a single scope, stored in the slots the resolver assigned them,
+/// which is the order they get defined in
+#[derive(Debug, Default)]
 pub struct Environment {
-    resolved_variable_map: Option<Rc<VariableMap>>,
-    active_variable_map: Rc<RefCell<HashMap<String, ExpressionLiteral>>>,
+    variables: RefCell<Vec<(Symbol, ExpressionLiteral)>>,
     pub parent_environment: Option<Rc<RefCell<Environment>>>,
 }
 
@@ -17,196 +38,166 @@ pub type EnvironmentRef = Rc<RefCell<Environment>>;
 
 impl Environment {
     pub fn new() -> Environment {
-        Environment {
-            resolved_variable_map: None,
-            active_variable_map: Rc::new(RefCell::new(HashMap::new())),
-            parent_environment: None,
-        }
-    }
-
-    pub fn with_resolved_variable_map(variable_map: VariableMap) -> Self {
-        Environment {
-            resolved_variable_map: Some(Rc::new(variable_map)),
-            active_variable_map: Rc::new(RefCell::new(HashMap::new())),
-            parent_environment: None,
-        }
+        Environment::default()
     }
 
     pub fn with_parent(parent: Rc<RefCell<Environment>>) -> Environment {
         Environment {
-            active_variable_map: Rc::new(RefCell::new(HashMap::new())),
             parent_environment: Some(parent),
-            resolved_variable_map: None,
+            ..Environment::default()
         }
     }
 
-    pub fn get_variable(
-        &self,
-        line_number: usize,
-        name: String,
-    ) -> Result<ExpressionLiteral, RuntimeError> {
-        let read_variable = self.active_variable_map.borrow().get(&name).cloned();
+    /// Looks the variable up by name rather than through the slot it was resolved
+    /// to, only for tests which build environments by hand
+    #[cfg(test)]
+    pub fn get_variable(&self, span: Span, name: Symbol) -> Result<ExpressionLiteral, LoxError> {
+        let variables = self.variables.borrow();
 
-        if let Some(literal) = read_variable {
-            return Ok(literal.clone());
+        if let Some((_, value)) = variables.iter().find(|(variable, _)| *variable == name) {
+            return Ok(value.clone());
         }
 
         if let Some(parent_environment) = &self.parent_environment {
-            return parent_environment.borrow().get_variable(line_number, name);
+            return parent_environment.borrow().get_variable(span, name);
         }
 
-        Err(RuntimeError {
-            line_number,
-            message: format!("Variable {name} not found in scope"),
-        })
+        Err(LoxError::new(
+            ErrorKind::UndefinedVariable(name.to_string()),
+            span,
+        ))
     }
 
     pub fn get_variable_at(
         &self,
-        line_number: usize,
-        name: String,
-        depth: usize,
-    ) -> Result<ExpressionLiteral, RuntimeError> {
-        match depth {
+        span: Span,
+        name: Symbol,
+        resolved: ResolvedVariable,
+    ) -> Result<ExpressionLiteral, LoxError> {
+        match resolved.depth {
             1.. => {
                 if let Some(parent_environment) = &self.parent_environment {
                     return parent_environment.borrow().get_variable_at(
-                        line_number,
+                        span,
                         name,
-                        depth - 1,
+                        ResolvedVariable {
+                            depth: resolved.depth - 1,
+                            ..resolved
+                        },
                     );
                 } else {
                     unreachable!("parent environment referenced but does not exist")
                 }
             }
-            0 => {
-                let read_variable = self.active_variable_map.borrow().get(&name).cloned();
-
-                if let Some(literal) = read_variable {
-                    return Ok(literal.clone());
-                }
-
-                Err(RuntimeError {
-                    line_number,
-                    message: format!("Variable {name} not found in scope"),
-                })
-            }
+            0 => match self.variables.borrow().get(resolved.slot) {
+                Some((_, literal)) => Ok(literal.clone()),
+                None => Err(LoxError::new(
+                    ErrorKind::UndefinedVariable(name.to_string()),
+                    span,
+                )),
+            },
         }
     }
 
-    pub fn define_variable(
-        &self,
-        line_number: usize,
-        name: String,
-        value: ExpressionLiteral,
-    ) -> Result<(), RuntimeError> {
-        if self.active_variable_map.borrow().contains_key(&name) {
-            return Err(RuntimeError {
-                line_number,
-                message: format!("Variable {name} already defined"),
-            });
-        }
+    /// Stores the variable in the next slot
+    ///
+    /// The resolver rejects declaring a name twice in the same scope, so the name
+    /// is only checked in debug builds
+    pub fn define_variable(&self, name: Symbol, value: ExpressionLiteral) {
+        let mut variables = self.variables.borrow_mut();
 
-        self.active_variable_map.borrow_mut().insert(name, value);
+        debug_assert!(
+            variables.iter().all(|(variable, _)| *variable != name),
+            "{name} was defined twice in the same environment"
+        );
 
-        Ok(())
-    }
-
-    pub fn set_variable(
-        &self,
-        line_number: usize,
-        name: String,
-        value: ExpressionLiteral,
-    ) -> Result<ExpressionLiteral, RuntimeError> {
-        if !self.active_variable_map.borrow().contains_key(&name) {
-            if let Some(parent_environment) = &self.parent_environment {
-                return parent_environment
-                    .borrow_mut()
-                    .set_variable(line_number, name, value);
-            }
-
-            return Err(RuntimeError {
-                line_number,
-                message: format!("Variable {name} not defined"),
-            });
-        }
-
-        self.active_variable_map
-            .borrow_mut()
-            .insert(name, value.clone())
-            .unwrap();
-
-        Ok(value)
+        variables.push((name, value));
     }
 
     pub fn set_variable_at(
         &self,
-        name: String,
+        span: Span,
+        name: Symbol,
         value: ExpressionLiteral,
-        depth: usize,
-    ) -> Result<ExpressionLiteral, RuntimeError> {
-        match depth {
+        resolved: ResolvedVariable,
+    ) -> Result<ExpressionLiteral, LoxError> {
+        match resolved.depth {
             1.. => {
                 if let Some(parent_environment) = &self.parent_environment {
-                    return parent_environment
-                        .borrow_mut()
-                        .set_variable_at(name, value, depth - 1);
+                    return parent_environment.borrow_mut().set_variable_at(
+                        span,
+                        name,
+                        value,
+                        ResolvedVariable {
+                            depth: resolved.depth - 1,
+                            ..resolved
+                        },
+                    );
                 } else {
                     unreachable!("parent environment referenced but does not exist")
                 }
             }
-            0 => {
-                self.active_variable_map
-                    .borrow_mut()
-                    .insert(name, value.clone());
+            0 => match self.variables.borrow_mut().get_mut(resolved.slot) {
+                Some((_, literal)) => {
+                    *literal = value.clone();
 
-                Ok(value)
-            }
+                    Ok(value)
+                }
+                None => Err(LoxError::new(
+                    ErrorKind::UndefinedVariable(name.to_string()),
+                    span,
+                )),
+            },
         }
     }
 
-    fn get_variable_map(&self) -> Rc<VariableMap> {
-        if let Some(parent) = &self.parent_environment {
-            parent.borrow().get_variable_map()
-        } else {
-            self.resolved_variable_map
-                .clone()
-                .expect("Global environment doesn't contain variable map from resolver")
-        }
+    /// How many variables have been defined in this environment
+    pub fn len(&self) -> usize {
+        self.variables.borrow().len()
     }
 
     pub fn get_variable_with_depth(
         &self,
-        variable: ExpressionVariable,
-    ) -> Result<ExpressionLiteral, RuntimeError> {
-        let potential_depth = self.get_variable_map().get(&variable).cloned();
-
-        match potential_depth {
-            Some(depth) => {
-                self.get_variable_at(variable.line_number, variable.identifier_name, depth)
+        variable: &ExpressionVariable,
+    ) -> Result<ExpressionLiteral, LoxError> {
+        let potential_resolution =
+            VARIABLE_MAP.with_borrow(|variable_map| variable_map.get(&variable.id).copied());
+
+        match potential_resolution {
+            Some(Some(resolved)) => {
+                self.get_variable_at(variable.span, variable.identifier_name, resolved)
             }
-            None => Err(RuntimeError {
-                line_number: variable.line_number,
-                // TODO: err
-                message: "Something fucked up".to_string(),
-            }),
+            Some(None) => Err(LoxError::new(
+                ErrorKind::UndefinedVariable(variable.identifier_name.to_string()),
+                variable.span,
+            )),
+            None => Err(LoxError::new(
+                ErrorKind::UnresolvedVariable(variable.identifier_name.to_string()),
+                variable.span,
+            )),
         }
     }
 
     pub fn set_variable_with_depth(
-        &mut self,
-        variable: ExpressionVariable,
+        &self,
+        variable: &ExpressionVariable,
         value: ExpressionLiteral,
-    ) -> Result<ExpressionLiteral, RuntimeError> {
-        let potential_depth = self.get_variable_map().get(&variable).cloned();
-
-        match potential_depth {
-            Some(depth) => self.set_variable_at(variable.identifier_name, value, depth),
-            None => Err(RuntimeError {
-                line_number: variable.line_number,
-                // TODO: err
-                message: "Something fucked up".to_string(),
-            }),
+    ) -> Result<ExpressionLiteral, LoxError> {
+        let potential_resolution =
+            VARIABLE_MAP.with_borrow(|variable_map| variable_map.get(&variable.id).copied());
+
+        match potential_resolution {
+            Some(Some(resolved)) => {
+                self.set_variable_at(variable.span, variable.identifier_name, value, resolved)
+            }
+            Some(None) => Err(LoxError::new(
+                ErrorKind::UndefinedVariable(variable.identifier_name.to_string()),
+                variable.span,
+            )),
+            None => Err(LoxError::new(
+                ErrorKind::UnresolvedVariable(variable.identifier_name.to_string()),
+                variable.span,
+            )),
         }
     }
 }
@@ -215,7 +206,9 @@ impl Environment {
 mod tests {
     use std::{cell::RefCell, rc::Rc};
 
-    use crate::tree::expression::ExpressionLiteral;
+    use crate::{
+        resolver::ResolvedVariable, span::Span, symbol::Symbol, tree::expression::ExpressionLiteral,
+    };
 
     use super::Environment;
 
@@ -226,26 +219,24 @@ mod tests {
         let child = Environment::with_parent(parent.clone());
 
         child
-            .active_variable_map
+            .variables
             .borrow_mut()
-            .insert("child_test".to_owned(), ExpressionLiteral::True);
+            .push((Symbol::intern("child_test"), ExpressionLiteral::True));
 
         child
             .parent_environment
             .unwrap()
             .borrow()
-            .active_variable_map
+            .variables
             .borrow_mut()
-            .insert("test".to_owned(), ExpressionLiteral::True);
+            .push((Symbol::intern("test"), ExpressionLiteral::True));
 
-        let value = parent
-            .borrow()
-            .active_variable_map
-            .borrow()
-            .get("test")
-            .cloned();
+        let value = parent.borrow().variables.borrow().first().cloned();
 
-        assert_eq!(value, Some(ExpressionLiteral::True))
+        assert_eq!(
+            value,
+            Some((Symbol::intern("test"), ExpressionLiteral::True))
+        )
     }
 
     #[test]
@@ -254,15 +245,46 @@ mod tests {
 
         let child = Environment::with_parent(parent.clone());
 
-        let definition =
+        parent
+            .borrow()
+            .define_variable(Symbol::intern("name"), ExpressionLiteral::True);
+
+        let value = child.get_variable(Span::default(), Symbol::intern("name"));
+
+        assert_eq!(value, Ok(ExpressionLiteral::True))
+    }
+
+    #[test]
+    fn test_environment_reads_and_writes_resolved_slots() {
+        let parent = Rc::new(RefCell::new(Environment::new()));
+
+        for name in ["first", "second"] {
             parent
                 .borrow()
-                .define_variable(0, "name".to_owned(), ExpressionLiteral::True);
+                .define_variable(Symbol::intern(name), ExpressionLiteral::Nil);
+        }
 
-        assert!(definition.is_ok());
+        let child = Environment::with_parent(parent.clone());
+        let second = ResolvedVariable { depth: 1, slot: 1 };
 
-        let value = child.get_variable(0, "name".to_owned());
+        child
+            .set_variable_at(
+                Span::default(),
+                Symbol::intern("second"),
+                ExpressionLiteral::True,
+                second,
+            )
+            .unwrap();
 
-        assert_eq!(value, Ok(ExpressionLiteral::True))
+        assert_eq!(
+            child.get_variable_at(Span::default(), Symbol::intern("second"), second),
+            Ok(ExpressionLiteral::True)
+        );
+        assert_eq!(
+            parent
+                .borrow()
+                .get_variable(Span::default(), Symbol::intern("first")),
+            Ok(ExpressionLiteral::Nil)
+        );
     }
 }
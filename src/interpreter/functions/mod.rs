@@ -1,26 +1,57 @@
 pub mod native;
-use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
-
-use crate::{errors::RuntimeError, tree::expression::ExpressionLiteral};
-
-use super::types::BlockReturn;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Debug, Display},
+    rc::Rc,
+};
+
+use crate::{
+    errors::{ErrorKind, LoxError},
+    parser::statements::FunStatement,
+    span::Span,
+    symbol::Symbol,
+    tree::expression::ExpressionLiteral,
+};
+
+use super::{bind_method, environment::EnvironmentRef, types::BlockReturn};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Reference {
     CallableReference(CallableReference),
     ClassReference(ClassReference),
     InstanceReference(InstanceReference),
+    ListReference(ListReference),
+}
+
+/// Name a function is called by, only turned into text when it's shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionName {
+    Function(Symbol),
+    Method { class: Symbol, method: Symbol },
+}
+
+impl Display for FunctionName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FunctionName::Function(name) => write!(f, "{name}"),
+            FunctionName::Method { class, method } => write!(f, "{class}.{method}"),
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct CallableReference {
+    pub name: FunctionName,
+    /// Where the function was defined, native functions use the default span
+    pub span: Span,
     pub arity: usize,
-    pub subroutine: Rc<dyn Fn(usize, Vec<ExpressionLiteral>) -> Result<BlockReturn, RuntimeError>>,
+    pub subroutine: Rc<dyn Fn(Span, Vec<ExpressionLiteral>) -> Result<BlockReturn, LoxError>>,
 }
 
 impl Debug for CallableReference {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "( func {} )", self.arity)
+        write!(f, "( func {} {} )", self.name, self.arity)
     }
 }
 
@@ -37,8 +68,11 @@ impl PartialEq for CallableReference {
 
 #[derive(Clone)]
 pub struct ClassReference {
-    pub name: String,
-    pub methods: Rc<RefCell<HashMap<String, CallableReference>>>,
+    pub name: Symbol,
+    /// Declarations of the methods, which are bound to an instance once they're accessed
+    pub methods: Rc<HashMap<Symbol, Rc<FunStatement>>>,
+    /// Where the class was declared, the environment its methods close over
+    pub environment: EnvironmentRef,
 }
 
 impl Debug for ClassReference {
@@ -59,7 +93,7 @@ impl PartialEq for ClassReference {
 #[derive(Clone)]
 pub struct InstanceReference {
     pub class: ClassReference,
-    pub fields: Rc<RefCell<HashMap<String, ExpressionLiteral>>>,
+    pub fields: Rc<RefCell<HashMap<Symbol, ExpressionLiteral>>>,
 }
 
 impl InstanceReference {
@@ -72,34 +106,32 @@ impl InstanceReference {
 
     pub fn get_property(
         &self,
-        line_number: usize,
-        property_name: &str,
-    ) -> Result<ExpressionLiteral, RuntimeError> {
-        let prop = self.fields.borrow().get(property_name).cloned();
+        span: Span,
+        property_name: Symbol,
+    ) -> Result<ExpressionLiteral, LoxError> {
+        let prop = self.fields.borrow().get(&property_name).cloned();
 
         if let Some(property) = prop {
             return Ok(property);
         };
 
-        let method = self.class.methods.borrow().get(property_name).cloned();
-
-        if let Some(method) = method {
+        if let Some(method) = self.class.methods.get(&property_name) {
             return Ok(ExpressionLiteral::Reference(Reference::CallableReference(
-                method,
+                bind_method(self, method.clone()),
             )));
         }
 
-        Err(RuntimeError {
-            line_number,
-            message: format!("Unable to find property {property_name}"),
-        })
+        Err(LoxError::new(
+            ErrorKind::UndefinedProperty(property_name.to_string()),
+            span,
+        ))
     }
 
     pub fn set_property(
         &self,
-        property_name: String,
+        property_name: Symbol,
         value: ExpressionLiteral,
-    ) -> Result<ExpressionLiteral, RuntimeError> {
+    ) -> Result<ExpressionLiteral, LoxError> {
         self.fields
             .borrow_mut()
             .insert(property_name, value.clone());
@@ -122,3 +154,79 @@ impl PartialEq for InstanceReference {
         false
     }
 }
+
+#[derive(Clone)]
+pub struct ListReference {
+    pub items: Rc<RefCell<Vec<ExpressionLiteral>>>,
+}
+
+impl ListReference {
+    pub fn new(items: Vec<ExpressionLiteral>) -> Self {
+        ListReference {
+            items: Rc::new(RefCell::new(items)),
+        }
+    }
+
+    pub fn get_property(
+        &self,
+        span: Span,
+        property_name: Symbol,
+    ) -> Result<ExpressionLiteral, LoxError> {
+        match &*property_name.as_str() {
+            "length" => Ok(ExpressionLiteral::Number(self.items.borrow().len() as f64)),
+            "get" => {
+                let items = self.items.clone();
+
+                Ok(ExpressionLiteral::Reference(Reference::CallableReference(
+                    CallableReference {
+                        name: FunctionName::Function(property_name),
+                        span: Span::default(),
+                        arity: 1,
+                        subroutine: Rc::new(move |span, args| -> Result<BlockReturn, LoxError> {
+                            let index = match args.first() {
+                                Some(ExpressionLiteral::Number(index)) => Some(*index),
+                                _ => None,
+                            };
+
+                            let items = items.borrow();
+
+                            Ok(BlockReturn::from(
+                                items[list_index(span, index, items.len())?].clone(),
+                            ))
+                        }),
+                    },
+                )))
+            }
+            _ => Err(LoxError::new(
+                ErrorKind::UndefinedProperty(property_name.to_string()),
+                span,
+            )),
+        }
+    }
+}
+
+/// Checks that `index` points at one of the `length` items of a list, `index` is
+/// `None` when it wasn't given as a number
+pub fn list_index(span: Span, index: Option<f64>, length: usize) -> Result<usize, LoxError> {
+    match index {
+        Some(index) if index.fract() == 0.0 && index >= 0.0 && (index as usize) < length => {
+            Ok(index as usize)
+        }
+        _ => Err(LoxError::new(ErrorKind::IndexOutOfRange { length }, span)),
+    }
+}
+
+impl Debug for ListReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "( list {} )", self.items.borrow().len())
+    }
+}
+
+/**
+ * Checks if the references are to the same list
+ */
+impl PartialEq for ListReference {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.items, &other.items)
+    }
+}
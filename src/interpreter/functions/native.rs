@@ -1,61 +1,231 @@
 use std::{rc::Rc, time::UNIX_EPOCH};
 
 use crate::{
-    errors::RuntimeError, interpreter::types::BlockReturn, tree::expression::ExpressionLiteral,
+    errors::{ErrorKind, LoxError},
+    interpreter::types::BlockReturn,
+    span::Span,
+    symbol::Symbol,
+    tree::expression::ExpressionLiteral,
+    vm::{self, value::Value},
 };
 
-use super::CallableReference;
+use super::{CallableReference, FunctionName, ListReference, Reference};
+
+/// A global every program starts with
+pub struct BuiltIn {
+    pub name: &'static str,
+    /// Value of the global on the tree walker, given the script's arguments
+    pub tree_walker: fn(&[String]) -> ExpressionLiteral,
+    /// Value of the global on the virtual machine, given the script's arguments
+    pub vm: fn(&[String]) -> Value,
+}
+
+/// Every built-in, in the order they take the first global slots
+///
+/// The resolver and both backends all define the globals from this table, so
+/// they always agree on which slot each one is in
+pub static BUILT_INS: [BuiltIn; 5] = [
+    BuiltIn {
+        name: "now",
+        tree_walker: |_| callable(create_native_now()),
+        vm: |_| Value::Native(&vm::native::NOW),
+    },
+    BuiltIn {
+        name: "print",
+        tree_walker: |_| callable(create_native_print()),
+        vm: |_| Value::Native(&vm::native::PRINT),
+    },
+    BuiltIn {
+        name: "env",
+        tree_walker: |_| callable(create_native_env()),
+        vm: |_| Value::Native(&vm::native::ENV),
+    },
+    BuiltIn {
+        name: "exit",
+        tree_walker: |_| callable(create_native_exit()),
+        vm: |_| Value::Native(&vm::native::EXIT),
+    },
+    BuiltIn {
+        name: "args",
+        tree_walker: |script_arguments| {
+            ExpressionLiteral::Reference(Reference::ListReference(ListReference::new(
+                script_arguments
+                    .iter()
+                    .cloned()
+                    .map(ExpressionLiteral::String)
+                    .collect(),
+            )))
+        },
+        vm: |script_arguments| vm::native::list(script_arguments),
+    },
+];
+
+fn callable(reference: CallableReference) -> ExpressionLiteral {
+    ExpressionLiteral::Reference(Reference::CallableReference(reference))
+}
+
+// The natives' behaviour lives in the helpers below, so that every backend
+// shares it and only has to convert its own values
+
+#[cfg(test)]
+thread_local! {
+    static CAPTURED_OUTPUT: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Seconds since the unix epoch, with millisecond precision
+pub fn seconds_since_epoch() -> f64 {
+    let timestamp = UNIX_EPOCH.elapsed().unwrap().as_millis() as f64;
+
+    timestamp / 1000f64
+}
+
+/// Writes the text of a printed value followed by a newline
+pub fn write_line(span: Span, text: &str) -> Result<(), LoxError> {
+    let mut line = text.to_owned();
+
+    line.push('\n');
+
+    #[cfg(test)]
+    {
+        let captured = CAPTURED_OUTPUT.with_borrow_mut(|captured| {
+            captured
+                .as_mut()
+                .map(|captured_output| captured_output.push_str(&line))
+        });
+
+        if captured.is_some() {
+            return Ok(());
+        }
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    {
+        if std::io::Write::write(&mut std::io::stdout(), line.as_bytes()).is_err() {
+            return Err(LoxError::new(ErrorKind::OutputFailed, span));
+        }
+    }
+
+    #[cfg(target_family = "wasm")]
+    {
+        let _ = span;
+
+        crate::logging::pushToLog(&line);
+    }
+
+    Ok(())
+}
+
+/// Collects everything printed on this thread while `f` runs instead of writing it out
+#[cfg(test)]
+pub fn capture_output<T>(f: impl FnOnce() -> T) -> (T, String) {
+    CAPTURED_OUTPUT.set(Some(String::new()));
+
+    let result = f();
+
+    (result, CAPTURED_OUTPUT.take().unwrap_or_default())
+}
+
+/// Value of the environment variable, `name` is `None` when it wasn't given as a string
+pub fn read_env(span: Span, name: Option<&str>) -> Result<Option<String>, LoxError> {
+    match name {
+        Some(name) => Ok(std::env::var(name).ok()),
+        None => Err(LoxError::new(
+            ErrorKind::InvalidArgument {
+                function: "env",
+                expected: "the name of the environment variable as a string",
+            },
+            span,
+        )),
+    }
+}
+
+/// Error that stops the program, `code` is `None` when it wasn't given as a number
+///
+/// Processes can only exit with a byte, so codes outside of it are an error rather
+/// than being truncated into one, which could turn a failure into success
+pub fn exit_error(span: Span, code: Option<f64>) -> LoxError {
+    match code {
+        Some(code) if code.fract() == 0.0 && (0.0..=255.0).contains(&code) => {
+            LoxError::new(ErrorKind::Exit(code as i32), span)
+        }
+        _ => LoxError::new(
+            ErrorKind::InvalidArgument {
+                function: "exit",
+                expected: "a whole number from 0 to 255 to exit with",
+            },
+            span,
+        ),
+    }
+}
 
 pub fn create_native_now() -> CallableReference {
     CallableReference {
+        name: FunctionName::Function(Symbol::intern("now")),
+        span: Span::default(),
         arity: 0,
-        subroutine: Rc::new(|_line_number, _args| -> Result<BlockReturn, RuntimeError> {
-            let timestamp = UNIX_EPOCH.elapsed().unwrap().as_millis() as f64;
-            let seconds = timestamp / 1000f64;
-
-            Ok(BlockReturn::from(ExpressionLiteral::Number(seconds)))
+        subroutine: Rc::new(|_span, _args| -> Result<BlockReturn, LoxError> {
+            Ok(BlockReturn::from(ExpressionLiteral::Number(
+                seconds_since_epoch(),
+            )))
         }),
     }
 }
 
 pub fn create_native_print() -> CallableReference {
     CallableReference {
+        name: FunctionName::Function(Symbol::intern("print")),
+        span: Span::default(),
         arity: 1,
-        subroutine: Rc::new(|line_number, args| -> Result<BlockReturn, RuntimeError> {
+        subroutine: Rc::new(|span, args| -> Result<BlockReturn, LoxError> {
             match args.first() {
                 Some(expr) => {
-                    let mut evaluated_string = expr.to_string();
-
-                    evaluated_string.push('\n');
-
-                    #[cfg(not(target_family = "wasm"))]
-                    {
-                        if std::io::Write::write(
-                            &mut std::io::stdout(),
-                            evaluated_string.as_bytes(),
-                        )
-                        .is_err()
-                        {
-                            return Err(RuntimeError {
-                                line_number,
-                                message: "Failed to access stdout".to_owned(),
-                            });
-                        }
-                    }
-
-                    #[cfg(target_family = "wasm")]
-                    {
-                        crate::logging::report!("{}", evaluated_string);
-                    }
+                    write_line(span, &expr.to_string())?;
 
                     Ok(BlockReturn::NoReturn)
                 }
-                None => Err(RuntimeError {
-                    line_number,
-                    message: "Must provide value to print, perhaps you meant print(\"\")?"
-                        .to_owned(),
-                }),
+                None => Err(LoxError::new(
+                    ErrorKind::InvalidArgument {
+                        function: "print",
+                        expected: "a value to print",
+                    },
+                    span,
+                )),
             }
         }),
     }
 }
+
+pub fn create_native_env() -> CallableReference {
+    CallableReference {
+        name: FunctionName::Function(Symbol::intern("env")),
+        span: Span::default(),
+        arity: 1,
+        subroutine: Rc::new(|span, args| -> Result<BlockReturn, LoxError> {
+            let name = match args.first() {
+                Some(ExpressionLiteral::String(name)) => Some(name.as_str()),
+                _ => None,
+            };
+
+            Ok(BlockReturn::from(match read_env(span, name)? {
+                Some(value) => ExpressionLiteral::String(value),
+                None => ExpressionLiteral::Nil,
+            }))
+        }),
+    }
+}
+
+pub fn create_native_exit() -> CallableReference {
+    CallableReference {
+        name: FunctionName::Function(Symbol::intern("exit")),
+        span: Span::default(),
+        arity: 1,
+        subroutine: Rc::new(|span, args| -> Result<BlockReturn, LoxError> {
+            let code = match args.first() {
+                Some(ExpressionLiteral::Number(code)) => Some(*code),
+                _ => None,
+            };
+
+            Err(exit_error(span, code))
+        }),
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Deepest the call stack can get before the program errors
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+/// Native stack a single call can need, unoptimized builds give every temporary
+/// its own stack slot so they need far more than release builds
+pub const STACK_BYTES_PER_CALL: usize = if cfg!(debug_assertions) {
+    256 * 1024
+} else {
+    16 * 1024
+};
+
+/// Stack for everything other than calls, such as parsing deeply nested expressions
+const BASE_STACK_BYTES: usize = 8 * 1024 * 1024;
+
+/// Bounds on the resources a program can use while running
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// How many calls can be in progress at once
+    pub max_call_depth: usize,
+    /// How many statements, calls and loop iterations can run in total, unlimited when `None`
+    pub max_steps: Option<u64>,
+    /// How long the program can run for, unlimited when `None`
+    pub timeout: Option<Duration>,
+    /// Lets another thread stop the program early
+    pub cancellation: Option<CancellationToken>,
+}
+
+/// Flag that stops a running program the next time it makes a call or goes
+/// around a loop, it can be cloned and set from any thread
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clears the flag so the token can be used for another run
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Checks if the tokens share the same flag
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CancellationToken {}
+
+impl Limits {
+    /// Native stack needed to reach the maximum call depth without overflowing
+    pub fn stack_size(&self) -> usize {
+        self.max_call_depth
+            .saturating_mul(STACK_BYTES_PER_CALL)
+            .saturating_add(BASE_STACK_BYTES)
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_steps: None,
+            timeout: None,
+            cancellation: None,
+        }
+    }
+}
@@ -1,26 +1,33 @@
+pub(crate) mod budget;
+mod call_stack;
 mod environment;
 pub mod functions;
+pub mod limits;
 mod statements;
 mod tests;
 mod types;
 
-use std::{borrow::Borrow, cell::RefCell, collections::HashMap, iter::zip, ops::Deref, rc::Rc};
+use std::{borrow::Borrow, cell::RefCell, iter::zip, ops::Deref, rc::Rc};
 
-use environment::{Environment, EnvironmentRef};
+use call_stack::{set_max_call_depth, CallGuard};
+use environment::{with_variable_map, Environment, EnvironmentRef};
 use functions::{
-    native::{create_native_now, create_native_print},
-    CallableReference, ClassReference, InstanceReference, Reference,
+    native::BUILT_INS, CallableReference, ClassReference, FunctionName, InstanceReference,
+    Reference,
 };
+use limits::Limits;
 use statements::interpret_variable_definition;
 use types::BlockReturn;
 
 use crate::{
-    errors::RuntimeError,
+    errors::{ErrorKind, LoxError},
     parser::{
-        statements::{IfStatement, Statement, WhileStatement},
-        ParsedStep, ParsingResult,
+        statements::{FunStatement, IfStatement, Statement, WhileStatement},
+        ParsedStep, ParsingResult, StepKind,
     },
     resolver::VariableMap,
+    span::Span,
+    symbol::Symbol,
     tree::expression::{
         ComparisonOperation, EqualityOperation, Expression, ExpressionLiteral, FactorOperation,
         LogicalOperation, Operation, TermOperation, UnaryOperation,
@@ -28,32 +35,73 @@ use crate::{
 };
 
 // TODO: Test
-pub fn interpret(variable_map: VariableMap, steps: Vec<ParsingResult>) -> Result<(), RuntimeError> {
-    let global_environment = Environment::with_resolved_variable_map(variable_map);
+pub fn interpret(
+    variable_map: VariableMap,
+    steps: &[ParsingResult],
+    script_arguments: Vec<String>,
+    limits: Limits,
+) -> Result<(), LoxError> {
+    Session::new(script_arguments).run(variable_map, steps, limits)
+}
+
+/// Globals kept between programs run one after another, like the lines of the REPL
+pub struct Session {
+    global_environment: EnvironmentRef,
+    /// Where the variables of every step run so far live
+    variable_map: VariableMap,
+}
+
+impl Session {
+    /// Starts off with only the built-ins defined
+    pub fn new(script_arguments: Vec<String>) -> Self {
+        let global_environment = Environment::new();
 
-    global_environment.define_variable(
-        0,
-        "now".to_owned(),
-        ExpressionLiteral::Reference(Reference::CallableReference(create_native_now())),
-    )?;
+        for built_in in &BUILT_INS {
+            global_environment.define_variable(
+                Symbol::intern(built_in.name),
+                (built_in.tree_walker)(&script_arguments),
+            );
+        }
+
+        Session {
+            global_environment: Rc::new(RefCell::new(global_environment)),
+            variable_map: VariableMap::default(),
+        }
+    }
 
-    global_environment.define_variable(
-        0,
-        "print".to_owned(),
-        ExpressionLiteral::Reference(Reference::CallableReference(create_native_print())),
-    )?;
+    /// Runs steps resolved after the ones run before them, with the same resolver
+    pub fn run(
+        &mut self,
+        variable_map: VariableMap,
+        steps: &[ParsingResult],
+        limits: Limits,
+    ) -> Result<(), LoxError> {
+        set_max_call_depth(limits.max_call_depth);
+        budget::start(&limits);
 
-    interpret_steps(Rc::new(RefCell::new(global_environment)), steps)?;
+        self.variable_map.extend(variable_map);
 
-    Ok(())
+        with_variable_map(&mut self.variable_map, || {
+            interpret_steps(self.global_environment.clone(), steps)
+        })?;
+
+        Ok(())
+    }
+
+    /// How many globals have been defined, including the built-ins
+    pub fn global_count(&self) -> usize {
+        let global_environment: &RefCell<Environment> = self.global_environment.borrow();
+
+        global_environment.borrow().len()
+    }
 }
 
 pub fn interpret_steps(
     environment: EnvironmentRef,
-    steps: Vec<ParsingResult>,
-) -> Result<BlockReturn, RuntimeError> {
+    steps: &[ParsingResult],
+) -> Result<BlockReturn, LoxError> {
     for step in steps {
-        match interpret_step(environment.clone(), step.unwrap())? {
+        match interpret_step(environment.clone(), step.as_ref().map_err(Clone::clone)?)? {
             BlockReturn::Returned(Some(returned)) => {
                 return Ok(BlockReturn::Returned(Some(returned)))
             }
@@ -65,21 +113,21 @@ pub fn interpret_steps(
     Ok(BlockReturn::NoReturn)
 }
 
-fn interpret_step(
-    environment: EnvironmentRef,
-    step: ParsedStep,
-) -> Result<BlockReturn, RuntimeError> {
-    Ok(match step {
-        ParsedStep::Expression(expr) => {
-            interpret_expression_tree(environment.clone(), expr)?;
+fn interpret_step(environment: EnvironmentRef, step: &ParsedStep) -> Result<BlockReturn, LoxError> {
+    Ok(match &step.kind {
+        StepKind::Expression(expr) => {
+            budget::step(step.span)?;
+
+            interpret_expression_tree(environment, expr)?;
 
             BlockReturn::NoReturn
         }
-        ParsedStep::Statement(statement) => {
-            // TODO: Find some way to get the line number of a statement
-            interpret_statement(environment, statement, 0)?
+        StepKind::Statement(statement) => {
+            budget::step(step.span)?;
+
+            interpret_statement(environment, statement)?
         }
-        ParsedStep::Block(steps) => {
+        StepKind::Block(steps) => {
             let block_environment = Rc::new(RefCell::new(Environment::with_parent(environment)));
 
             interpret_steps(block_environment, steps)?
@@ -89,60 +137,51 @@ fn interpret_step(
 
 pub fn interpret_statement(
     environment: EnvironmentRef,
-    statement: Statement,
-    line_number: usize,
-) -> Result<BlockReturn, RuntimeError> {
+    statement: &Statement,
+) -> Result<BlockReturn, LoxError> {
     match statement {
-        Statement::Variable(name, value) => {
-            interpret_variable_definition(environment.clone(), line_number, name, value)?
+        Statement::Variable(_span, name, value) => {
+            interpret_variable_definition(environment.clone(), *name, value)?
         }
         Statement::If(IfStatement {
+            span: _,
             condition,
             then_statement,
             else_statement,
         }) => {
             if is_truthy(environment.clone(), condition)? {
-                interpret_step(environment.clone(), *then_statement)?;
-            } else if else_statement.is_some() {
-                interpret_step(environment, *else_statement.unwrap())?;
+                return interpret_step(environment.clone(), then_statement);
+            } else if let Some(else_statement) = else_statement {
+                return interpret_step(environment, else_statement);
             }
         }
-        Statement::While(WhileStatement { condition, body }) => {
-            while is_truthy(environment.clone(), condition.clone())? {
-                interpret_step(environment.clone(), *body.clone())?;
+        Statement::While(WhileStatement {
+            span,
+            condition,
+            body,
+        }) => {
+            while is_truthy(environment.clone(), condition)? {
+                // Counted separately from the body, which could be empty
+                budget::step(*span)?;
+
+                if let returned @ BlockReturn::Returned(_) =
+                    interpret_step(environment.clone(), body)?
+                {
+                    return Ok(returned);
+                }
             }
         }
         Statement::Fun(function_definition) => {
-            let function_parent_environment = environment.clone();
-            let function_body = function_definition.clone().body;
-
-            let name = function_definition.name.clone();
-
-            let func =
-                ExpressionLiteral::Reference(Reference::CallableReference(CallableReference {
-                    arity: function_definition.parameters.len(),
-                    subroutine: Rc::new(
-                        move |call_line_number, args| -> Result<BlockReturn, RuntimeError> {
-                            let env = Environment::with_parent(function_parent_environment.clone());
-                            let function_environment = Rc::new(RefCell::new(env.clone()));
-
-                            for (name, value) in zip(function_definition.clone().parameters, args) {
-                                (function_environment).borrow_mut().define_variable(
-                                    call_line_number,
-                                    name,
-                                    value,
-                                )?;
-                            }
-
-                            interpret_step(function_environment, *function_body.clone())
-                        },
-                    ),
-                }));
+            let func = ExpressionLiteral::Reference(Reference::CallableReference(create_function(
+                environment.clone(),
+                FunctionName::Function(function_definition.name),
+                function_definition.clone(),
+            )));
 
             let env: &RefCell<Environment> = environment.borrow();
-            env.borrow().define_variable(line_number, name, func)?;
+            env.borrow().define_variable(function_definition.name, func);
         }
-        Statement::Return(optional_expression) => match optional_expression {
+        Statement::Return(_span, optional_expression) => match optional_expression {
             Some(expression) => {
                 let returned = interpret_expression_tree(environment, expression)?;
 
@@ -151,106 +190,137 @@ pub fn interpret_statement(
             None => return Ok(BlockReturn::Returned(None)),
         },
         Statement::Class(class) => {
-            let mut methods = HashMap::new();
-
-            for method in class.methods {
-                let parent_environment = environment.clone();
-
-                methods.insert(
-                    method.name.clone(),
-                    CallableReference {
-                        arity: method.parameters.len(),
-                        subroutine: Rc::new(
-                            move |call_line_number, args| -> Result<BlockReturn, RuntimeError> {
-                                let env = Environment::with_parent(parent_environment.clone());
-                                let function_environment = Rc::new(RefCell::new(env.clone()));
-
-                                for (name, value) in zip(method.clone().parameters, args) {
-                                    function_environment.borrow_mut().define_variable(
-                                        call_line_number,
-                                        name,
-                                        value,
-                                    )?;
-                                }
-
-                                interpret_step(function_environment, *method.clone().body)
-                            },
-                        ),
-                    },
-                );
-            }
+            let methods = class
+                .methods
+                .iter()
+                .map(|method| (method.name, method.clone()))
+                .collect();
+
+            let class_reference = ClassReference {
+                name: class.name,
+                methods: Rc::new(methods),
+                environment: environment.clone(),
+            };
 
             let env: &RefCell<Environment> = environment.borrow();
 
             env.borrow().define_variable(
-                line_number,
-                class.name.clone(),
-                ExpressionLiteral::Reference(Reference::ClassReference(ClassReference {
-                    name: class.name,
-                    methods: Rc::new(RefCell::new(methods)),
-                })),
-            )?;
+                class.name,
+                ExpressionLiteral::Reference(Reference::ClassReference(class_reference)),
+            );
         }
     }
 
     Ok(BlockReturn::NoReturn)
 }
 
+/// Creates a function that runs the declaration's body in a new environment
+/// inside of `parent_environment`, sharing the declaration rather than copying it
+fn create_function(
+    parent_environment: EnvironmentRef,
+    name: FunctionName,
+    function_definition: Rc<FunStatement>,
+) -> CallableReference {
+    CallableReference {
+        name,
+        span: function_definition.span,
+        arity: function_definition.parameters.len(),
+        subroutine: Rc::new(move |_call_span, args| -> Result<BlockReturn, LoxError> {
+            let function_environment = Rc::new(RefCell::new(Environment::with_parent(
+                parent_environment.clone(),
+            )));
+
+            for (parameter, value) in zip(&function_definition.parameters, args) {
+                function_environment
+                    .borrow_mut()
+                    .define_variable(parameter.name, value);
+            }
+
+            interpret_step(function_environment, &function_definition.body)
+        }),
+    }
+}
+
+/// Creates the method for `instance`, running in an environment that holds only
+/// `this` inside of the one its class was declared in
+///
+/// Initializers always return the instance, even from a bare `return`
+fn bind_method(instance: &InstanceReference, declaration: Rc<FunStatement>) -> CallableReference {
+    let this = ExpressionLiteral::Reference(Reference::InstanceReference(instance.clone()));
+
+    let this_environment = Environment::with_parent(instance.class.environment.clone());
+    this_environment.define_variable(Symbol::intern("this"), this.clone());
+
+    let is_initializer = declaration.name == "init";
+
+    let method = create_function(
+        Rc::new(RefCell::new(this_environment)),
+        FunctionName::Method {
+            class: instance.class.name,
+            method: declaration.name,
+        },
+        declaration,
+    );
+
+    if !is_initializer {
+        return method;
+    }
+
+    let body = method.subroutine.clone();
+
+    CallableReference {
+        subroutine: Rc::new(move |span, args| {
+            body(span, args)?;
+
+            Ok(BlockReturn::Returned(Some(this.clone())))
+        }),
+        ..method
+    }
+}
+
 pub fn interpret_expression_tree(
     environment: EnvironmentRef,
-    tree: Expression,
-) -> Result<ExpressionLiteral, RuntimeError> {
-    let literal: Result<ExpressionLiteral, RuntimeError> = match tree {
-        Expression::Grouping(grouped_expression) => {
-            interpret_expression_tree(environment, *grouped_expression)
+    tree: &Expression,
+) -> Result<ExpressionLiteral, LoxError> {
+    let literal: Result<ExpressionLiteral, LoxError> = match tree {
+        Expression::Grouping(_span, grouped_expression) => {
+            interpret_expression_tree(environment, grouped_expression)
         }
-        Expression::Literal(literal) => Ok(literal),
+        Expression::Literal(_span, literal) => Ok(literal.clone()),
         Expression::Operation(operation) => match operation {
-            Operation::Negate(UnaryOperation {
-                operand,
-                line_number,
-            }) => match *operand {
-                Expression::Literal(literal) => match literal {
+            Operation::Negate(UnaryOperation { operand, span }) => match operand.as_ref() {
+                Expression::Literal(_span, literal) => match literal {
                     ExpressionLiteral::Number(number) => Ok(ExpressionLiteral::Number(-number)),
-                    ExpressionLiteral::Nil => Err(RuntimeError {
-                        line_number,
-                        message: "Tried to Negate Nil value".to_string(),
-                    }),
-                    literal => Err(RuntimeError {
-                        line_number,
-                        message: format!("Tried to Negate invalid literal: {literal}"),
-                    }),
+                    literal => Err(LoxError::new(
+                        ErrorKind::InvalidOperand {
+                            operation: "negate",
+                            operand: literal.to_string(),
+                        },
+                        *span,
+                    )),
                 },
                 expression => interpret_expression_tree(environment, expression),
             },
 
-            Operation::Not(UnaryOperation {
-                operand,
-                line_number: _,
-            }) => {
-                if is_truthy(environment, *operand)? {
+            Operation::Not(UnaryOperation { operand, span: _ }) => {
+                if is_truthy(environment, operand)? {
                     Ok(ExpressionLiteral::False)
                 } else {
                     Ok(ExpressionLiteral::True)
                 }
             }
 
-            Operation::Equal(EqualityOperation {
-                left,
-                right,
-                line_number,
-            }) => {
-                let left_parsed = interpret_expression_tree(environment.clone(), *left)?;
-                let right_parsed = interpret_expression_tree(environment.clone(), *right)?;
+            Operation::Equal(EqualityOperation { left, right, span }) => {
+                let left_parsed = interpret_expression_tree(environment.clone(), left)?;
+                let right_parsed = interpret_expression_tree(environment.clone(), right)?;
 
                 if !left_parsed.is_same_type(&right_parsed) {
-                    return Err(RuntimeError {
-                        message: format!(
-                            "Tried to compare invalid types to each other: {} and {}",
-                            left_parsed, right_parsed
-                        ),
-                        line_number,
-                    });
+                    return Err(invalid_operands(
+                        "compare",
+                        left_parsed,
+                        right_parsed,
+                        *span,
+                    ));
                 }
 
                 if left_parsed == right_parsed {
@@ -260,22 +330,17 @@ pub fn interpret_expression_tree(
                 }
             }
 
-            Operation::NotEqual(EqualityOperation {
-                left,
-                right,
-                line_number,
-            }) => {
-                let left_parsed = interpret_expression_tree(environment.clone(), *left)?;
-                let right_parsed = interpret_expression_tree(environment.clone(), *right)?;
+            Operation::NotEqual(EqualityOperation { left, right, span }) => {
+                let left_parsed = interpret_expression_tree(environment.clone(), left)?;
+                let right_parsed = interpret_expression_tree(environment.clone(), right)?;
 
                 if !left_parsed.is_same_type(&right_parsed) {
-                    return Err(RuntimeError {
-                        message: format!(
-                            "Tried to compare invalid types to each other: {} and {}",
-                            left_parsed, right_parsed
-                        ),
-                        line_number,
-                    });
+                    return Err(invalid_operands(
+                        "compare",
+                        left_parsed,
+                        right_parsed,
+                        *span,
+                    ));
                 }
 
                 if left_parsed != right_parsed {
@@ -285,13 +350,9 @@ pub fn interpret_expression_tree(
                 }
             }
 
-            Operation::Less(ComparisonOperation {
-                left,
-                right,
-                line_number,
-            }) => {
-                let left_parsed = interpret_expression_tree(environment.clone(), *left)?;
-                let right_parsed = interpret_expression_tree(environment.clone(), *right)?;
+            Operation::Less(ComparisonOperation { left, right, span }) => {
+                let left_parsed = interpret_expression_tree(environment.clone(), left)?;
+                let right_parsed = interpret_expression_tree(environment.clone(), right)?;
 
                 match (&left_parsed, &right_parsed) {
                     (
@@ -302,20 +363,18 @@ pub fn interpret_expression_tree(
                     } else {
                         ExpressionLiteral::False
                     }),
-                    _ => Err(RuntimeError {
-                        message: format!("Cannot compare types {left_parsed} and {right_parsed}"),
-                        line_number,
-                    }),
+                    _ => Err(invalid_operands(
+                        "compare",
+                        left_parsed,
+                        right_parsed,
+                        *span,
+                    )),
                 }
             }
 
-            Operation::LessEqual(ComparisonOperation {
-                left,
-                right,
-                line_number,
-            }) => {
-                let left_parsed = interpret_expression_tree(environment.clone(), *left)?;
-                let right_parsed = interpret_expression_tree(environment.clone(), *right)?;
+            Operation::LessEqual(ComparisonOperation { left, right, span }) => {
+                let left_parsed = interpret_expression_tree(environment.clone(), left)?;
+                let right_parsed = interpret_expression_tree(environment.clone(), right)?;
 
                 match (&left_parsed, &right_parsed) {
                     (
@@ -326,20 +385,18 @@ pub fn interpret_expression_tree(
                     } else {
                         ExpressionLiteral::False
                     }),
-                    _ => Err(RuntimeError {
-                        message: format!("Cannot compare types {left_parsed} and {right_parsed}"),
-                        line_number,
-                    }),
+                    _ => Err(invalid_operands(
+                        "compare",
+                        left_parsed,
+                        right_parsed,
+                        *span,
+                    )),
                 }
             }
 
-            Operation::Greater(ComparisonOperation {
-                left,
-                right,
-                line_number,
-            }) => {
-                let left_parsed = interpret_expression_tree(environment.clone(), *left)?;
-                let right_parsed = interpret_expression_tree(environment.clone(), *right)?;
+            Operation::Greater(ComparisonOperation { left, right, span }) => {
+                let left_parsed = interpret_expression_tree(environment.clone(), left)?;
+                let right_parsed = interpret_expression_tree(environment.clone(), right)?;
 
                 match (&left_parsed, &right_parsed) {
                     (
@@ -350,20 +407,18 @@ pub fn interpret_expression_tree(
                     } else {
                         ExpressionLiteral::False
                     }),
-                    _ => Err(RuntimeError {
-                        message: format!("Cannot compare types {left_parsed} and {right_parsed}"),
-                        line_number,
-                    }),
+                    _ => Err(invalid_operands(
+                        "compare",
+                        left_parsed,
+                        right_parsed,
+                        *span,
+                    )),
                 }
             }
 
-            Operation::GreaterEqual(ComparisonOperation {
-                left,
-                right,
-                line_number,
-            }) => {
-                let left_parsed = interpret_expression_tree(environment.clone(), *left)?;
-                let right_parsed = interpret_expression_tree(environment.clone(), *right)?;
+            Operation::GreaterEqual(ComparisonOperation { left, right, span }) => {
+                let left_parsed = interpret_expression_tree(environment.clone(), left)?;
+                let right_parsed = interpret_expression_tree(environment.clone(), right)?;
 
                 match (&left_parsed, &right_parsed) {
                     (
@@ -374,20 +429,18 @@ pub fn interpret_expression_tree(
                     } else {
                         ExpressionLiteral::False
                     }),
-                    _ => Err(RuntimeError {
-                        message: format!("Cannot compare types {left_parsed} and {right_parsed}"),
-                        line_number,
-                    }),
+                    _ => Err(invalid_operands(
+                        "compare",
+                        left_parsed,
+                        right_parsed,
+                        *span,
+                    )),
                 }
             }
 
-            Operation::Plus(TermOperation {
-                left,
-                right,
-                line_number,
-            }) => {
-                let left_parsed = interpret_expression_tree(environment.clone(), *left)?;
-                let right_parsed = interpret_expression_tree(environment.clone(), *right)?;
+            Operation::Plus(TermOperation { left, right, span }) => {
+                let left_parsed = interpret_expression_tree(environment.clone(), left)?;
+                let right_parsed = interpret_expression_tree(environment.clone(), right)?;
 
                 match (&left_parsed, &right_parsed) {
                     (
@@ -400,60 +453,49 @@ pub fn interpret_expression_tree(
                     ) => Ok(ExpressionLiteral::String(
                         left_string.to_owned() + right_string,
                     )),
-                    _ => Err(RuntimeError {
-                        message: format!("Cannot add values {left_parsed} and {right_parsed}"),
-                        line_number,
-                    }),
+                    _ => Err(invalid_operands("add", left_parsed, right_parsed, *span)),
                 }
             }
 
-            Operation::Minus(TermOperation {
-                left,
-                right,
-                line_number,
-            }) => {
-                let left_parsed = interpret_expression_tree(environment.clone(), *left)?;
-                let right_parsed = interpret_expression_tree(environment.clone(), *right)?;
+            Operation::Minus(TermOperation { left, right, span }) => {
+                let left_parsed = interpret_expression_tree(environment.clone(), left)?;
+                let right_parsed = interpret_expression_tree(environment.clone(), right)?;
 
                 match (&left_parsed, &right_parsed) {
                     (
                         ExpressionLiteral::Number(left_number),
                         ExpressionLiteral::Number(right_number),
                     ) => Ok(ExpressionLiteral::Number(left_number - right_number)),
-                    _ => Err(RuntimeError {
-                        message: format!("Cannot subtract values {left_parsed} and {right_parsed}"),
-                        line_number,
-                    }),
+                    _ => Err(invalid_operands(
+                        "subtract",
+                        left_parsed,
+                        right_parsed,
+                        *span,
+                    )),
                 }
             }
 
-            Operation::Multiply(FactorOperation {
-                left,
-                right,
-                line_number,
-            }) => {
-                let left_parsed = interpret_expression_tree(environment.clone(), *left)?;
-                let right_parsed = interpret_expression_tree(environment.clone(), *right)?;
+            Operation::Multiply(FactorOperation { left, right, span }) => {
+                let left_parsed = interpret_expression_tree(environment.clone(), left)?;
+                let right_parsed = interpret_expression_tree(environment.clone(), right)?;
 
                 match (&left_parsed, &right_parsed) {
                     (
                         ExpressionLiteral::Number(left_number),
                         ExpressionLiteral::Number(right_number),
                     ) => Ok(ExpressionLiteral::Number(left_number * right_number)),
-                    _ => Err(RuntimeError {
-                        message: format!("Cannot multiply types {left_parsed} and {right_parsed}"),
-                        line_number,
-                    }),
+                    _ => Err(invalid_operands(
+                        "multiply",
+                        left_parsed,
+                        right_parsed,
+                        *span,
+                    )),
                 }
             }
 
-            Operation::Divide(FactorOperation {
-                left,
-                right,
-                line_number,
-            }) => {
-                let left_parsed = interpret_expression_tree(environment.clone(), *left)?;
-                let right_parsed = interpret_expression_tree(environment.clone(), *right)?;
+            Operation::Divide(FactorOperation { left, right, span }) => {
+                let left_parsed = interpret_expression_tree(environment.clone(), left)?;
+                let right_parsed = interpret_expression_tree(environment.clone(), right)?;
 
                 // TODO: Handle divide by zero behavior
 
@@ -462,39 +504,36 @@ pub fn interpret_expression_tree(
                         ExpressionLiteral::Number(left_number),
                         ExpressionLiteral::Number(right_number),
                     ) => Ok(ExpressionLiteral::Number(left_number / right_number)),
-                    _ => Err(RuntimeError {
-                        message: format!("Cannot divide types {left_parsed} and {right_parsed}"),
-                        line_number,
-                    }),
+                    _ => Err(invalid_operands("divide", left_parsed, right_parsed, *span)),
                 }
             }
 
             Operation::And(LogicalOperation {
                 left,
                 right,
-                line_number: _,
+                span: _,
             }) => {
-                let left = interpret_expression_tree(environment.clone(), *left)?;
+                let left = interpret_expression_tree(environment.clone(), left)?;
 
-                if !is_truthy(environment.clone(), Expression::Literal(left.clone()))? {
+                if !is_literal_truthy(&left) {
                     return Ok(left);
                 }
 
-                return interpret_expression_tree(environment, *right);
+                return interpret_expression_tree(environment, right);
             }
 
             Operation::Or(LogicalOperation {
                 left,
                 right,
-                line_number: _,
+                span: _,
             }) => {
-                let left = interpret_expression_tree(environment.clone(), *left)?;
+                let left = interpret_expression_tree(environment.clone(), left)?;
 
-                if is_truthy(environment.clone(), Expression::Literal(left.clone()))? {
+                if is_literal_truthy(&left) {
                     return Ok(left);
                 }
 
-                return interpret_expression_tree(environment, *right);
+                return interpret_expression_tree(environment, right);
             }
         },
         Expression::Variable(var) => {
@@ -504,123 +543,141 @@ pub fn interpret_expression_tree(
         }
 
         Expression::Assign(expression_variable, right_side_tree) => {
-            let expression_value =
-                interpret_expression_tree(environment.clone(), *right_side_tree)?;
+            let expression_value = interpret_expression_tree(environment.clone(), right_side_tree)?;
 
             let env: &RefCell<Environment> = environment.borrow();
-            env.borrow().set_variable(
-                expression_variable.line_number,
-                expression_variable.identifier_name,
-                expression_value,
-            )
+            env.borrow()
+                .set_variable_with_depth(expression_variable, expression_value)
         }
-        Expression::Call(line_number, callable, arguments) => {
-            match interpret_expression_tree(environment.clone(), *callable)? {
+        Expression::Call(span, callable, arguments) => {
+            // Everything is evaluated before checking the call, like the VM does
+            let callee = interpret_expression_tree(environment.clone(), callable)?;
+
+            let arguments = arguments
+                .iter()
+                .map(|argument| interpret_expression_tree(environment.clone(), argument))
+                .collect::<Result<Vec<ExpressionLiteral>, LoxError>>()?;
+
+            match callee {
                 ExpressionLiteral::Reference(reference) => match reference {
                     Reference::CallableReference(callable_reference) => {
-                        evaluate_callable_reference(
-                            environment,
-                            callable_reference,
-                            arguments,
-                            line_number,
-                        )
+                        evaluate_callable_reference(callable_reference, arguments, *span)
                     }
                     Reference::ClassReference(class) => {
                         let instance = InstanceReference::instantiate(class);
 
-                        let reference = Reference::InstanceReference(instance);
-
-                        Ok(ExpressionLiteral::Reference(reference))
+                        match instance.class.methods.get(&Symbol::intern("init")) {
+                            Some(initializer) => evaluate_callable_reference(
+                                bind_method(&instance, initializer.clone()),
+                                arguments,
+                                *span,
+                            ),
+                            None if arguments.is_empty() => Ok(ExpressionLiteral::Reference(
+                                Reference::InstanceReference(instance),
+                            )),
+                            None => Err(LoxError::new(
+                                ErrorKind::ArityMismatch {
+                                    expected: 0,
+                                    found: arguments.len(),
+                                },
+                                *span,
+                            )),
+                        }
+                    }
+                    reference @ (Reference::InstanceReference(_) | Reference::ListReference(_)) => {
+                        Err(LoxError::new(
+                            ErrorKind::NotCallable(
+                                ExpressionLiteral::Reference(reference).to_string(),
+                            ),
+                            *span,
+                        ))
                     }
-                    Reference::InstanceReference(_) => Err(RuntimeError {
-                        line_number,
-                        message: "Can't call a class instance, only a class type".to_string(),
-                    }),
                 },
-                invalid_type => Err(RuntimeError {
-                    line_number,
-                    message: format!(
-                        "Expected function or method reference, found {}",
-                        invalid_type
-                    ),
-                }),
+                invalid_type => Err(LoxError::new(
+                    ErrorKind::NotCallable(invalid_type.to_string()),
+                    *span,
+                )),
             }
         }
-        Expression::Get(line_number, object_expression, identifier) => {
-            let object = interpret_expression_tree(environment, *object_expression)?;
+        Expression::Get(span, object_expression, identifier) => {
+            let object = interpret_expression_tree(environment, object_expression)?;
 
             match object {
-                ExpressionLiteral::Reference(reference) => match reference {
-                    Reference::InstanceReference(instance) => {
-                        instance.get_property(line_number, &identifier)
-                    }
-                    Reference::ClassReference(_) => Err(RuntimeError {
-                        line_number,
-                        message: "Can't access properties on a class, only an instance".to_string(),
-                    }),
-                    _ => Err(RuntimeError {
-                        line_number,
-                        message: "Can only access properties on a instance".to_string(),
-                    }),
-                },
-                _ => Err(RuntimeError {
-                    line_number,
-                    message: "Can only access properties on a instance".to_string(),
-                }),
+                ExpressionLiteral::Reference(Reference::InstanceReference(instance)) => {
+                    instance.get_property(*span, *identifier)
+                }
+                ExpressionLiteral::Reference(Reference::ListReference(list)) => {
+                    list.get_property(*span, *identifier)
+                }
+                object => Err(LoxError::new(
+                    ErrorKind::PropertyOnNonInstance(object.to_string()),
+                    *span,
+                )),
             }
         }
-        Expression::Set(line_number, object_expression, identifier, value) => {
-            let object = interpret_expression_tree(environment.clone(), *object_expression)?;
+        Expression::Set(span, object_expression, identifier, value) => {
+            let object = interpret_expression_tree(environment.clone(), object_expression)?;
+            let value = interpret_expression_tree(environment, value)?;
 
             match object {
-                ExpressionLiteral::Reference(reference) => match reference {
-                    Reference::InstanceReference(instance) => instance
-                        .set_property(identifier, interpret_expression_tree(environment, *value)?),
-                    Reference::ClassReference(_) => Err(RuntimeError {
-                        line_number,
-                        message: "Can't access properties on a class, only an instance".to_string(),
-                    }),
-                    _ => Err(RuntimeError {
-                        line_number,
-                        message: "Can only access properties on a instance".to_string(),
-                    }),
-                },
-                _ => Err(RuntimeError {
-                    line_number,
-                    message: "Can only access properties on a instance".to_string(),
-                }),
+                ExpressionLiteral::Reference(Reference::InstanceReference(instance)) => {
+                    instance.set_property(*identifier, value)
+                }
+                object => Err(LoxError::new(
+                    ErrorKind::PropertyOnNonInstance(object.to_string()),
+                    *span,
+                )),
             }
         }
-        Expression::This => todo!(),
+        Expression::This(this) => {
+            let env: &RefCell<Environment> = environment.borrow();
+
+            env.borrow().get_variable_with_depth(this)
+        }
     };
 
     literal
 }
 
+fn invalid_operands(
+    operation: &'static str,
+    left: ExpressionLiteral,
+    right: ExpressionLiteral,
+    span: Span,
+) -> LoxError {
+    LoxError::new(
+        ErrorKind::InvalidOperands {
+            operation,
+            left: left.to_string(),
+            right: right.to_string(),
+        },
+        span,
+    )
+}
+
 fn evaluate_callable_reference(
-    environment: EnvironmentRef,
     reference: CallableReference,
-    arguments: Vec<Expression>,
-    line_number: usize,
-) -> Result<ExpressionLiteral, RuntimeError> {
-    let provided_arity = arguments.len();
-
-    if provided_arity != reference.arity {
-        return Err(RuntimeError {
-            line_number,
-            message: format!(
-                "Expected {} arguments, received {}",
-                reference.arity, provided_arity
-            ),
-        });
+    arguments: Vec<ExpressionLiteral>,
+    span: Span,
+) -> Result<ExpressionLiteral, LoxError> {
+    if arguments.len() != reference.arity {
+        return Err(LoxError::new(
+            ErrorKind::ArityMismatch {
+                expected: reference.arity,
+                found: arguments.len(),
+            },
+            span,
+        ));
     };
 
-    let evaluated_args = arguments
-        .into_iter()
-        .map(|expr| interpret_expression_tree(environment.clone(), expr))
-        .collect::<Result<Vec<ExpressionLiteral>, RuntimeError>>()?;
+    budget::step(span)?;
 
-    let ret = Fn::call(reference.subroutine.deref(), (line_number, evaluated_args))?;
+    let _call = CallGuard::enter(reference.name, span)
+        .map_err(|error| error.with_backtrace(call_stack::backtrace()))?;
+
+    // The innermost call sees the error first, while the whole stack is still there
+    let ret = Fn::call(reference.subroutine.deref(), (span, arguments))
+        .map_err(|error| error.with_backtrace(call_stack::backtrace()))?;
 
     match ret {
         BlockReturn::Returned(Some(value)) => Ok(value),
@@ -628,20 +685,30 @@ fn evaluate_callable_reference(
     }
 }
 
-pub fn is_truthy(environment: EnvironmentRef, expr: Expression) -> Result<bool, RuntimeError> {
+/// Evaluates an expression that never reads a variable, such as an operation
+/// on literals, exactly like running it would
+pub fn evaluate_constant(expression: &Expression) -> Result<ExpressionLiteral, LoxError> {
+    interpret_expression_tree(Rc::new(RefCell::new(Environment::new())), expression)
+}
+
+pub fn is_truthy(environment: EnvironmentRef, expr: &Expression) -> Result<bool, LoxError> {
     match expr {
-        Expression::Literal(literal) => match literal {
-            ExpressionLiteral::Number(number) => Ok(number != 0.0),
-            ExpressionLiteral::String(str) => Ok(!str.is_empty()),
-            ExpressionLiteral::True => Ok(true),
-            ExpressionLiteral::False => Ok(false),
-            ExpressionLiteral::Nil => Ok(false),
-            ExpressionLiteral::Reference(_) => Ok(true),
-        },
+        Expression::Literal(_span, literal) => Ok(is_literal_truthy(literal)),
         tree => {
-            let evaluated_tree = interpret_expression_tree(environment.clone(), tree)?;
+            let evaluated_tree = interpret_expression_tree(environment, tree)?;
 
-            is_truthy(environment, Expression::Literal(evaluated_tree))
+            Ok(is_literal_truthy(&evaluated_tree))
         }
     }
 }
+
+pub fn is_literal_truthy(literal: &ExpressionLiteral) -> bool {
+    match literal {
+        ExpressionLiteral::Number(number) => *number != 0.0,
+        ExpressionLiteral::String(str) => !str.is_empty(),
+        ExpressionLiteral::True => true,
+        ExpressionLiteral::False => false,
+        ExpressionLiteral::Nil => false,
+        ExpressionLiteral::Reference(_) => true,
+    }
+}
@@ -1,13 +1,25 @@
 #![cfg(test)]
 
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, rc::Rc, time::Duration};
 
 use crate::{
-    interpreter::{environment::Environment, is_truthy},
+    conformance::run_on,
+    errors::{ErrorKind, LoxError},
+    interpreter::{
+        environment::Environment,
+        is_truthy,
+        limits::{CancellationToken, Limits},
+        Session,
+    },
+    parser::{parse, statements::Statement, StepKind},
+    resolver::resolve,
+    scanner::scan_tokens,
+    span::Span,
     tree::expression::{
         ComparisonOperation, EqualityOperation, Expression, ExpressionLiteral, FactorOperation,
         Operation, TermOperation,
     },
+    Backend,
 };
 
 use super::interpret_expression_tree;
@@ -15,12 +27,18 @@ use super::interpret_expression_tree;
 #[test]
 fn test_equality_operation() {
     let expr: Expression = Expression::Operation(Operation::Equal(EqualityOperation {
-        left: Box::new(Expression::Literal(ExpressionLiteral::True)),
-        right: Box::new(Expression::Literal(ExpressionLiteral::True)),
-        line_number: 0,
+        left: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::True,
+        )),
+        right: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::True,
+        )),
+        span: Span::default(),
     }));
 
-    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert!(result.is_ok());
 
@@ -30,12 +48,18 @@ fn test_equality_operation() {
 #[test]
 fn test_inequality_operation() {
     let expr: Expression = Expression::Operation(Operation::NotEqual(EqualityOperation {
-        left: Box::new(Expression::Literal(ExpressionLiteral::True)),
-        right: Box::new(Expression::Literal(ExpressionLiteral::True)),
-        line_number: 0,
+        left: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::True,
+        )),
+        right: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::True,
+        )),
+        span: Span::default(),
     }));
 
-    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert!(result.is_ok());
 
@@ -45,12 +69,18 @@ fn test_inequality_operation() {
 #[test]
 fn test_invalid_equality_operation() {
     let expr: Expression = Expression::Operation(Operation::Equal(EqualityOperation {
-        left: Box::new(Expression::Literal(ExpressionLiteral::Number(10.0))),
-        right: Box::new(Expression::Literal(ExpressionLiteral::True)),
-        line_number: 0,
+        left: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(10.0),
+        )),
+        right: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::True,
+        )),
+        span: Span::default(),
     }));
 
-    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert!(result.is_err());
 }
@@ -58,50 +88,57 @@ fn test_invalid_equality_operation() {
 #[test]
 fn test_invalid_inequality_operation() {
     let expr: Expression = Expression::Operation(Operation::NotEqual(EqualityOperation {
-        left: Box::new(Expression::Literal(ExpressionLiteral::Number(10.0))),
-        right: Box::new(Expression::Literal(ExpressionLiteral::True)),
-        line_number: 0,
+        left: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(10.0),
+        )),
+        right: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::True,
+        )),
+        span: Span::default(),
     }));
 
-    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert!(result.is_err());
 }
 
 #[test]
 fn test_is_number_truthy() {
-    let expr = Expression::Literal(ExpressionLiteral::Number(0.1));
+    let expr = Expression::Literal(Span::default(), ExpressionLiteral::Number(0.1));
 
-    let result = is_truthy(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = is_truthy(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert_eq!(result, Ok(true));
 }
 
 #[test]
 fn test_is_zero_falsy() {
-    let expr = Expression::Literal(ExpressionLiteral::Number(0.0));
+    let expr = Expression::Literal(Span::default(), ExpressionLiteral::Number(0.0));
 
-    let result = is_truthy(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = is_truthy(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert_eq!(result, Ok(false));
 }
 
 #[test]
 fn test_is_string_truthy() {
-    let expr = Expression::Literal(ExpressionLiteral::String(
-        "This string should be truthy!".to_owned(),
-    ));
+    let expr = Expression::Literal(
+        Span::default(),
+        ExpressionLiteral::String("This string should be truthy!".to_owned()),
+    );
 
-    let result = is_truthy(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = is_truthy(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert_eq!(result, Ok(true));
 }
 
 #[test]
 fn test_is_empty_string_falsy() {
-    let expr = Expression::Literal(ExpressionLiteral::String("".to_owned()));
+    let expr = Expression::Literal(Span::default(), ExpressionLiteral::String("".to_owned()));
 
-    let result = is_truthy(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = is_truthy(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert_eq!(result, Ok(false));
 }
@@ -109,12 +146,18 @@ fn test_is_empty_string_falsy() {
 #[test]
 fn test_plus_operation() {
     let expr: Expression = Expression::Operation(Operation::Plus(TermOperation {
-        left: Box::new(Expression::Literal(ExpressionLiteral::Number(0.1))),
-        right: Box::new(Expression::Literal(ExpressionLiteral::Number(0.2))),
-        line_number: 0,
+        left: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(0.1),
+        )),
+        right: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(0.2),
+        )),
+        span: Span::default(),
     }));
 
-    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert!(result.is_ok());
 
@@ -124,12 +167,18 @@ fn test_plus_operation() {
 #[test]
 fn test_multiply_operation() {
     let expr: Expression = Expression::Operation(Operation::Multiply(FactorOperation {
-        left: Box::new(Expression::Literal(ExpressionLiteral::Number(100.0))),
-        right: Box::new(Expression::Literal(ExpressionLiteral::Number(5.0))),
-        line_number: 0,
+        left: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(100.0),
+        )),
+        right: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(5.0),
+        )),
+        span: Span::default(),
     }));
 
-    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert!(result.is_ok());
 
@@ -139,12 +188,18 @@ fn test_multiply_operation() {
 #[test]
 fn test_less_operation() {
     let expr: Expression = Expression::Operation(Operation::Less(ComparisonOperation {
-        left: Box::new(Expression::Literal(ExpressionLiteral::Number(100.0))),
-        right: Box::new(Expression::Literal(ExpressionLiteral::Number(5.0))),
-        line_number: 0,
+        left: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(100.0),
+        )),
+        right: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(5.0),
+        )),
+        span: Span::default(),
     }));
 
-    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert!(result.is_ok());
 
@@ -154,12 +209,18 @@ fn test_less_operation() {
 #[test]
 fn test_less_operation_when_equal() {
     let expr: Expression = Expression::Operation(Operation::Less(ComparisonOperation {
-        left: Box::new(Expression::Literal(ExpressionLiteral::Number(100.0))),
-        right: Box::new(Expression::Literal(ExpressionLiteral::Number(100.0))),
-        line_number: 0,
+        left: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(100.0),
+        )),
+        right: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(100.0),
+        )),
+        span: Span::default(),
     }));
 
-    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert!(result.is_ok());
 
@@ -169,12 +230,18 @@ fn test_less_operation_when_equal() {
 #[test]
 fn test_less_equal_operation() {
     let expr: Expression = Expression::Operation(Operation::LessEqual(ComparisonOperation {
-        left: Box::new(Expression::Literal(ExpressionLiteral::Number(100.0))),
-        right: Box::new(Expression::Literal(ExpressionLiteral::Number(5.0))),
-        line_number: 0,
+        left: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(100.0),
+        )),
+        right: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(5.0),
+        )),
+        span: Span::default(),
     }));
 
-    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert!(result.is_ok());
 
@@ -184,12 +251,18 @@ fn test_less_equal_operation() {
 #[test]
 fn test_less_equal_operation_when_equal() {
     let expr: Expression = Expression::Operation(Operation::LessEqual(ComparisonOperation {
-        left: Box::new(Expression::Literal(ExpressionLiteral::Number(100.0))),
-        right: Box::new(Expression::Literal(ExpressionLiteral::Number(100.0))),
-        line_number: 0,
+        left: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(100.0),
+        )),
+        right: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(100.0),
+        )),
+        span: Span::default(),
     }));
 
-    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert!(result.is_ok());
 
@@ -200,12 +273,18 @@ fn test_less_equal_operation_when_equal() {
 #[test]
 fn test_greater_operation() {
     let expr: Expression = Expression::Operation(Operation::Greater(ComparisonOperation {
-        left: Box::new(Expression::Literal(ExpressionLiteral::Number(100.0))),
-        right: Box::new(Expression::Literal(ExpressionLiteral::Number(5.0))),
-        line_number: 0,
+        left: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(100.0),
+        )),
+        right: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(5.0),
+        )),
+        span: Span::default(),
     }));
 
-    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert!(result.is_ok());
 
@@ -215,12 +294,18 @@ fn test_greater_operation() {
 #[test]
 fn test_greater_operation_when_equal() {
     let expr: Expression = Expression::Operation(Operation::Greater(ComparisonOperation {
-        left: Box::new(Expression::Literal(ExpressionLiteral::Number(100.0))),
-        right: Box::new(Expression::Literal(ExpressionLiteral::Number(100.0))),
-        line_number: 0,
+        left: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(100.0),
+        )),
+        right: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(100.0),
+        )),
+        span: Span::default(),
     }));
 
-    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert!(result.is_ok());
 
@@ -230,12 +315,18 @@ fn test_greater_operation_when_equal() {
 #[test]
 fn test_greater_equal_operation() {
     let expr: Expression = Expression::Operation(Operation::Greater(ComparisonOperation {
-        left: Box::new(Expression::Literal(ExpressionLiteral::Number(100.0))),
-        right: Box::new(Expression::Literal(ExpressionLiteral::Number(5.0))),
-        line_number: 0,
+        left: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(100.0),
+        )),
+        right: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(5.0),
+        )),
+        span: Span::default(),
     }));
 
-    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert!(result.is_ok());
 
@@ -245,14 +336,215 @@ fn test_greater_equal_operation() {
 #[test]
 fn test_greater_equal_operation_when_equal() {
     let expr: Expression = Expression::Operation(Operation::LessEqual(ComparisonOperation {
-        left: Box::new(Expression::Literal(ExpressionLiteral::Number(100.0))),
-        right: Box::new(Expression::Literal(ExpressionLiteral::Number(100.0))),
-        line_number: 0,
+        left: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(100.0),
+        )),
+        right: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(100.0),
+        )),
+        span: Span::default(),
     }));
 
-    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), expr);
+    let result = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), &expr);
 
     assert!(result.is_ok());
 
     assert_eq!(result.unwrap(), ExpressionLiteral::True)
 }
+
+#[test]
+fn test_adding_mismatched_types_errors() {
+    let expr: Expression = Expression::Operation(Operation::Plus(TermOperation {
+        left: Box::new(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(1.0),
+        )),
+        right: Box::new(Expression::Literal(Span::default(), ExpressionLiteral::Nil)),
+        span: Span::default(),
+    }));
+
+    let error = interpret_expression_tree(Rc::new(RefCell::new(Environment::default())), &expr)
+        .unwrap_err();
+
+    assert_eq!(
+        error.kind,
+        ErrorKind::InvalidOperands {
+            operation: "add",
+            left: "1.00".to_owned(),
+            right: "nil".to_owned()
+        }
+    );
+    assert_eq!(error.code(), "E0405");
+}
+
+/// Runs a whole program, which must compile
+fn interpret_source(source: &str) -> Result<(), LoxError> {
+    interpret_source_with_limits(source, Limits::default())
+}
+
+fn interpret_source_with_limits(source: &str, limits: Limits) -> Result<(), LoxError> {
+    run_on(source, Backend::TreeWalker, limits).0
+}
+
+#[test]
+fn test_error_inside_calls_has_backtrace() {
+    let error = interpret_source(
+        "fun inner() {\n    return 1 + nil;\n}\nfun outer() {\n    inner();\n}\nouter();",
+    )
+    .unwrap_err();
+
+    assert_eq!(error.span.line, 2);
+
+    let frames: Vec<(&str, usize)> = error
+        .backtrace
+        .iter()
+        .map(|frame| (frame.function.as_str(), frame.call_span.line))
+        .collect();
+
+    assert_eq!(frames, vec![("inner", 5), ("outer", 7)]);
+}
+
+#[test]
+fn test_undeclared_variables_are_undefined_when_reached() {
+    let read =
+        interpret_source("if false {\n    print(never);\n}\nprint(misspelled);").unwrap_err();
+
+    assert_eq!(
+        read.kind,
+        ErrorKind::UndefinedVariable("misspelled".to_owned())
+    );
+    assert_eq!(read.span.line, 4);
+
+    let assigned = interpret_source("misspelled = 1;").unwrap_err();
+
+    assert_eq!(assigned.code(), "E0401");
+}
+
+#[test]
+fn test_error_outside_calls_has_no_backtrace() {
+    let error = interpret_source("fun f() {}\nf();\n1 + nil;").unwrap_err();
+
+    assert!(error.backtrace.is_empty());
+}
+
+#[test]
+fn test_runaway_recursion_is_stack_overflow() {
+    let error = interpret_source_with_limits(
+        "fun f() {\n    return f();\n}\nf();",
+        Limits {
+            max_call_depth: 10,
+            ..Limits::default()
+        },
+    )
+    .unwrap_err();
+
+    assert_eq!(error.kind, ErrorKind::StackOverflow { limit: 10 });
+    assert_eq!(error.backtrace.len(), 10);
+    assert_eq!(error.backtrace[9].call_span.line, 4);
+}
+
+#[test]
+fn test_step_limit_stops_infinite_loop() {
+    let error = interpret_source_with_limits(
+        "let i = 0;\nwhile true {\n    i = i + 1;\n}",
+        Limits {
+            max_steps: Some(100),
+            ..Limits::default()
+        },
+    )
+    .unwrap_err();
+
+    assert_eq!(error.kind, ErrorKind::StepLimitExceeded { limit: 100 });
+}
+
+#[test]
+fn test_step_limit_allows_programs_within_it() {
+    let result = interpret_source_with_limits(
+        "let a = 1;\nlet b = 2;",
+        Limits {
+            max_steps: Some(2),
+            ..Limits::default()
+        },
+    );
+
+    assert!(result.is_ok());
+}
+
+#[test]
+fn test_timeout_stops_infinite_loop() {
+    let timeout = Duration::from_millis(10);
+
+    let error = interpret_source_with_limits(
+        "while true {}",
+        Limits {
+            timeout: Some(timeout),
+            ..Limits::default()
+        },
+    )
+    .unwrap_err();
+
+    assert_eq!(error.kind, ErrorKind::TimedOut(timeout));
+    assert_eq!(error.code(), "E0415");
+}
+
+#[test]
+fn test_cancellation_from_another_thread_interrupts() {
+    let cancellation = CancellationToken::new();
+    let canceller = cancellation.clone();
+
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(10));
+
+        canceller.cancel();
+    });
+
+    // Both the loop and the statement in its body check for cancellation, so
+    // the line it stops on depends on timing and only the kind is checked
+    let error = interpret_source_with_limits(
+        "let i = 0;\nwhile true {\n    i = i + 1;\n}",
+        Limits {
+            cancellation: Some(cancellation),
+            ..Limits::default()
+        },
+    )
+    .unwrap_err();
+
+    assert_eq!(error.kind, ErrorKind::Interrupted);
+}
+
+#[test]
+fn test_functions_share_their_declaration() {
+    let steps = parse(scan_tokens("fun f() {}\nclass A {\n    fun m() {}\n}").unwrap());
+    let variable_map = resolve(&steps).unwrap().variable_map;
+
+    let mut session = Session::new(vec![]);
+
+    session
+        .run(variable_map, &steps, Limits::default())
+        .unwrap();
+
+    let StepKind::Statement(Statement::Fun(function)) = &steps[0].as_ref().unwrap().kind else {
+        panic!("expected a function declaration");
+    };
+    let StepKind::Statement(Statement::Class(class)) = &steps[1].as_ref().unwrap().kind else {
+        panic!("expected a class declaration");
+    };
+
+    // One reference from the tree and one from the function created from it
+    assert_eq!(Rc::strong_count(function), 2);
+    assert_eq!(Rc::strong_count(&class.methods[0]), 2);
+}
+
+#[test]
+fn test_functions_read_their_parameters() {
+    let (result, output) = run_on(
+        "fun fib(n) {\n    if n < 2 {\n        return n;\n    }\n    return fib(n - 1) + fib(n - 2);\n}\nprint(fib(10));",
+        Backend::TreeWalker,
+        Limits::default(),
+    );
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "55.00\n");
+}
@@ -4,93 +4,525 @@
 #[macro_use]
 pub mod logging;
 
+mod conformance;
+pub mod diagnostics;
 pub mod errors;
 pub mod interpreter;
+pub mod optimizer;
 pub mod parser;
+#[cfg(not(target_family = "wasm"))]
+pub mod repl;
 pub mod resolver;
 pub mod scanner;
+pub mod span;
+pub mod symbol;
 pub mod tests;
 pub mod tokens;
 pub mod tree;
+pub mod vm;
+pub mod wasm;
+#[cfg(not(target_family = "wasm"))]
+pub mod watch;
 
-use interpreter::interpret;
-use parser::{parse, ParsingResult};
-use resolver::{resolve, VariableMap};
-use scanner::scan_tokens;
+use std::{fmt, str::FromStr};
 
-use wasm_bindgen::prelude::*;
+use diagnostics::{report_diagnostic, Diagnostic};
+use errors::{ErrorKind, LoxError, WARNING_CODES};
+use interpreter::{interpret, limits::Limits};
+use parser::{parse, parse_continuing, parsing_errors, ParsingResult};
+use resolver::Resolution;
+use scanner::scan_tokens_from;
+use symbol::Interner;
+use tokens::Token;
+
+/// Exit codes in the style of BSD's sysexits.h
+pub mod exit_codes {
+    /// The command was used incorrectly, e.g. with the wrong number of arguments
+    pub const USAGE: i32 = 64;
+    /// The input data was incorrect, used for scanning, parsing and resolving errors
+    pub const DATA_ERROR: i32 = 65;
+    /// An input file did not exist or was not readable
+    pub const NO_INPUT: i32 = 66;
+    /// The program errored while being run
+    pub const SOFTWARE: i32 = 70;
+    /// The program was stopped by Ctrl-C, 128 plus the number of SIGINT like shells report
+    pub const INTERRUPTED: i32 = 130;
+}
+
+/// How far a source file made it through the pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    Success,
+    /// Failed during scanning, parsing or resolving, before anything was executed
+    CompileError,
+    RuntimeError,
+    /// The program stopped itself with the native `exit`
+    Exited(i32),
+    /// The program was cancelled before it finished
+    Interrupted,
+}
+
+impl RunStatus {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RunStatus::Success => 0,
+            RunStatus::CompileError => exit_codes::DATA_ERROR,
+            RunStatus::RuntimeError => exit_codes::SOFTWARE,
+            RunStatus::Exited(code) => *code,
+            RunStatus::Interrupted => exit_codes::INTERRUPTED,
+        }
+    }
+}
+
+/// How a program is executed once it has been scanned, parsed and resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Walks the syntax tree directly
+    #[default]
+    TreeWalker,
+    /// Compiles the tree to bytecode and runs it on the virtual machine in `vm`
+    Vm,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tree" => Ok(Backend::TreeWalker),
+            "vm" => Ok(Backend::Vm),
+            unknown => Err(format!(
+                "Unknown backend \"{unknown}\", expected one of tree or vm"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Backend::TreeWalker => write!(f, "tree"),
+            Backend::Vm => write!(f, "vm"),
+        }
+    }
+}
 
 /// Core function that takes in the raw source code and does stuff
 ///
-// TODO: Rework this whole function
-#[wasm_bindgen]
-pub fn run(source: &str) {
-    report!(
-        "Parsing {} characters: \n {}",
-        source.len(),
-        source.escape_default()
-    );
+/// Any errors are reported as they're found, the returned status only says
+/// which stage of the pipeline failed
+pub fn run(source: &str) -> RunStatus {
+    run_with_args(source, vec![])
+}
 
-    let tokens = scan_tokens(source).unwrap();
+/// Runs the source with `script_arguments` available to it through the global `args` list
+pub fn run_with_args(source: &str, script_arguments: Vec<String>) -> RunStatus {
+    run_with_limits(source, script_arguments, Limits::default())
+}
 
-    report_progress!("Scanned {} tokens", tokens.len());
+/// Runs the source like `run_with_args`, erroring once the program goes past any of the `limits`
+pub fn run_with_limits(source: &str, script_arguments: Vec<String>, limits: Limits) -> RunStatus {
+    run_with_backend(source, script_arguments, limits, Backend::default())
+}
 
-    let syntax_tree: Vec<ParsingResult> = parse(tokens);
+/// Runs the source like `run_with_limits`, executing it with the given backend
+pub fn run_with_backend(
+    source: &str,
+    script_arguments: Vec<String>,
+    limits: Limits,
+    backend: Backend,
+) -> RunStatus {
+    #[cfg(not(target_family = "wasm"))]
+    return with_interpreter_stack(&limits, || {
+        execute(source, script_arguments, limits.clone(), backend)
+    })
+    .unwrap_or(RunStatus::RuntimeError);
 
-    report_progress!("Parsed tokens into {} blocks", syntax_tree.len());
+    // The wasm stack size is set when linking, see .cargo/config.toml
+    #[cfg(target_family = "wasm")]
+    execute(source, script_arguments, limits, backend)
+}
 
-    // TODO: Check for errors in sub blocks
-    match syntax_tree
-        .iter()
-        .filter_map(|parsed_block| parsed_block.clone().err())
-        .collect::<Vec<_>>()
-        .as_slice()
-    {
-        [] => {}
-        errors => {
-            for error in errors {
+/// Runs `interpreter` on a thread with enough native stack for the deepest call
+/// stack allowed, since calls recurse on it
+///
+/// Returns `None` when the thread couldn't be started or panicked
+#[cfg(not(target_family = "wasm"))]
+fn with_interpreter_stack<T: Send>(
+    limits: &Limits,
+    interpreter: impl FnOnce() -> T + Send,
+) -> Option<T> {
+    std::thread::scope(|scope| {
+        let interpreter = std::thread::Builder::new()
+            .name("interpreter".to_owned())
+            .stack_size(limits.stack_size())
+            .spawn_scoped(scope, interpreter);
+
+        match interpreter {
+            Ok(interpreter) => interpreter.join().ok(),
+            Err(err) => {
                 report_error!(
-                    "Parsing error appeared at line number {} with issue: {}",
-                    error.line_number,
-                    error.message
+                    "Couldn't reserve a stack for {} calls: {}",
+                    limits.max_call_depth,
+                    err
                 );
-            }
 
-            return;
+                None
+            }
         }
-    }
+    })
+}
 
-    let resolved_variable_map: VariableMap = match resolve(syntax_tree.clone()) {
-        Ok(map) => map,
-        Err(err) => {
-            report_error!(
-                "Failed to resolve at line {} with message {}",
-                err.line_number,
-                err.message
-            );
+/// Runs every stage of the pipeline on the current thread
+fn execute(
+    source: &str,
+    script_arguments: Vec<String>,
+    limits: Limits,
+    backend: Backend,
+) -> RunStatus {
+    let _interner = Interner::new();
 
-            return;
-        }
+    let Some((mut syntax_tree, resolution)) = compile(source) else {
+        return RunStatus::CompileError;
     };
 
+    if optimizer::is_enabled() {
+        syntax_tree = optimizer::optimize(syntax_tree);
+
+        report_progress!("Optimized tree into {} blocks", syntax_tree.len());
+    }
+
     #[cfg(not(target_family = "wasm"))]
     let starting_time = std::time::Instant::now();
 
     report!("\n---- output ----");
 
-    match interpret(resolved_variable_map, syntax_tree) {
+    let result = match backend {
+        Backend::TreeWalker => interpret(
+            resolution.variable_map,
+            &syntax_tree,
+            script_arguments,
+            limits,
+        ),
+        Backend::Vm => vm::interpret(
+            resolution.variable_map,
+            &syntax_tree,
+            script_arguments,
+            limits,
+        ),
+    };
+
+    let status = report_result(result, source);
+
+    #[cfg(not(target_family = "wasm"))]
+    if status == RunStatus::Success {
+        report!("\nExecuted in {}μs", starting_time.elapsed().as_micros());
+    }
+
+    status
+}
+
+/// Reports how running the source went, turning the result into its status
+fn report_result(result: Result<(), LoxError>, source: &str) -> RunStatus {
+    match result {
         Ok(_) => {
             report!("---- program finished ----\n");
 
-            #[cfg(not(target_family = "wasm"))]
-            report!("\nExecuted in {}μs", starting_time.elapsed().as_micros())
+            RunStatus::Success
+        }
+
+        Err(LoxError {
+            kind: ErrorKind::Exit(code),
+            ..
+        }) => {
+            report!("---- program exited with code {} ----\n", code);
+
+            RunStatus::Exited(code)
+        }
+
+        Err(LoxError {
+            kind: ErrorKind::Interrupted,
+            span,
+            ..
+        }) => {
+            report_error!("Interrupted at line {}", span.line);
+
+            RunStatus::Interrupted
         }
 
         Err(err) => {
-            report!(
-                "---- program errored ----\n\nExperienced runtime error at line {} with message:\n {}",
-                err.line_number, err.message
-            )
+            report!("---- program errored ----\n");
+
+            report_diagnostic(&Diagnostic::from(&err), source);
+
+            RunStatus::RuntimeError
+        }
+    }
+}
+
+/// Runs sources one after another, each seeing the globals defined by the ones
+/// before it, like the lines of the REPL
+///
+/// Has to stay on the thread it was made on, which needs as much stack as
+/// `run_with_backend` gives the interpreter
+pub struct Session {
+    /// Every source run so far, one after another, which spans point into so
+    /// errors in functions from earlier sources are shown on the line they're on
+    source: String,
+    resolver: resolver::Session,
+    globals: Globals,
+    limits: Limits,
+    /// Keeps the names of every line alive, declared last so it's dropped last
+    _interner: Interner,
+}
+
+/// Globals kept by the backend the session runs on
+enum Globals {
+    TreeWalker(interpreter::Session),
+    Vm(vm::Session),
+}
+
+impl Session {
+    pub fn new(script_arguments: Vec<String>, limits: Limits, backend: Backend) -> Self {
+        let interner = Interner::new();
+
+        let globals = match backend {
+            Backend::TreeWalker => Globals::TreeWalker(interpreter::Session::new(script_arguments)),
+            Backend::Vm => Globals::Vm(vm::Session::new(script_arguments)),
+        };
+
+        Session {
+            source: String::new(),
+            resolver: resolver::Session::new(),
+            globals,
+            limits,
+            _interner: interner,
+        }
+    }
+
+    /// Runs the source like `run_with_backend`, keeping the globals it defines
+    ///
+    /// Globals the source declares but never got to define before erroring are
+    /// forgotten, so they can be declared again
+    pub fn run(&mut self, source: &str) -> RunStatus {
+        if !self.source.is_empty() && !self.source.ends_with('\n') {
+            self.source.push('\n');
+        }
+
+        let start = self.source.len();
+
+        self.source.push_str(source);
+
+        let Some((mut syntax_tree, resolution)) =
+            compile_after(&self.source, start, parse_continuing, &mut self.resolver)
+        else {
+            return RunStatus::CompileError;
+        };
+
+        if optimizer::is_enabled() {
+            syntax_tree = optimizer::optimize(syntax_tree);
+        }
+
+        let result = match &mut self.globals {
+            Globals::TreeWalker(globals) => {
+                globals.run(resolution.variable_map, &syntax_tree, self.limits.clone())
+            }
+            Globals::Vm(globals) => {
+                globals.run(resolution.variable_map, &syntax_tree, self.limits.clone())
+            }
+        };
+
+        if result.is_err() {
+            let defined = match &self.globals {
+                Globals::TreeWalker(globals) => globals.global_count(),
+                Globals::Vm(globals) => globals.global_count(),
+            };
+
+            self.resolver.keep_defined_globals(defined);
+        }
+
+        report_result(result, &self.source)
+    }
+}
+
+/// Which warnings `check` reports and whether they fail it
+#[derive(Debug, Clone, Default)]
+pub struct WarningOptions {
+    /// Codes of warnings that are never reported, e.g. "W0301"
+    pub allowed: Vec<String>,
+    /// Fails the check when any warning is reported
+    pub deny: bool,
+}
+
+impl WarningOptions {
+    /// Stops reporting the warning with the code, erroring for unknown codes
+    pub fn allow(&mut self, code: &str) -> Result<(), String> {
+        if !WARNING_CODES.contains(&code) {
+            return Err(format!(
+                "Unknown warning \"{code}\", expected one of {}",
+                WARNING_CODES.join(", ")
+            ));
+        }
+
+        self.allowed.push(code.to_owned());
+
+        Ok(())
+    }
+}
+
+/// Scans, parses and resolves the source without executing it
+pub fn check(source: &str) -> RunStatus {
+    check_with_warnings(source, &WarningOptions::default())
+}
+
+/// Checks the source like `check`, reporting the warnings that aren't allowed
+pub fn check_with_warnings(source: &str, options: &WarningOptions) -> RunStatus {
+    let _interner = Interner::new();
+
+    let Some((_, resolution)) = compile(source) else {
+        return RunStatus::CompileError;
+    };
+
+    let warnings: Vec<&LoxError> = resolution
+        .warnings
+        .iter()
+        .filter(|warning| !options.allowed.iter().any(|code| code == warning.code()))
+        .collect();
+
+    for warning in &warnings {
+        report_diagnostic(&Diagnostic::from(*warning), source);
+    }
+
+    if options.deny && !warnings.is_empty() {
+        report_error!("Check failed with {} warnings", warnings.len());
+
+        return RunStatus::CompileError;
+    }
+
+    RunStatus::Success
+}
+
+/// Prints every token the scanner produces for the source, one per line
+pub fn dump_tokens(source: &str) -> RunStatus {
+    let _interner = Interner::new();
+
+    let Some(tokens) = scan_source(source, 0) else {
+        return RunStatus::CompileError;
+    };
+
+    for token in tokens {
+        report_output!("{}", token);
+    }
+
+    RunStatus::Success
+}
+
+/// Pretty prints the parsed tree of the source, including any steps that failed to parse
+pub fn dump_ast(source: &str) -> RunStatus {
+    let _interner = Interner::new();
+
+    let Some(tokens) = scan_source(source, 0) else {
+        return RunStatus::CompileError;
+    };
+
+    let syntax_tree: Vec<ParsingResult> = parse(tokens);
+
+    print_steps(&syntax_tree);
+
+    if report_parsing_errors(&syntax_tree, source) {
+        return RunStatus::CompileError;
+    }
+
+    RunStatus::Success
+}
+
+/// Pretty prints the tree of the source as it's run, after resolving and optimizing it
+pub fn dump_optimized_ast(source: &str) -> RunStatus {
+    let _interner = Interner::new();
+
+    let Some((syntax_tree, _)) = compile(source) else {
+        return RunStatus::CompileError;
+    };
+
+    print_steps(&optimizer::optimize(syntax_tree));
+
+    RunStatus::Success
+}
+
+fn print_steps(steps: &[ParsingResult]) {
+    for step in steps {
+        match step {
+            Ok(step) => report_output!("{}", step),
+            Err(err) => report_output!("( error \"{}\" )", err.kind),
+        }
+    }
+}
+
+/// Scans the source from the `start` byte offset, reporting any errors found
+fn scan_source(source: &str, start: usize) -> Option<Vec<Token<'_>>> {
+    report_progress!("Parsing {} characters", source.len() - start);
+    report_trace!("{}", source[start..].escape_default());
+
+    match scan_tokens_from(source, start) {
+        Ok(tokens) => {
+            report_progress!("Scanned {} tokens", tokens.len());
+
+            Some(tokens)
+        }
+        Err(errors) => {
+            for error in errors {
+                report_diagnostic(&Diagnostic::from(&error), source);
+            }
+
+            None
+        }
+    }
+}
+
+/// Reports every parsing error in the tree, returning whether there were any
+fn report_parsing_errors(syntax_tree: &[ParsingResult], source: &str) -> bool {
+    let errors = parsing_errors(syntax_tree);
+
+    for error in &errors {
+        report_diagnostic(&Diagnostic::from(*error), source);
+    }
+
+    !errors.is_empty()
+}
+
+/// Runs every stage of the pipeline before execution, reporting any errors found
+///
+/// Warnings are left for the caller to report, most of them only matter to `check`
+fn compile(source: &str) -> Option<(Vec<ParsingResult>, Resolution)> {
+    compile_after(source, 0, parse, &mut resolver::Session::new())
+}
+
+/// Compiles the source from the `start` byte offset like `compile`, parsing it
+/// with `parse` and resolving it after whatever `resolver` resolved before
+fn compile_after(
+    source: &str,
+    start: usize,
+    parse: fn(Vec<Token>) -> Vec<ParsingResult>,
+    resolver: &mut resolver::Session,
+) -> Option<(Vec<ParsingResult>, Resolution)> {
+    let tokens = scan_source(source, start)?;
+
+    let syntax_tree: Vec<ParsingResult> = parse(tokens);
+
+    report_progress!("Parsed tokens into {} blocks", syntax_tree.len());
+
+    if report_parsing_errors(&syntax_tree, source) {
+        return None;
+    }
+
+    match resolver.resolve(&syntax_tree) {
+        Ok(resolution) => Some((syntax_tree, resolution)),
+        Err(errors) => {
+            for error in &errors {
+                report_diagnostic(&Diagnostic::from(error), source);
+            }
+
+            None
         }
     }
 }
@@ -1,3 +1,11 @@
+use std::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use wasm_bindgen::prelude::*;
+
 #[cfg(target_family = "wasm")]
 #[wasm_bindgen::prelude::wasm_bindgen(module = "/web/src/log.ts")]
 extern "C" {
@@ -5,19 +13,89 @@ extern "C" {
     pub fn pushToLog(s: &str);
 }
 
+/// How much of the pipeline's own chatter gets reported
+///
+/// Program output and errors are always reported, every level above `Quiet`
+/// adds to what the level below it prints
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// Only program output and errors
+    Quiet = 0,
+    /// Output separators and timing
+    Normal = 1,
+    /// Progress through each stage of the pipeline
+    Verbose = 2,
+    /// Everything, including the full source being run
+    Trace = 3,
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Normal as u8);
+
+#[wasm_bindgen]
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn log_level() -> LogLevel {
+    match LOG_LEVEL.load(Ordering::Relaxed) {
+        0 => LogLevel::Quiet,
+        1 => LogLevel::Normal,
+        2 => LogLevel::Verbose,
+        _ => LogLevel::Trace,
+    }
+}
+
+pub fn is_enabled(level: LogLevel) -> bool {
+    log_level() >= level
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "quiet" => Ok(LogLevel::Quiet),
+            "normal" => Ok(LogLevel::Normal),
+            "verbose" => Ok(LogLevel::Verbose),
+            "trace" => Ok(LogLevel::Trace),
+            unknown => Err(format!(
+                "Unknown log level \"{unknown}\", expected one of quiet, normal, verbose or trace"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogLevel::Quiet => write!(f, "quiet"),
+            LogLevel::Normal => write!(f, "normal"),
+            LogLevel::Verbose => write!(f, "verbose"),
+            LogLevel::Trace => write!(f, "trace"),
+        }
+    }
+}
+
 #[cfg(target_family = "wasm")]
 #[macro_export]
 macro_rules! report {
     // Note that this is using the `log` function imported above during
     // `bare_bones`
-    ($($t:tt)*) => ($crate::logging::pushToLog(&format_args!($($t)*).to_string()))
+    ($($t:tt)*) => {{
+        if $crate::logging::is_enabled($crate::logging::LogLevel::Normal) {
+            $crate::logging::pushToLog(&format_args!($($t)*).to_string())
+        }
+    }};
 }
 
 #[cfg(not(target_family = "wasm"))]
 #[macro_export]
 macro_rules! report {
     ($($arg:tt)*) => {{
-        eprintln!($($arg)*);
+        if $crate::logging::is_enabled($crate::logging::LogLevel::Normal) {
+            eprintln!($($arg)*);
+        }
     }};
 }
 
@@ -47,22 +125,83 @@ macro_rules! report_error {
 macro_rules! report_progress {
     // Note that this is using the `log` function imported above during
     // `bare_bones`
-    ($($t:tt)*) => ($crate::logging::pushToLog(&("Progress: ".to_owned() + &format_args!($($t)*).to_string())))
+    ($($t:tt)*) => {{
+        if $crate::logging::is_enabled($crate::logging::LogLevel::Verbose) {
+            $crate::logging::pushToLog(&("Progress: ".to_owned() + &format_args!($($t)*).to_string()))
+        }
+    }};
 }
 
 #[cfg(not(target_family = "wasm"))]
 #[macro_export]
 macro_rules! report_progress {
-    () => {
-        eprint!("Progress: ");
-        eprintln!($($arg)*);
-    };
     ($($arg:tt)*) => {{
-        eprint!("Progress: ");
-        eprintln!($($arg)*);
+        if $crate::logging::is_enabled($crate::logging::LogLevel::Verbose) {
+            eprint!("Progress: ");
+            eprintln!($($arg)*);
+        }
+    }};
+}
+
+#[cfg(target_family = "wasm")]
+#[macro_export]
+macro_rules! report_trace {
+    ($($t:tt)*) => {{
+        if $crate::logging::is_enabled($crate::logging::LogLevel::Trace) {
+            $crate::logging::pushToLog(&("Trace: ".to_owned() + &format_args!($($t)*).to_string()))
+        }
+    }};
+}
+
+#[cfg(not(target_family = "wasm"))]
+#[macro_export]
+macro_rules! report_trace {
+    ($($arg:tt)*) => {{
+        if $crate::logging::is_enabled($crate::logging::LogLevel::Trace) {
+            eprint!("Trace: ");
+            eprintln!($($arg)*);
+        }
+    }};
+}
+
+/// Reports output the user asked for, such as a token or tree dump, at every log level
+#[cfg(target_family = "wasm")]
+#[macro_export]
+macro_rules! report_output {
+    ($($t:tt)*) => ($crate::logging::pushToLog(&format_args!($($t)*).to_string()))
+}
+
+/// Reports output the user asked for, such as a token or tree dump, at every log level
+#[cfg(not(target_family = "wasm"))]
+#[macro_export]
+macro_rules! report_output {
+    ($($arg:tt)*) => {{
+        // Ignore failed writes so piping into something like `head` doesn't panic
+        let _ = std::io::Write::write_fmt(&mut std::io::stdout(), format_args!("{}\n", format_args!($($arg)*)));
     }};
 }
 
 pub use report;
 pub use report_error;
+pub use report_output;
 pub use report_progress;
+pub use report_trace;
+
+#[cfg(test)]
+mod tests {
+    use super::LogLevel;
+
+    #[test]
+    fn log_levels_are_ordered_by_verbosity() {
+        assert!(LogLevel::Quiet < LogLevel::Normal);
+        assert!(LogLevel::Normal < LogLevel::Verbose);
+        assert!(LogLevel::Verbose < LogLevel::Trace);
+    }
+
+    #[test]
+    fn log_level_can_be_parsed() {
+        assert_eq!("quiet".parse(), Ok(LogLevel::Quiet));
+        assert_eq!("trace".parse(), Ok(LogLevel::Trace));
+        assert!("loud".parse::<LogLevel>().is_err());
+    }
+}
@@ -1,38 +1,258 @@
-#[allow(unused_imports)]
-use rust_lox;
+#[cfg(not(target_family = "wasm"))]
+use std::{process::ExitCode, time::Duration};
 
 #[cfg(not(target_family = "wasm"))]
-fn main() {
-    use rust_lox::run;
+use rust_lox::exit_codes;
+
+#[cfg(not(target_family = "wasm"))]
+const USAGE: &str = "\
+Usage: rust-lox [options] [command] [file] [arguments...]
+
+Commands:
+  run <file>     Run a file, the default when no command is given
+  check <file>   Scan, parse and resolve a file without running it
+  tokens <file>  Print the tokens scanned from a file
+  ast <file>     Print the tree parsed from a file, or the tree that's run with --optimized
+  eval <code>    Run the given code
+  repl           Read and run code a line at a time
+
+Files can be given as - to read from stdin. Runs ./demo.lox when no file is given.
+Ctrl-C stops the running program, pressing it again quits straight away.
+Any arguments following the file are passed to the script in the global `args` list.
+
+Options:
+  -q, --quiet            Only print program output and errors
+  -v, --verbose          Print progress through each stage of the pipeline
+      --trace            Print everything, including the source being run
+      --log-level=LEVEL  One of quiet, normal, verbose or trace
+  -w, --watch            Re-run the file every time it's saved
+      --max-call-depth=N Error once more than N calls are in progress, defaults to 1000
+      --max-steps=N      Error once N statements, calls and loop iterations have run
+      --timeout=MS       Error once the program has run for MS milliseconds
+      --backend=BACKEND  Run with tree, the tree walker, or vm, the bytecode virtual machine
+      --allow=CODES      Don't report the comma separated warning codes when checking
+      --deny-warnings    Fail checking when any warnings are reported
+      --no-opt           Run the tree as parsed, without folding constants or dropping dead code
+      --optimized        Print the optimized tree with the ast command
+  -h, --help             Print this message";
+
+#[cfg(not(target_family = "wasm"))]
+#[derive(PartialEq)]
+enum Command {
+    Run,
+    Check,
+    Tokens,
+    Ast,
+    Eval,
+    Repl,
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn main() -> ExitCode {
+    use rust_lox::{
+        check_with_warnings, dump_ast, dump_optimized_ast, dump_tokens,
+        interpreter::limits::{CancellationToken, Limits},
+        logging::{set_log_level, LogLevel},
+        optimizer,
+        repl::repl,
+        report_progress, run_with_backend,
+        watch::watch,
+        Backend, WarningOptions,
+    };
+
+    let mut args = std::env::args().skip(1);
+
+    let mut command: Option<Command> = None;
+    let mut target: Option<String> = None;
+    let mut watching = false;
+    let mut limits = Limits::default();
+    let mut backend = Backend::default();
+    let mut warning_options = WarningOptions::default();
+    let mut optimized_ast = false;
+
+    // Options and the command are only read up until the target, everything after
+    // belongs to the script
+    for arg in args.by_ref() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{USAGE}");
+
+                return ExitCode::SUCCESS;
+            }
+            "-q" | "--quiet" => set_log_level(LogLevel::Quiet),
+            "-v" | "--verbose" => set_log_level(LogLevel::Verbose),
+            "--trace" => set_log_level(LogLevel::Trace),
+            "-w" | "--watch" => watching = true,
+            "--deny-warnings" => warning_options.deny = true,
+            "--no-opt" => optimizer::set_enabled(false),
+            "--optimized" => optimized_ast = true,
+            flag if flag.starts_with("--log-level=") => {
+                match flag.trim_start_matches("--log-level=").parse::<LogLevel>() {
+                    Ok(level) => set_log_level(level),
+                    Err(message) => return usage_error(&message),
+                }
+            }
+            flag if flag.starts_with("--max-call-depth=") => {
+                match flag
+                    .trim_start_matches("--max-call-depth=")
+                    .parse::<usize>()
+                {
+                    Ok(max_call_depth) => limits.max_call_depth = max_call_depth,
+                    Err(_) => return usage_error(&format!("Invalid call depth in {flag}")),
+                }
+            }
+            flag if flag.starts_with("--max-steps=") => {
+                match flag.trim_start_matches("--max-steps=").parse::<u64>() {
+                    Ok(max_steps) => limits.max_steps = Some(max_steps),
+                    Err(_) => return usage_error(&format!("Invalid step count in {flag}")),
+                }
+            }
+            flag if flag.starts_with("--timeout=") => {
+                match flag.trim_start_matches("--timeout=").parse::<u64>() {
+                    Ok(timeout) => limits.timeout = Some(Duration::from_millis(timeout)),
+                    Err(_) => return usage_error(&format!("Invalid timeout in {flag}")),
+                }
+            }
+            flag if flag.starts_with("--backend=") => {
+                match flag.trim_start_matches("--backend=").parse::<Backend>() {
+                    Ok(selected) => backend = selected,
+                    Err(message) => return usage_error(&message),
+                }
+            }
+            flag if flag.starts_with("--allow=") => {
+                for code in flag.trim_start_matches("--allow=").split(',') {
+                    if let Err(message) = warning_options.allow(code) {
+                        return usage_error(&message);
+                    }
+                }
+            }
+            flag if flag.starts_with('-') && flag != "-" => {
+                return usage_error(&format!("Unknown option {flag}"));
+            }
+            "run" if command.is_none() => command = Some(Command::Run),
+            "check" if command.is_none() => command = Some(Command::Check),
+            "tokens" if command.is_none() => command = Some(Command::Tokens),
+            "ast" if command.is_none() => command = Some(Command::Ast),
+            "eval" if command.is_none() => command = Some(Command::Eval),
+            "repl" if command.is_none() => command = Some(Command::Repl),
+            _ => {
+                target = Some(arg);
+
+                break;
+            }
+        }
+    }
+
+    let script_arguments: Vec<String> = args.collect();
+    let command = command.unwrap_or(Command::Run);
+
+    if command != Command::Run && command != Command::Eval && !script_arguments.is_empty() {
+        return usage_error("Only run and eval accept arguments following the file");
+    }
 
-    let args: Vec<String> = std::env::args().collect();
+    if optimized_ast && command != Command::Ast {
+        return usage_error("Only ast can print the optimized tree");
+    }
 
-    let file_name: Result<&str, &str> = match args.as_slice() {
-        [] => unreachable!("Will always have at least the path of the executable"),
+    if watching {
+        return match (command, target) {
+            (Command::Run, Some(file_name)) if file_name != "-" => {
+                watch(&file_name, script_arguments, limits, backend)
+            }
+            _ => usage_error("Only files given to run can be watched"),
+        };
+    }
 
-        // TODO: Use the run function to create a REPL
-        [_] => Ok("./demo.lox"),
+    if matches!(command, Command::Run | Command::Eval | Command::Repl) {
+        let cancellation = CancellationToken::new();
+        let handler_cancellation = cancellation.clone();
 
-        [_, file_name] => {
-            println!("Target file: {}", file_name);
+        // The program is asked to stop first, in case it's stuck somewhere that never checks
+        let handler = ctrlc::set_handler(move || {
+            if handler_cancellation.is_cancelled() {
+                std::process::exit(exit_codes::INTERRUPTED);
+            }
+
+            handler_cancellation.cancel();
+        });
+
+        if handler.is_ok() {
+            limits.cancellation = Some(cancellation);
+        }
+    }
 
-            Ok(file_name.as_str())
+    if command == Command::Repl {
+        if target.is_some() {
+            return usage_error("repl doesn't take a file");
         }
 
-        _too_many => Err(""),
+        return ExitCode::from(repl(limits, backend).exit_code() as u8);
+    }
+
+    let source_file: String = match (&command, target) {
+        (Command::Eval, Some(code)) => code,
+        (Command::Eval, None) => {
+            return usage_error("eval expects an argument containing the code to run")
+        }
+
+        (_, None) => match read_source("./demo.lox") {
+            Ok(source) => source,
+            Err(exit_code) => return exit_code,
+        },
+
+        (_, Some(file_name)) => {
+            report_progress!("Target file: {}", file_name);
+
+            match read_source(&file_name) {
+                Ok(source) => source,
+                Err(exit_code) => return exit_code,
+            }
+        }
     };
 
-    let source_file: String = read_file(file_name.unwrap());
+    let status = match command {
+        Command::Run | Command::Eval => {
+            run_with_backend(&source_file, script_arguments, limits, backend)
+        }
+        Command::Check => check_with_warnings(&source_file, &warning_options),
+        Command::Tokens => dump_tokens(&source_file),
+        Command::Ast if optimized_ast => dump_optimized_ast(&source_file),
+        Command::Ast => dump_ast(&source_file),
+        Command::Repl => unreachable!("the repl reads its own source"),
+    };
 
-    run(&source_file);
+    ExitCode::from(status.exit_code() as u8)
 }
 
-#[cfg(target_family = "wasm")]
-fn main() {
-    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+/// Reads the whole source file, treating "-" as stdin
+#[cfg(not(target_family = "wasm"))]
+fn read_source(file_name: &str) -> Result<String, ExitCode> {
+    use std::io::Read;
+
+    let source = if file_name == "-" {
+        let mut source = String::new();
+
+        std::io::stdin().read_to_string(&mut source).map(|_| source)
+    } else {
+        std::fs::read_to_string(file_name)
+    };
+
+    source.map_err(|err| {
+        rust_lox::report_error!("Couldn't read {}: {}", file_name, err);
+
+        ExitCode::from(exit_codes::NO_INPUT as u8)
+    })
 }
 
 #[cfg(not(target_family = "wasm"))]
-fn read_file(file_name: &str) -> String {
-    std::fs::read_to_string(file_name).expect("File name is invalid")
+fn usage_error(message: &str) -> ExitCode {
+    rust_lox::report_error!("{}", message);
+    eprintln!("\n{USAGE}");
+
+    ExitCode::from(exit_codes::USAGE as u8)
+}
+
+#[cfg(target_family = "wasm")]
+fn main() {
+    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
 }
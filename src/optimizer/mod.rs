@@ -0,0 +1,112 @@
+//! Simplifies the resolved tree before it's run, folding operations on literals
+//! into the literal they evaluate to and dropping code that can never run
+//!
+//! Node IDs are left untouched so the resolver's variable map still applies, and
+//! every folded literal keeps the span of the operation it replaced
+
+mod tests;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    interpreter::{evaluate_constant, is_literal_truthy},
+    parser::{
+        statements::{IfStatement, Statement},
+        ParsedStep, ParsingResult, StepKind,
+    },
+    tree::{
+        expression::Expression,
+        visit::{walk_expression_mut, walk_steps_mut, MutVisitor},
+    },
+};
+
+static OPTIMIZE: AtomicBool = AtomicBool::new(true);
+
+/// Turns the pass off for every program run afterwards, to compare against the
+/// unoptimized tree
+pub fn set_enabled(enabled: bool) {
+    OPTIMIZE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    OPTIMIZE.load(Ordering::Relaxed)
+}
+
+pub fn optimize(mut steps: Vec<ParsingResult>) -> Vec<ParsingResult> {
+    Optimizer.visit_steps(&mut steps);
+
+    steps
+}
+
+struct Optimizer;
+
+impl MutVisitor for Optimizer {
+    /// Steps are simplified after their children, so a branch chosen by a
+    /// constant condition has already been optimized when it takes its place
+    fn visit_steps(&mut self, steps: &mut Vec<ParsingResult>) {
+        walk_steps_mut(self, steps);
+
+        *steps = std::mem::take(steps)
+            .into_iter()
+            .filter_map(|step| match step {
+                Ok(step) => simplify_step(step).map(Ok),
+                Err(err) => Some(Err(err)),
+            })
+            .collect();
+    }
+
+    /// Folds every operation on literals within the expression, innermost first
+    ///
+    /// Operations that would error are left as they are, so the error is still
+    /// raised when the program reaches them
+    fn visit_expression(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression);
+
+        match expression {
+            Expression::Grouping(_, grouped) => {
+                if let Expression::Literal(span, literal) = grouped.as_ref() {
+                    *expression = Expression::Literal(*span, literal.clone());
+                }
+            }
+            Expression::Operation(operation) => {
+                if !operation
+                    .operands()
+                    .iter()
+                    .all(|operand| matches!(operand, Expression::Literal(_, _)))
+                {
+                    return;
+                }
+
+                if let Ok(value) = evaluate_constant(expression) {
+                    *expression = Expression::Literal(expression.span(), value);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Simplifies an optimized step, returning `None` when it would do nothing at all
+///
+/// Only steps within a list are simplified, the bodies of functions, loops and
+/// `if` statements stay blocks even when they end up empty
+fn simplify_step(step: ParsedStep) -> Option<ParsedStep> {
+    match step.kind {
+        StepKind::Block(steps) if steps.is_empty() => None,
+        // The chosen branch is a block either way, so it still gets the
+        // environment its variables were resolved in
+        StepKind::Statement(Statement::If(IfStatement {
+            condition: Expression::Literal(_, literal),
+            then_statement,
+            else_statement,
+            ..
+        })) => {
+            if is_literal_truthy(&literal) {
+                simplify_step(*then_statement)
+            } else {
+                else_statement.and_then(|else_statement| simplify_step(*else_statement))
+            }
+        }
+        kind => Some(ParsedStep { kind, ..step }),
+    }
+}
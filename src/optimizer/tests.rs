@@ -0,0 +1,99 @@
+#![cfg(test)]
+
+use crate::{
+    conformance::run_prepared_on_both,
+    errors::LoxError,
+    parser::{parse, ParsingResult},
+    scanner::scan_tokens,
+};
+
+use super::optimize;
+
+fn parse_source(source: &str) -> Vec<ParsingResult> {
+    parse(scan_tokens(source).unwrap())
+}
+
+fn print_tree(steps: &[ParsingResult]) -> String {
+    steps
+        .iter()
+        .map(|step| step.as_ref().unwrap().to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Optimizes the source, checking it prints the same tree as `expected` does unoptimized
+fn assert_optimizes_to(source: &str, expected: &str) {
+    assert_eq!(
+        print_tree(&optimize(parse_source(source))),
+        print_tree(&parse_source(expected))
+    );
+}
+
+/// Resolves and optimizes the source, then runs it on both backends, which must agree
+fn run_optimized(source: &str) -> (Result<(), LoxError>, String) {
+    run_prepared_on_both(source, optimize)
+}
+
+#[test]
+fn test_folds_arithmetic_and_groupings() {
+    assert_optimizes_to("print((1 + 2) * 4 / 2);", "print(6);");
+}
+
+#[test]
+fn test_folds_comparisons_and_not() {
+    assert_optimizes_to(
+        "let a = !(1 < 2);\nlet b = 3 >= 3 and \"x\" == \"x\";",
+        "let a = false;\nlet b = true;",
+    );
+}
+
+#[test]
+fn test_folds_string_concatenation() {
+    assert_optimizes_to("print(\"a\" + \"b\" + \"c\");", "print(\"abc\");");
+}
+
+#[test]
+fn test_leaves_operations_on_variables() {
+    assert_optimizes_to(
+        "let a = 1;\nprint(a + (2 + 3));",
+        "let a = 1;\nprint(a + 5);",
+    );
+}
+
+#[test]
+fn test_keeps_invalid_operations_and_their_line() {
+    let source = "let a = 1;\nprint(a);\nprint((1 + 2) + nil);";
+
+    assert_optimizes_to(source, "let a = 1;\nprint(a);\nprint(3 + nil);");
+
+    let (result, output) = run_optimized(source);
+
+    assert_eq!(output, "1.00\n");
+    assert_eq!(result.unwrap_err().span.line, 3);
+}
+
+#[test]
+fn test_drops_branches_with_constant_conditions() {
+    assert_optimizes_to(
+        "if 1 < 2 { print(1); } else { print(2); }\nif !true { print(3); }\nif \"\" { print(4); } else { print(5); }",
+        "{ print(1); }\n{ print(5); }",
+    );
+}
+
+#[test]
+fn test_removes_empty_blocks_but_keeps_bodies() {
+    assert_optimizes_to(
+        "{}\n{ {} }\nfun f() {}\nwhile false {}",
+        "fun f() {}\nwhile false {}",
+    );
+}
+
+#[test]
+fn test_variables_resolve_after_optimizing() {
+    let (result, output) = run_optimized(
+        "let a = 1;\nif 2 > 1 {\n    let b = a + 1;\n    print(b);\n}\nfun f(n) {\n    if false {\n        let unused = 0;\n    }\n    return n * (2 + 3);\n}\nprint(f(a));",
+    );
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "2.00\n5.00\n");
+}
@@ -0,0 +1,103 @@
+use core::fmt;
+use std::fmt::{Display, Formatter};
+
+use super::{
+    statements::{FunStatement, Statement},
+    ParsedStep, StepKind,
+};
+
+const INDENT: &str = "    ";
+
+impl Display for ParsedStep {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+impl ParsedStep {
+    /// Writes the step assuming the current line has already been indented to `depth`
+    fn fmt_indented(&self, f: &mut Formatter, depth: usize) -> fmt::Result {
+        match &self.kind {
+            StepKind::Expression(expression) => write!(f, "{}", expression),
+            StepKind::Statement(statement) => statement.fmt_indented(f, depth),
+            StepKind::Block(steps) => {
+                writeln!(f, "{{")?;
+
+                for step in steps {
+                    write!(f, "{}", INDENT.repeat(depth + 1))?;
+
+                    match step {
+                        Ok(step) => step.fmt_indented(f, depth + 1)?,
+                        Err(err) => write!(f, "( error \"{}\" )", err.kind)?,
+                    }
+
+                    writeln!(f)?;
+                }
+
+                write!(f, "{}}}", INDENT.repeat(depth))
+            }
+        }
+    }
+}
+
+impl Statement {
+    /// Writes the statement assuming the current line has already been indented to `depth`
+    fn fmt_indented(&self, f: &mut Formatter, depth: usize) -> fmt::Result {
+        match self {
+            Statement::Variable(_span, name, value) => write!(f, "( let {} = {} )", name, value),
+            Statement::If(if_statement) => {
+                write!(f, "( if {} ", if_statement.condition)?;
+                if_statement.then_statement.fmt_indented(f, depth)?;
+
+                if let Some(else_statement) = &if_statement.else_statement {
+                    write!(f, " else ")?;
+                    else_statement.fmt_indented(f, depth)?;
+                }
+
+                write!(f, " )")
+            }
+            Statement::While(while_statement) => {
+                write!(f, "( while {} ", while_statement.condition)?;
+                while_statement.body.fmt_indented(f, depth)?;
+                write!(f, " )")
+            }
+            Statement::Fun(function) => function.fmt_indented(f, depth, "fun"),
+            Statement::Return(_span, Some(value)) => write!(f, "( return {} )", value),
+            Statement::Return(_span, None) => write!(f, "( return )"),
+            Statement::Class(class) => {
+                writeln!(f, "( class {} {{", class.name)?;
+
+                for method in &class.methods {
+                    write!(f, "{}", INDENT.repeat(depth + 1))?;
+                    method.fmt_indented(f, depth + 1, "method")?;
+                    writeln!(f)?;
+                }
+
+                write!(f, "{}}} )", INDENT.repeat(depth))
+            }
+        }
+    }
+}
+
+impl FunStatement {
+    fn fmt_indented(&self, f: &mut Formatter, depth: usize, keyword: &str) -> fmt::Result {
+        write!(
+            f,
+            "( {keyword} {} ( {} ) ",
+            self.name,
+            self.parameters
+                .iter()
+                .map(|parameter| parameter.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )?;
+        self.body.fmt_indented(f, depth)?;
+        write!(f, " )")
+    }
+}
@@ -1,29 +1,75 @@
+use std::convert::Infallible;
+
 use rules::declaration;
 use statements::Statement;
 
 use crate::{
-    errors::ParsingError,
+    errors::LoxError,
+    span::Span,
     tokens::{Token, TokenType},
-    tree::expression::Expression,
+    tree::{
+        expression::{Expression, NodeId},
+        visit::Visitor,
+    },
 };
 
+mod display;
 pub mod rules;
 pub mod statements;
 pub mod tests;
 mod util;
 
+/// A single step of the program, the span is that of its expression or
+/// statement, or the opening brace of a block
+#[derive(Debug, Clone)]
+pub struct ParsedStep {
+    pub id: NodeId,
+    pub span: Span,
+    pub kind: StepKind,
+}
+
 #[derive(Debug, Clone)]
-pub enum ParsedStep {
+pub enum StepKind {
     Expression(Expression),
     Statement(Statement),
     Block(Vec<ParsingResult>),
 }
 
-pub type TokenIter<'a> = std::iter::Peekable<std::slice::Iter<'a, Token>>;
-pub type ParsingResult = Result<ParsedStep, ParsingError>;
-type ExpressionParsingResult = Result<Expression, ParsingError>;
+impl ParsedStep {
+    pub fn expression(expression: Expression) -> Self {
+        ParsedStep::new(expression.span(), StepKind::Expression(expression))
+    }
+
+    pub fn statement(statement: Statement) -> Self {
+        ParsedStep::new(statement.span(), StepKind::Statement(statement))
+    }
+
+    pub fn block(opening_brace: Span, steps: Vec<ParsingResult>) -> Self {
+        ParsedStep::new(opening_brace, StepKind::Block(steps))
+    }
+
+    fn new(span: Span, kind: StepKind) -> Self {
+        ParsedStep {
+            id: util::next_node_id(),
+            span,
+            kind,
+        }
+    }
+}
+
+pub type TokenIter<'a> = std::iter::Peekable<std::slice::Iter<'a, Token<'a>>>;
+pub type ParsingResult = Result<ParsedStep, LoxError>;
+type ExpressionParsingResult = Result<Expression, LoxError>;
 
 pub fn parse(tokens_vec: Vec<Token>) -> Vec<ParsingResult> {
+    util::start_node_ids();
+
+    parse_continuing(tokens_vec)
+}
+
+/// Parses like `parse`, numbering nodes on from the last parse on this thread, so
+/// the trees of several parses can share a variable map like the lines of the REPL
+pub fn parse_continuing(tokens_vec: Vec<Token>) -> Vec<ParsingResult> {
     let mut tokens: TokenIter = tokens_vec.iter().peekable();
 
     top_parse_steps(&mut tokens)
@@ -32,8 +78,11 @@ pub fn parse(tokens_vec: Vec<Token>) -> Vec<ParsingResult> {
 fn top_parse_steps(tokens: &mut TokenIter) -> Vec<ParsingResult> {
     let mut return_vector: Vec<ParsingResult> = vec![];
 
-    while let Some(_) = tokens.peek() {
-        return_vector.push(declaration(tokens));
+    while tokens
+        .peek()
+        .is_some_and(|token| token.token_type != TokenType::Eof)
+    {
+        return_vector.push(recovering_declaration(tokens, false));
     }
 
     return_vector
@@ -43,12 +92,86 @@ fn parse_steps(tokens: &mut TokenIter) -> Vec<ParsingResult> {
     let mut return_vector: Vec<ParsingResult> = vec![];
 
     while let Some(token) = tokens.peek() {
-        if token.token_type == TokenType::RightBrace {
+        if token.token_type == TokenType::RightBrace || token.token_type == TokenType::Eof {
             break;
         }
 
-        return_vector.push(declaration(tokens));
+        return_vector.push(recovering_declaration(tokens, true));
     }
 
     return_vector
 }
+
+/// Parses a declaration, skipping to the next statement boundary when it fails
+/// so that the errors following it still get reported
+fn recovering_declaration(tokens: &mut TokenIter, in_block: bool) -> ParsingResult {
+    let remaining_tokens = tokens.len();
+
+    let step = declaration(tokens);
+
+    if step.is_err() {
+        synchronize(tokens, in_block);
+
+        // Always move forward, otherwise the same error would be found forever
+        if tokens.len() == remaining_tokens {
+            tokens.next();
+        }
+    }
+
+    step
+}
+
+/// Skips tokens until just after a semicolon or right before a token that starts
+/// a new statement, closes the block being parsed or ends the file
+///
+/// Outside of a block a `}` has nothing to close, so it's skipped over too
+fn synchronize(tokens: &mut TokenIter, in_block: bool) {
+    while let Some(token) = tokens.peek() {
+        match token.token_type {
+            TokenType::Semicolon => {
+                tokens.next();
+
+                return;
+            }
+            TokenType::RightBrace if in_block => return,
+            TokenType::Class
+            | TokenType::Fun
+            | TokenType::Let
+            | TokenType::If
+            | TokenType::While
+            | TokenType::Return
+            | TokenType::Eof => return,
+            _ => {
+                tokens.next();
+            }
+        }
+    }
+}
+
+/// Every error in the tree, including those nested in blocks, functions and classes
+pub fn parsing_errors(syntax_tree: &[ParsingResult]) -> Vec<&LoxError> {
+    let mut collector = ErrorCollector { errors: vec![] };
+
+    let Ok(()) = collector.visit_steps(syntax_tree);
+
+    collector.errors
+}
+
+struct ErrorCollector<'ast> {
+    errors: Vec<&'ast LoxError>,
+}
+
+impl<'ast> Visitor<'ast> for ErrorCollector<'ast> {
+    type Error = Infallible;
+
+    fn visit_parsing_error(&mut self, error: &'ast LoxError) -> Result<(), Infallible> {
+        self.errors.push(error);
+
+        Ok(())
+    }
+
+    // Only steps can fail to parse, so there's no need to look inside expressions
+    fn visit_expression(&mut self, _expression: &'ast Expression) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
@@ -1,6 +1,8 @@
 use statements::variable_statement;
 
-use crate::errors::ParsingError;
+use crate::errors::{ErrorKind, LoxError};
+use crate::span::Span;
+use crate::symbol::Symbol;
 use crate::tokens::TokenType;
 use crate::tree::expression::{
     ComparisonOperation, EqualityOperation, Expression, ExpressionLiteral, ExpressionVariable,
@@ -11,18 +13,16 @@ use super::statements::{
     class_declaration_statement, function_declaration_statement, if_statement, return_statement,
     while_statement,
 };
-use super::util::{consume_expected_character, parse_call_arguments};
+use super::util::{
+    consume_expected_character, consume_identifier, next_node_id, parse_call_arguments, peek_token,
+    NestingGuard,
+};
 use super::{
     parse_steps, statements, ExpressionParsingResult, ParsedStep, ParsingResult, TokenIter,
 };
 
 pub fn declaration(tokens: &mut TokenIter) -> ParsingResult {
-    let token = tokens.peek();
-
-    match token
-        .expect("There should always be another character in declaration")
-        .token_type
-    {
+    match peek_token(tokens)?.token_type {
         TokenType::Let => variable_statement(tokens),
         TokenType::Fun => function_declaration_statement(tokens),
         TokenType::Class => class_declaration_statement(tokens),
@@ -31,9 +31,7 @@ pub fn declaration(tokens: &mut TokenIter) -> ParsingResult {
 }
 
 pub fn statement(tokens: &mut TokenIter) -> ParsingResult {
-    let token = tokens.peek();
-
-    match token.unwrap().token_type {
+    match peek_token(tokens)?.token_type {
         TokenType::LeftBrace => block(tokens),
         TokenType::If => if_statement(tokens),
         TokenType::While => while_statement(tokens),
@@ -44,19 +42,21 @@ pub fn statement(tokens: &mut TokenIter) -> ParsingResult {
 
             consume_expected_character(tokens, TokenType::Semicolon)?;
 
-            Ok(ParsedStep::Expression(expr))
+            Ok(ParsedStep::expression(expr))
         }
     }
 }
 
 pub fn block(tokens: &mut TokenIter) -> ParsingResult {
-    consume_expected_character(tokens, TokenType::LeftBrace)?;
+    let opening_brace = consume_expected_character(tokens, TokenType::LeftBrace)?;
+
+    let _nesting = NestingGuard::enter(opening_brace.span)?;
 
     let block_steps = parse_steps(tokens);
 
     consume_expected_character(tokens, TokenType::RightBrace)?;
 
-    Ok(ParsedStep::Block(block_steps))
+    Ok(ParsedStep::block(opening_brace.span, block_steps))
 }
 
 pub fn expression(tokens: &mut TokenIter) -> ExpressionParsingResult {
@@ -71,6 +71,8 @@ pub fn assignment(tokens: &mut TokenIter) -> ExpressionParsingResult {
             // consume equals
             tokens.next();
 
+            let _nesting = NestingGuard::enter(token.span)?;
+
             let right_side = assignment(tokens)?;
 
             match left_side {
@@ -78,17 +80,16 @@ pub fn assignment(tokens: &mut TokenIter) -> ExpressionParsingResult {
                     expression_variable,
                     Box::new(right_side),
                 )),
-                Expression::Get(line_number, expr, property_identifier) => Ok(Expression::Set(
-                    line_number,
+                Expression::Get(span, expr, property_identifier) => Ok(Expression::Set(
+                    span,
                     expr,
                     property_identifier,
                     Box::new(right_side),
                 )),
-                _ => Err(ParsingError {
-                    line_number: token.line_number,
-                    message: "expected left side of assignment operator to be identifier"
-                        .to_string(),
-                }),
+                _ => Err(LoxError::new(
+                    ErrorKind::InvalidAssignmentTarget,
+                    token.span,
+                )),
             }
         }
         _ => Ok(left_side),
@@ -106,7 +107,7 @@ pub fn logical_or(tokens: &mut TokenIter) -> ExpressionParsingResult {
                 expr = Expression::Operation(Operation::Or(LogicalOperation {
                     left: Box::new(expr),
                     right: Box::new(logical_and(tokens)?),
-                    line_number: token.line_number,
+                    span: token.span,
                 }))
             }
             _ => break,
@@ -127,7 +128,7 @@ pub fn logical_and(tokens: &mut TokenIter) -> ExpressionParsingResult {
                 expr = Expression::Operation(Operation::And(LogicalOperation {
                     left: Box::new(expr),
                     right: Box::new(equality(tokens)?),
-                    line_number: token.line_number,
+                    span: token.span,
                 }))
             }
             _ => break,
@@ -137,190 +138,95 @@ pub fn logical_and(tokens: &mut TokenIter) -> ExpressionParsingResult {
     Ok(expr)
 }
 
-pub fn equality(tokens: &mut TokenIter) -> ExpressionParsingResult {
-    let mut expression = comparison(tokens)?;
-
-    let new_equality_operation = |expression: Expression,
-                                  line_number: usize,
-                                  tokens: &mut TokenIter|
-     -> Result<EqualityOperation, ParsingError> {
-        Ok(EqualityOperation {
-            left: Box::new(expression),
-            right: Box::new(equality(tokens)?),
-            line_number,
-        })
-    };
+/// Builds the operation between two operands, given the span of its operator
+type BinaryOperation = fn(Box<Expression>, Box<Expression>, Span) -> Operation;
+
+/// Parses operands separated by operators of the same precedence, e.g. `a + b - c`
+///
+/// The chain is read in a loop, so it doesn't count towards the nesting limit
+/// however long it is, and then grouped to the right like `a + (b - c)`
+fn binary_chain(
+    tokens: &mut TokenIter,
+    operand: fn(&mut TokenIter) -> ExpressionParsingResult,
+    operator: fn(&TokenType) -> Option<BinaryOperation>,
+) -> ExpressionParsingResult {
+    let mut operands = vec![operand(tokens)?];
+    let mut operators = vec![];
+
+    while let Some(&token) = tokens.peek() {
+        let Some(operation) = operator(&token.token_type) else {
+            break;
+        };
 
-    loop {
-        // Look at the next token, if it is a equality
-        match tokens.peek() {
-            Some(&next_token) if next_token.token_type == TokenType::BangEqual => {
-                tokens.next();
-
-                expression = Expression::Operation(Operation::NotEqual(new_equality_operation(
-                    expression,
-                    next_token.line_number,
-                    tokens,
-                )?))
-            }
+        tokens.next();
 
-            Some(&next_token) if next_token.token_type == TokenType::EqualEqual => {
-                tokens.next();
-                expression = Expression::Operation(Operation::Equal(new_equality_operation(
-                    expression,
-                    next_token.line_number,
-                    tokens,
-                )?))
-            }
-            _ => break,
-        }
+        operators.push((operation, token.span));
+        operands.push(operand(tokens)?);
     }
 
-    Ok(expression)
-}
+    let mut expression = operands.pop().expect("there's always a first operand");
 
-pub fn comparison(tokens: &mut TokenIter) -> ExpressionParsingResult {
-    let mut expression = term(tokens)?;
-
-    let new_comparison_operation = |expression: Expression,
-                                    line_number: usize,
-                                    tokens: &mut TokenIter|
-     -> Result<ComparisonOperation, ParsingError> {
-        Ok(ComparisonOperation {
-            left: Box::new(expression),
-            right: Box::new(comparison(tokens)?),
-            line_number,
-        })
-    };
+    while let Some((operation, span)) = operators.pop() {
+        let left = operands.pop().expect("every operator follows an operand");
 
-    loop {
-        // Look at the next token, if it is a equality
-        expression = match tokens.peek() {
-            Some(&next_token) if next_token.token_type == TokenType::Greater => {
-                tokens.next();
-
-                Expression::Operation(Operation::Greater(new_comparison_operation(
-                    expression,
-                    next_token.line_number,
-                    tokens,
-                )?))
-            }
-
-            Some(&next_token) if next_token.token_type == TokenType::GreaterEqual => {
-                tokens.next();
-
-                Expression::Operation(Operation::GreaterEqual(new_comparison_operation(
-                    expression,
-                    next_token.line_number,
-                    tokens,
-                )?))
-            }
-
-            Some(&next_token) if next_token.token_type == TokenType::Less => {
-                tokens.next();
-
-                Expression::Operation(Operation::Less(new_comparison_operation(
-                    expression,
-                    next_token.line_number,
-                    tokens,
-                )?))
-            }
-
-            Some(&next_token) if next_token.token_type == TokenType::LessEqual => {
-                tokens.next();
-
-                Expression::Operation(Operation::LessEqual(new_comparison_operation(
-                    expression,
-                    next_token.line_number,
-                    tokens,
-                )?))
-            }
-
-            _ => break,
-        }
+        expression = Expression::Operation(operation(Box::new(left), Box::new(expression), span));
     }
 
     Ok(expression)
 }
 
-pub fn term(tokens: &mut TokenIter) -> ExpressionParsingResult {
-    let mut expression = factor(tokens)?;
-
-    let new_term_operation = |expression: Expression,
-                              line_number,
-                              tokens: &mut TokenIter|
-     -> Result<TermOperation, ParsingError> {
-        Ok(TermOperation {
-            left: Box::new(expression),
-            right: Box::new(term(tokens)?),
-            line_number,
-        })
-    };
+pub fn equality(tokens: &mut TokenIter) -> ExpressionParsingResult {
+    binary_chain(tokens, comparison, |token_type| match token_type {
+        TokenType::BangEqual => {
+            Some(|left, right, span| Operation::NotEqual(EqualityOperation { left, right, span }))
+        }
+        TokenType::EqualEqual => {
+            Some(|left, right, span| Operation::Equal(EqualityOperation { left, right, span }))
+        }
+        _ => None,
+    })
+}
 
-    loop {
-        // Look at the next token, if it is a equality
-        expression = match tokens.peek() {
-            Some(&next_token) if next_token.token_type == TokenType::Plus => {
-                tokens.next();
-                Expression::Operation(Operation::Plus(new_term_operation(
-                    expression,
-                    next_token.line_number,
-                    tokens,
-                )?))
-            }
-            Some(&next_token) if next_token.token_type == TokenType::Minus => {
-                tokens.next();
-                Expression::Operation(Operation::Minus(new_term_operation(
-                    expression,
-                    next_token.line_number,
-                    tokens,
-                )?))
-            }
-            _ => break,
+pub fn comparison(tokens: &mut TokenIter) -> ExpressionParsingResult {
+    binary_chain(tokens, term, |token_type| match token_type {
+        TokenType::Greater => {
+            Some(|left, right, span| Operation::Greater(ComparisonOperation { left, right, span }))
         }
-    }
+        TokenType::GreaterEqual => Some(|left, right, span| {
+            Operation::GreaterEqual(ComparisonOperation { left, right, span })
+        }),
+        TokenType::Less => {
+            Some(|left, right, span| Operation::Less(ComparisonOperation { left, right, span }))
+        }
+        TokenType::LessEqual => Some(|left, right, span| {
+            Operation::LessEqual(ComparisonOperation { left, right, span })
+        }),
+        _ => None,
+    })
+}
 
-    Ok(expression)
+pub fn term(tokens: &mut TokenIter) -> ExpressionParsingResult {
+    binary_chain(tokens, factor, |token_type| match token_type {
+        TokenType::Plus => {
+            Some(|left, right, span| Operation::Plus(TermOperation { left, right, span }))
+        }
+        TokenType::Minus => {
+            Some(|left, right, span| Operation::Minus(TermOperation { left, right, span }))
+        }
+        _ => None,
+    })
 }
 
 pub fn factor(tokens: &mut TokenIter) -> ExpressionParsingResult {
-    let mut expression = unary(tokens)?;
-
-    let new_factor_operation = |expression: Expression,
-                                line_number: usize,
-                                tokens: &mut TokenIter|
-     -> Result<FactorOperation, ParsingError> {
-        Ok(FactorOperation {
-            left: Box::new(expression),
-            right: Box::new(factor(tokens)?),
-            line_number, // TODO:
-        })
-    };
-
-    loop {
-        // Look at the next token, if it is a equality
-        expression = match tokens.peek() {
-            Some(&next_token) if next_token.token_type == TokenType::Star => {
-                tokens.next();
-                Expression::Operation(Operation::Multiply(new_factor_operation(
-                    expression,
-                    next_token.line_number,
-                    tokens,
-                )?))
-            }
-            Some(&next_token) if next_token.token_type == TokenType::Slash => {
-                tokens.next();
-                Expression::Operation(Operation::Divide(new_factor_operation(
-                    expression,
-                    next_token.line_number,
-                    tokens,
-                )?))
-            }
-            _ => break,
-        };
-    }
-
-    Ok(expression)
+    binary_chain(tokens, unary, |token_type| match token_type {
+        TokenType::Star => {
+            Some(|left, right, span| Operation::Multiply(FactorOperation { left, right, span }))
+        }
+        TokenType::Slash => {
+            Some(|left, right, span| Operation::Divide(FactorOperation { left, right, span }))
+        }
+        _ => None,
+    })
 }
 
 pub fn unary(tokens: &mut TokenIter) -> ExpressionParsingResult {
@@ -328,9 +234,11 @@ pub fn unary(tokens: &mut TokenIter) -> ExpressionParsingResult {
         Some(&next_token) if next_token.token_type == TokenType::Bang => {
             tokens.next();
 
+            let _nesting = NestingGuard::enter(next_token.span)?;
+
             Ok(Expression::Operation(Operation::Not(UnaryOperation {
                 operand: Box::new(unary(tokens)?),
-                line_number: next_token.line_number,
+                span: next_token.span,
             })))
         }
         _ => Ok(call(tokens)?),
@@ -343,26 +251,22 @@ pub fn call(tokens: &mut TokenIter) -> ExpressionParsingResult {
     loop {
         match tokens.peek() {
             Some(&token) if token.token_type == TokenType::LeftParen => {
-                let arguments = parse_call_arguments(tokens)?;
+                // Only the arguments nest, each call of a chain like `f()()` follows the last
+                let arguments = {
+                    let _nesting = NestingGuard::enter(token.span)?;
+
+                    parse_call_arguments(tokens)?
+                };
 
-                expression = Expression::Call(token.line_number, Box::from(expression), arguments);
+                expression = Expression::Call(token.span, Box::from(expression), arguments);
             }
 
             Some(&token) if token.token_type == TokenType::Dot => {
                 consume_expected_character(tokens, TokenType::Dot)?;
 
-                if let TokenType::Identifier(identifier) = &tokens.next().unwrap().token_type {
-                    expression = Expression::Get(
-                        token.line_number,
-                        Box::from(expression),
-                        identifier.clone(),
-                    );
-                } else {
-                    return Err(ParsingError {
-                        line_number: token.line_number,
-                        message: "Expected identifier following dot".to_string(),
-                    });
-                };
+                let (_, identifier) = consume_identifier(tokens, "property name following dot")?;
+
+                expression = Expression::Get(token.span, Box::from(expression), identifier);
             }
 
             _ => break,
@@ -373,36 +277,72 @@ pub fn call(tokens: &mut TokenIter) -> ExpressionParsingResult {
 }
 
 pub fn primary(tokens: &mut TokenIter) -> ExpressionParsingResult {
-    let token = tokens.next().unwrap();
+    let Some(token) = tokens.next_if(|token| {
+        matches!(
+            token.token_type,
+            TokenType::True
+                | TokenType::False
+                | TokenType::Nil
+                | TokenType::String(_)
+                | TokenType::Number(_)
+                | TokenType::LeftParen
+                | TokenType::This
+                | TokenType::Identifier(_)
+        )
+    }) else {
+        // Left in place so synchronizing can stop at it, e.g. the `;` in `let x = ;`
+        let unrecognized = peek_token(tokens)?;
+
+        return Err(LoxError::new(
+            ErrorKind::ExpectedExpression(unrecognized.token_type.clone()),
+            unrecognized.span,
+        ));
+    };
 
     match &token.token_type {
-        TokenType::True => Ok(Expression::Literal(ExpressionLiteral::True)),
-        TokenType::False => Ok(Expression::Literal(ExpressionLiteral::False)),
-        TokenType::Nil => Ok(Expression::Literal(ExpressionLiteral::Nil)),
+        TokenType::True => Ok(Expression::Literal(token.span, ExpressionLiteral::True)),
+        TokenType::False => Ok(Expression::Literal(token.span, ExpressionLiteral::False)),
+        TokenType::Nil => Ok(Expression::Literal(token.span, ExpressionLiteral::Nil)),
 
         // TODO There's probably better ways of passing this string literal without cloning it
-        TokenType::String(str) => Ok(Expression::Literal(ExpressionLiteral::String(str.clone()))),
+        TokenType::String(str) => Ok(Expression::Literal(
+            token.span,
+            ExpressionLiteral::String(str.clone()),
+        )),
 
-        TokenType::Number(number) => Ok(Expression::Literal(ExpressionLiteral::Number(*number))),
+        TokenType::Number(number) => Ok(Expression::Literal(
+            token.span,
+            ExpressionLiteral::Number(*number),
+        )),
 
         TokenType::LeftParen => {
+            let _nesting = NestingGuard::enter(token.span)?;
+
             let expr = expression(tokens)?;
 
-            consume_expected_character(tokens, TokenType::RightParen)?;
+            let closing_parenthesis = consume_expected_character(tokens, TokenType::RightParen)?;
 
-            Ok(Expression::Grouping(Box::from(expr)))
+            Ok(Expression::Grouping(
+                token.span.to(closing_parenthesis.span),
+                Box::from(expr),
+            ))
         }
 
-        TokenType::This => Ok(Expression::This),
+        TokenType::This => Ok(Expression::This(ExpressionVariable {
+            id: next_node_id(),
+            span: token.span,
+            identifier_name: Symbol::intern("this"),
+        })),
 
         TokenType::Identifier(identifier_name) => Ok(Expression::Variable(ExpressionVariable {
-            line_number: token.line_number,
-            identifier_name: identifier_name.clone(),
+            id: next_node_id(),
+            span: token.span,
+            identifier_name: *identifier_name,
         })),
 
-        unrecognized_type => Err(ParsingError {
-            line_number: token.line_number,
-            message: format!("Unrecognized token: \"{:?}\"", *unrecognized_type).to_string(),
-        }),
+        unrecognized_type => Err(LoxError::new(
+            ErrorKind::ExpectedExpression(unrecognized_type.clone()),
+            token.span,
+        )),
     }
 }
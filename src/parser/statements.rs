@@ -1,40 +1,66 @@
-use std::vec;
+use std::{rc::Rc, vec};
 
-use crate::errors::ParsingError;
+use crate::errors::LoxError;
+use crate::span::Span;
+use crate::symbol::Symbol;
 use crate::tokens::TokenType;
 use crate::tree::expression::Expression;
 
 use super::{
     rules::{block, expression},
-    util::consume_expected_character,
+    util::{consume_expected_character, consume_identifier, expected_token_error, peek_token},
     ParsedStep, ParsingResult, TokenIter,
 };
 
+/// Each statement carries the span of the token that identifies it, the name
+/// for declarations and the keyword for everything else
 #[derive(Debug, Clone)]
 pub enum Statement {
-    Variable(String, Expression),
+    Variable(Span, Symbol, Expression),
     If(IfStatement),
     While(WhileStatement),
-    Fun(FunStatement),
-    Return(Option<Expression>),
+    /// Shared with the functions created from it, which run its body in place
+    Fun(Rc<FunStatement>),
+    Return(Span, Option<Expression>),
     Class(ClassStatement),
 }
 
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Variable(span, _, _) | Statement::Return(span, _) => *span,
+            Statement::If(if_statement) => if_statement.span,
+            Statement::While(while_statement) => while_statement.span,
+            Statement::Fun(function) => function.span,
+            Statement::Class(class) => class.span,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FunStatement {
-    pub name: String,
-    pub parameters: Vec<String>,
+    pub span: Span,
+    pub name: Symbol,
+    pub parameters: Vec<Parameter>,
     pub body: Box<ParsedStep>,
 }
 
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub span: Span,
+    pub name: Symbol,
+}
+
 #[derive(Debug, Clone)]
 pub struct ClassStatement {
-    pub name: String,
-    pub methods: Vec<FunStatement>,
+    pub span: Span,
+    pub name: Symbol,
+    pub methods: Vec<Rc<FunStatement>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct IfStatement {
+    pub span: Span,
     pub condition: Expression,
     pub then_statement: Box<ParsedStep>,
     pub else_statement: Option<Box<ParsedStep>>,
@@ -42,6 +68,7 @@ pub struct IfStatement {
 
 #[derive(Debug, Clone)]
 pub struct WhileStatement {
+    pub span: Span,
     pub condition: Expression,
     pub body: Box<ParsedStep>,
 }
@@ -50,108 +77,89 @@ pub fn variable_statement(tokens: &mut TokenIter) -> ParsingResult {
     // consume "let"
     tokens.next();
 
-    let identifier_token = tokens.next().unwrap();
-
-    let identifier_name = match &identifier_token.token_type {
-        TokenType::Identifier(token_identifier_name) => token_identifier_name,
-        unrecognized_identifier => {
-            return Err(ParsingError {
-                line_number: identifier_token.line_number,
-                message: format!(
-                    "Expected identifier following \"let\", found {:?}",
-                    unrecognized_identifier
-                ),
-            })
-        }
-    };
+    let (identifier_span, identifier_name) = consume_identifier(tokens, "variable name")?;
 
     consume_expected_character(tokens, TokenType::Equal)?;
 
     let value = expression(tokens)?;
 
-    tokens
-        .next_if(|value| value.token_type == TokenType::Semicolon)
-        .expect("expected semicolon following variable declaration");
+    consume_expected_character(tokens, TokenType::Semicolon)?;
 
-    Ok(ParsedStep::Statement(Statement::Variable(
-        identifier_name.clone(),
+    Ok(ParsedStep::statement(Statement::Variable(
+        identifier_span,
+        identifier_name,
         value,
     )))
 }
 
 pub fn function_declaration_statement(tokens: &mut TokenIter) -> ParsingResult {
+    Ok(ParsedStep::statement(Statement::Fun(Rc::new(function(
+        tokens,
+    )?))))
+}
+
+/// Parses a function declaration, shared by top level functions and class methods
+fn function(tokens: &mut TokenIter) -> Result<FunStatement, LoxError> {
     consume_expected_character(tokens, TokenType::Fun)?;
 
-    let function_identifier = tokens.next().unwrap();
-    let function_name = match &function_identifier.token_type {
-        TokenType::Identifier(name) => name.clone(),
-        unknown => {
-            return Err(ParsingError {
-                line_number: function_identifier.line_number,
-                message: format!("Expected function name, found {:?}", unknown),
-            })
-        }
-    };
+    let (function_span, function_name) = consume_identifier(tokens, "function name")?;
 
     consume_expected_character(tokens, TokenType::LeftParen)?;
 
-    let mut parameters: Vec<String> = vec![];
+    let mut parameters: Vec<Parameter> = vec![];
 
-    loop {
-        match &tokens.next().unwrap().token_type {
-            TokenType::Identifier(parameter_name) => parameters.push(parameter_name.clone()),
-            TokenType::RightParen => break,
-            unknown => {
-                return Err(ParsingError {
-                    line_number: function_identifier.line_number,
-                    message: format!("Expected function parameter, found {:?}", unknown),
-                })
-            }
-        };
-
-        match tokens.next() {
-            Some(token) if token.token_type == TokenType::Comma => continue,
-            Some(token) if token.token_type == TokenType::RightParen => break,
-            Some(unrecognized_token) => {
-                return Err(ParsingError {
-                    line_number: unrecognized_token.line_number,
-                    message: format!(
-                        "Expected comma delimiting another parameter or closing parenthesis, found {:?}",
-                        unrecognized_token.token_type
-                    ),
-                })
+    if tokens
+        .next_if(|token| token.token_type == TokenType::RightParen)
+        .is_none()
+    {
+        loop {
+            let (parameter_span, parameter_name) =
+                consume_identifier(tokens, "function parameter")?;
+
+            parameters.push(Parameter {
+                span: parameter_span,
+                name: parameter_name,
+            });
+
+            let delimiter = peek_token(tokens)?;
+
+            match delimiter.token_type {
+                TokenType::Comma => {
+                    tokens.next();
+                }
+                TokenType::RightParen => {
+                    tokens.next();
+
+                    break;
+                }
+                _ => {
+                    return Err(expected_token_error(
+                        vec![TokenType::Comma, TokenType::RightParen],
+                        delimiter,
+                    ))
+                }
             }
-
-            None => unimplemented!(),
         }
     }
 
     let body = Box::new(block(tokens)?);
 
-    Ok(ParsedStep::Statement(Statement::Fun(FunStatement {
+    Ok(FunStatement {
+        span: function_span,
         name: function_name,
         parameters,
         body,
-    })))
+    })
 }
 
 pub fn class_declaration_statement(tokens: &mut TokenIter) -> ParsingResult {
     consume_expected_character(tokens, TokenType::Class)?;
 
-    let class_identifier = tokens.next().unwrap();
-    let class_name = match &class_identifier.token_type {
-        TokenType::Identifier(name) => name.clone(),
-        unknown => {
-            return Err(ParsingError {
-                line_number: class_identifier.line_number,
-                message: format!("Expected class name, found {:?}", unknown),
-            })
-        }
-    };
+    let (class_span, class_name) = consume_identifier(tokens, "class name")?;
 
     consume_expected_character(tokens, TokenType::LeftBrace)?;
 
-    let mut methods: Vec<FunStatement> = Vec::new();
+    let mut methods: Vec<Rc<FunStatement>> = Vec::new();
 
     loop {
         if tokens
@@ -161,22 +169,20 @@ pub fn class_declaration_statement(tokens: &mut TokenIter) -> ParsingResult {
             break;
         }
 
-        match function_declaration_statement(tokens)? {
-            ParsedStep::Statement(Statement::Fun(function)) => methods.push(function),
-            _ => unreachable!(),
-        }
+        methods.push(Rc::new(function(tokens)?));
     }
 
     consume_expected_character(tokens, TokenType::RightBrace)?;
 
-    Ok(ParsedStep::Statement(Statement::Class(ClassStatement {
+    Ok(ParsedStep::statement(Statement::Class(ClassStatement {
+        span: class_span,
         name: class_name,
         methods,
     })))
 }
 
 pub fn if_statement(tokens: &mut TokenIter) -> ParsingResult {
-    consume_expected_character(tokens, TokenType::If)?;
+    let keyword = consume_expected_character(tokens, TokenType::If)?;
 
     let condition = expression(tokens)?;
 
@@ -195,7 +201,8 @@ pub fn if_statement(tokens: &mut TokenIter) -> ParsingResult {
         None
     };
 
-    Ok(ParsedStep::Statement(Statement::If(IfStatement {
+    Ok(ParsedStep::statement(Statement::If(IfStatement {
+        span: keyword.span,
         condition,
         then_statement,
         else_statement,
@@ -203,20 +210,21 @@ pub fn if_statement(tokens: &mut TokenIter) -> ParsingResult {
 }
 
 pub fn while_statement(tokens: &mut TokenIter) -> ParsingResult {
-    consume_expected_character(tokens, TokenType::While)?;
+    let keyword = consume_expected_character(tokens, TokenType::While)?;
 
     let condition = expression(tokens)?;
 
     let body = Box::new(block(tokens)?);
 
-    Ok(ParsedStep::Statement(Statement::While(WhileStatement {
+    Ok(ParsedStep::statement(Statement::While(WhileStatement {
+        span: keyword.span,
         condition,
         body,
     })))
 }
 
 pub fn return_statement(tokens: &mut TokenIter) -> ParsingResult {
-    consume_expected_character(tokens, TokenType::Return)?;
+    let keyword = consume_expected_character(tokens, TokenType::Return)?;
 
     if tokens
         .peek()
@@ -224,12 +232,15 @@ pub fn return_statement(tokens: &mut TokenIter) -> ParsingResult {
     {
         consume_expected_character(tokens, TokenType::Semicolon)?;
 
-        return Ok(ParsedStep::Statement(Statement::Return(None)));
+        return Ok(ParsedStep::statement(Statement::Return(keyword.span, None)));
     }
 
     let expr = expression(tokens)?;
 
     consume_expected_character(tokens, TokenType::Semicolon)?;
 
-    Ok(ParsedStep::Statement(Statement::Return(Some(expr))))
+    Ok(ParsedStep::statement(Statement::Return(
+        keyword.span,
+        Some(expr),
+    )))
 }
@@ -1,41 +1,53 @@
 #![cfg(test)]
 
 use crate::{
+    errors::ErrorKind,
     parser::{
         rules::{factor, primary, unary},
+        util::start_node_ids,
         TokenIter,
     },
     scanner::scan_tokens,
+    span::Span,
+    symbol::Symbol,
     tokens::{Token, TokenType},
     tree::expression::{
         ComparisonOperation, EqualityOperation, Expression, ExpressionLiteral, ExpressionVariable,
-        FactorOperation, Operation, TermOperation, UnaryOperation,
+        FactorOperation, NodeId, Operation, TermOperation, UnaryOperation,
     },
 };
 
 use super::rules::{comparison, equality, expression, term};
 
+/// Span of the characters between `start` and `end` on the first line
+fn span(start: usize, end: usize) -> Span {
+    Span::new(start, end, 1, start + 1)
+}
+
 #[test]
 fn test_primary_parse_false_token() {
     let tokens_vec = [Token {
         token_type: TokenType::False,
-        lexeme: "".to_string(),
-        line_number: 1,
+        lexeme: "false",
+        span: span(0, 5),
     }];
 
     let mut tokens: TokenIter = tokens_vec.iter().peekable();
 
     let result = primary(&mut tokens);
 
-    assert_eq!(result, Ok(Expression::Literal(ExpressionLiteral::False)))
+    assert_eq!(
+        result,
+        Ok(Expression::Literal(span(0, 5), ExpressionLiteral::False))
+    )
 }
 
 #[test]
 fn test_primary_parse_string_token() {
     let tokens_vec = [Token {
         token_type: TokenType::String("Inside string".to_string()),
-        lexeme: "".to_string(),
-        line_number: 1,
+        lexeme: "\"Inside string\"",
+        span: span(0, 15),
     }];
 
     let mut tokens: TokenIter = tokens_vec.iter().peekable();
@@ -44,9 +56,10 @@ fn test_primary_parse_string_token() {
 
     assert_eq!(
         result,
-        Ok(Expression::Literal(ExpressionLiteral::String(
-            "Inside string".to_string()
-        )))
+        Ok(Expression::Literal(
+            span(0, 15),
+            ExpressionLiteral::String("Inside string".to_string())
+        ))
     )
 }
 
@@ -54,8 +67,8 @@ fn test_primary_parse_string_token() {
 fn test_primary_parse_number_token() {
     let tokens_vec = [Token {
         token_type: TokenType::Number(10.0),
-        lexeme: "".to_string(),
-        line_number: 1,
+        lexeme: "10",
+        span: span(0, 2),
     }];
 
     let mut tokens: TokenIter = tokens_vec.iter().peekable();
@@ -64,22 +77,27 @@ fn test_primary_parse_number_token() {
 
     assert_eq!(
         result,
-        Ok(Expression::Literal(ExpressionLiteral::Number(10.0)))
+        Ok(Expression::Literal(
+            span(0, 2),
+            ExpressionLiteral::Number(10.0)
+        ))
     )
 }
 
 #[test]
 fn test_unary_parse_simple_bang() {
-    let tokens_vec = [Token {
+    let tokens_vec = [
+        Token {
             token_type: TokenType::Bang,
-            lexeme: "".to_string(),
-            line_number: 1,
+            lexeme: "!",
+            span: span(0, 1),
         },
         Token {
             token_type: TokenType::True,
-            lexeme: "".to_string(),
-            line_number: 1,
-        }];
+            lexeme: "true",
+            span: span(1, 5),
+        },
+    ];
 
     let mut tokens: TokenIter = tokens_vec.iter().peekable();
 
@@ -88,29 +106,31 @@ fn test_unary_parse_simple_bang() {
     assert_eq!(
         result,
         Ok(Expression::Operation(Operation::Not(UnaryOperation {
-            operand: (Box::new(Expression::Literal(ExpressionLiteral::True))),
-            line_number: 1
+            operand: (Box::new(Expression::Literal(span(1, 5), ExpressionLiteral::True))),
+            span: span(0, 1)
         })))
     );
 }
 
 #[test]
 fn test_unary_parse_multiple_bang() {
-    let tokens_vec = [Token {
+    let tokens_vec = [
+        Token {
             token_type: TokenType::Bang,
-            lexeme: "".to_string(),
-            line_number: 1,
+            lexeme: "!",
+            span: span(0, 1),
         },
         Token {
             token_type: TokenType::Bang,
-            lexeme: "".to_string(),
-            line_number: 1,
+            lexeme: "!",
+            span: span(1, 2),
         },
         Token {
             token_type: TokenType::True,
-            lexeme: "".to_string(),
-            line_number: 1,
-        }];
+            lexeme: "true",
+            span: span(2, 6),
+        },
+    ];
 
     let mut tokens: TokenIter = tokens_vec.iter().peekable();
 
@@ -120,31 +140,33 @@ fn test_unary_parse_multiple_bang() {
         result,
         Ok(Expression::Operation(Operation::Not(UnaryOperation {
             operand: Box::new(Expression::Operation(Operation::Not(UnaryOperation {
-                operand: (Box::new(Expression::Literal(ExpressionLiteral::True))),
-                line_number: 1
+                operand: (Box::new(Expression::Literal(span(2, 6), ExpressionLiteral::True))),
+                span: span(1, 2)
             }))),
-            line_number: 1
+            span: span(0, 1)
         })))
     );
 }
 
 #[test]
 fn test_factor_parse_simple_multiplication() {
-    let tokens_vec = [Token {
+    let tokens_vec = [
+        Token {
             token_type: TokenType::Number(10.0),
-            lexeme: "".to_string(),
-            line_number: 1,
+            lexeme: "10",
+            span: span(0, 2),
         },
         Token {
             token_type: TokenType::Star,
-            lexeme: "".to_string(),
-            line_number: 1,
+            lexeme: "*",
+            span: span(3, 4),
         },
         Token {
             token_type: TokenType::Number(4.0),
-            lexeme: "".to_string(),
-            line_number: 1,
-        }];
+            lexeme: "4",
+            span: span(5, 6),
+        },
+    ];
 
     let mut tokens: TokenIter = tokens_vec.iter().peekable();
 
@@ -154,9 +176,15 @@ fn test_factor_parse_simple_multiplication() {
         result,
         Ok(Expression::Operation(Operation::Multiply(
             FactorOperation {
-                left: (Box::new(Expression::Literal(ExpressionLiteral::Number(10.0)))),
-                right: (Box::new(Expression::Literal(ExpressionLiteral::Number(4.0)))),
-                line_number: 1
+                left: (Box::new(Expression::Literal(
+                    span(0, 2),
+                    ExpressionLiteral::Number(10.0)
+                ))),
+                right: (Box::new(Expression::Literal(
+                    span(5, 6),
+                    ExpressionLiteral::Number(4.0)
+                ))),
+                span: span(3, 4)
             }
         )))
     );
@@ -172,15 +200,24 @@ fn test_factor_parse_multiple_multiplication() {
         result,
         Ok(Expression::Operation(Operation::Multiply(
             FactorOperation {
-                left: (Box::new(Expression::Literal(ExpressionLiteral::Number(10.0)))),
+                left: (Box::new(Expression::Literal(
+                    span(0, 2),
+                    ExpressionLiteral::Number(10.0)
+                ))),
                 right: (Box::new(Expression::Operation(Operation::Multiply(
                     FactorOperation {
-                        left: (Box::new(Expression::Literal(ExpressionLiteral::Number(4.0)))),
-                        right: (Box::new(Expression::Literal(ExpressionLiteral::Number(3.0)))),
-                        line_number: 1
+                        left: (Box::new(Expression::Literal(
+                            span(5, 6),
+                            ExpressionLiteral::Number(4.0)
+                        ))),
+                        right: (Box::new(Expression::Literal(
+                            span(9, 10),
+                            ExpressionLiteral::Number(3.0)
+                        ))),
+                        span: span(7, 8)
                     }
                 )))),
-                line_number: 1
+                span: span(3, 4)
             }
         )))
     );
@@ -195,9 +232,15 @@ fn test_term_parse_simple_addition() {
     assert_eq!(
         result,
         Ok(Expression::Operation(Operation::Plus(TermOperation {
-            left: (Box::new(Expression::Literal(ExpressionLiteral::Number(10.0)))),
-            right: (Box::new(Expression::Literal(ExpressionLiteral::Number(4.0)))),
-            line_number: 1
+            left: (Box::new(Expression::Literal(
+                span(0, 2),
+                ExpressionLiteral::Number(10.0)
+            ))),
+            right: (Box::new(Expression::Literal(
+                span(5, 6),
+                ExpressionLiteral::Number(4.0)
+            ))),
+            span: span(3, 4)
         })))
     );
 }
@@ -213,13 +256,22 @@ fn test_term_parse_nested_addition() {
         Ok(Expression::Operation(Operation::Plus(TermOperation {
             left: (Box::new(Expression::Operation(Operation::Multiply(
                 FactorOperation {
-                    left: Box::new(Expression::Literal(ExpressionLiteral::Number(10.0))),
-                    right: (Box::new(Expression::Literal(ExpressionLiteral::Number(4.0)))),
-                    line_number: 1
+                    left: Box::new(Expression::Literal(
+                        span(0, 2),
+                        ExpressionLiteral::Number(10.0)
+                    )),
+                    right: (Box::new(Expression::Literal(
+                        span(5, 6),
+                        ExpressionLiteral::Number(4.0)
+                    ))),
+                    span: span(3, 4)
                 }
             )))),
-            right: (Box::new(Expression::Literal(ExpressionLiteral::Number(3.0)))),
-            line_number: 1
+            right: (Box::new(Expression::Literal(
+                span(9, 10),
+                ExpressionLiteral::Number(3.0)
+            ))),
+            span: span(7, 8)
         })))
     );
 }
@@ -234,19 +286,31 @@ fn test_comparison_parse_nested_comparison() {
         result,
         Ok(Expression::Operation(Operation::Greater(
             ComparisonOperation {
-                left: (Box::new(Expression::Literal(ExpressionLiteral::Number(5.0)))),
+                left: (Box::new(Expression::Literal(
+                    span(0, 1),
+                    ExpressionLiteral::Number(5.0)
+                ))),
                 right: (Box::new(Expression::Operation(Operation::Greater(
                     ComparisonOperation {
-                        left: Box::new(Expression::Literal(ExpressionLiteral::Number(4.0))),
+                        left: Box::new(Expression::Literal(
+                            span(4, 5),
+                            ExpressionLiteral::Number(4.0)
+                        )),
                         right: Box::new(Expression::Operation(Operation::Plus(TermOperation {
-                            left: Box::new(Expression::Literal(ExpressionLiteral::Number(3.0))),
-                            right: Box::new(Expression::Literal(ExpressionLiteral::Number(2.0))),
-                            line_number: 1
+                            left: Box::new(Expression::Literal(
+                                span(8, 9),
+                                ExpressionLiteral::Number(3.0)
+                            )),
+                            right: Box::new(Expression::Literal(
+                                span(12, 13),
+                                ExpressionLiteral::Number(2.0)
+                            )),
+                            span: span(10, 11)
                         }))),
-                        line_number: 1
+                        span: span(6, 7)
                     }
                 )))),
-                line_number: 1
+                span: span(2, 3)
             }
         )))
     );
@@ -261,9 +325,9 @@ fn test_equality_parse_simple_equality() {
     assert_eq!(
         result,
         Ok(Expression::Operation(Operation::Equal(EqualityOperation {
-            left: (Box::new(Expression::Literal(ExpressionLiteral::True))),
-            right: (Box::new(Expression::Literal(ExpressionLiteral::False))),
-            line_number: 1
+            left: (Box::new(Expression::Literal(span(0, 4), ExpressionLiteral::True))),
+            right: (Box::new(Expression::Literal(span(8, 13), ExpressionLiteral::False))),
+            span: span(5, 7)
         })))
     );
 }
@@ -281,35 +345,59 @@ fn test_equality_parse_nested_equality() {
                 ComparisonOperation {
                     left: Box::new(Expression::Operation(Operation::Multiply(
                         FactorOperation {
-                            left: Box::new(Expression::Literal(ExpressionLiteral::Number(4.0))),
-                            right: Box::new(Expression::Literal(ExpressionLiteral::Number(3.0))),
-                            line_number: 1
+                            left: Box::new(Expression::Literal(
+                                span(0, 1),
+                                ExpressionLiteral::Number(4.0)
+                            )),
+                            right: Box::new(Expression::Literal(
+                                span(4, 5),
+                                ExpressionLiteral::Number(3.0)
+                            )),
+                            span: span(2, 3)
                         },
                     ))),
                     right: Box::new(Expression::Operation(Operation::Plus(TermOperation {
-                        left: Box::new(Expression::Literal(ExpressionLiteral::Number(4.0))),
-                        right: Box::new(Expression::Literal(ExpressionLiteral::Number(3.0))),
-                        line_number: 1
+                        left: Box::new(Expression::Literal(
+                            span(8, 9),
+                            ExpressionLiteral::Number(4.0)
+                        )),
+                        right: Box::new(Expression::Literal(
+                            span(12, 13),
+                            ExpressionLiteral::Number(3.0)
+                        )),
+                        span: span(10, 11)
                     }))),
-                    line_number: 1
+                    span: span(6, 7)
                 }
             ))),
             right: Box::new(Expression::Operation(Operation::Less(
                 ComparisonOperation {
                     left: Box::new(Expression::Operation(Operation::Divide(FactorOperation {
-                        left: Box::new(Expression::Literal(ExpressionLiteral::Number(2.0))),
-                        right: Box::new(Expression::Literal(ExpressionLiteral::Number(4.0))),
-                        line_number: 1
+                        left: Box::new(Expression::Literal(
+                            span(17, 18),
+                            ExpressionLiteral::Number(2.0)
+                        )),
+                        right: Box::new(Expression::Literal(
+                            span(21, 22),
+                            ExpressionLiteral::Number(4.0)
+                        )),
+                        span: span(19, 20)
                     }))),
                     right: Box::new(Expression::Operation(Operation::Divide(FactorOperation {
-                        left: Box::new(Expression::Literal(ExpressionLiteral::Number(3.0))),
-                        right: Box::new(Expression::Literal(ExpressionLiteral::Number(4.0))),
-                        line_number: 1
+                        left: Box::new(Expression::Literal(
+                            span(25, 26),
+                            ExpressionLiteral::Number(3.0)
+                        )),
+                        right: Box::new(Expression::Literal(
+                            span(29, 30),
+                            ExpressionLiteral::Number(4.0)
+                        )),
+                        span: span(27, 28)
                     }))),
-                    line_number: 1
+                    span: span(23, 24)
                 }
             ))),
-            line_number: 1
+            span: span(14, 16)
         })))
     );
 }
@@ -324,15 +412,25 @@ fn test_expression_parse_grouped_expression() {
         result,
         Ok(Expression::Operation(Operation::Multiply(
             FactorOperation {
-                left: Box::new(Expression::Grouping(Box::new(Expression::Operation(
-                    Operation::Plus(TermOperation {
-                        left: Box::new(Expression::Literal(ExpressionLiteral::Number(4.0))),
-                        right: Box::new(Expression::Literal(ExpressionLiteral::Number(3.0))),
-                        line_number: 1
-                    })
-                )))),
-                right: Box::new(Expression::Literal(ExpressionLiteral::Number(2.0))),
-                line_number: 1
+                left: Box::new(Expression::Grouping(
+                    span(0, 7),
+                    Box::new(Expression::Operation(Operation::Plus(TermOperation {
+                        left: Box::new(Expression::Literal(
+                            span(1, 2),
+                            ExpressionLiteral::Number(4.0)
+                        )),
+                        right: Box::new(Expression::Literal(
+                            span(5, 6),
+                            ExpressionLiteral::Number(3.0)
+                        )),
+                        span: span(3, 4)
+                    })))
+                )),
+                right: Box::new(Expression::Literal(
+                    span(10, 11),
+                    ExpressionLiteral::Number(2.0)
+                )),
+                span: span(8, 9)
             }
         )))
     );
@@ -342,17 +440,200 @@ fn test_expression_parse_grouped_expression() {
 fn test_variable_reference_parsing() {
     let tokens = scan_tokens("epic + 4").unwrap();
 
+    start_node_ids();
+
     let result = term(&mut tokens.iter().peekable());
 
     assert_eq!(
         result,
         Ok(Expression::Operation(Operation::Plus(TermOperation {
             left: (Box::new(Expression::Variable(ExpressionVariable {
-                line_number: 1,
-                identifier_name: "epic".to_string()
+                id: NodeId(0),
+                span: span(0, 4),
+                identifier_name: Symbol::intern("epic")
             }))),
-            right: (Box::new(Expression::Literal(ExpressionLiteral::Number(4.0)))),
-            line_number: 1
+            right: (Box::new(Expression::Literal(
+                span(7, 8),
+                ExpressionLiteral::Number(4.0)
+            ))),
+            span: span(5, 6)
         })))
     );
 }
+
+#[test]
+fn test_parsed_steps_can_be_pretty_printed() {
+    let tokens = scan_tokens("fun add(a, b) { return a + b; } let x = add(1, 2);").unwrap();
+
+    let steps = crate::parser::parse(tokens)
+        .into_iter()
+        .map(|step| step.unwrap().to_string())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        steps,
+        vec![
+            "( fun add ( a, b ) {\n    ( return ( + ( *a ) ( *b ) ) )\n} )",
+            "( let x = ( ( *add ) <-call-with- ( , 1.00, 2.00 )) )"
+        ]
+    );
+}
+
+#[test]
+fn test_missing_closing_parenthesis_error() {
+    let tokens = scan_tokens("(4 + 3").unwrap();
+
+    let error = expression(&mut tokens.iter().peekable()).unwrap_err();
+
+    assert_eq!(
+        error.kind,
+        ErrorKind::ExpectedToken {
+            expected: vec![TokenType::RightParen],
+            found: None
+        }
+    );
+    assert_eq!(error.code(), "E0201");
+    assert_eq!(error.span, span(6, 6));
+}
+
+#[test]
+fn test_parse_recovers_to_report_every_error() {
+    let tokens = scan_tokens("let a = ; let b = 1; fun f() { let c = +; c; } b;").unwrap();
+
+    let steps = crate::parser::parse(tokens);
+
+    assert_eq!(steps.len(), 4);
+    assert!(steps[0].is_err());
+    assert!(steps[1].is_ok());
+    assert!(steps[3].is_ok());
+
+    let codes: Vec<&str> = crate::parser::parsing_errors(&steps)
+        .iter()
+        .map(|error| error.code())
+        .collect();
+
+    assert_eq!(codes, vec!["E0203", "E0203"]);
+}
+
+#[test]
+fn test_parse_stops_at_stray_closing_brace() {
+    let tokens = scan_tokens("} let a = 1;").unwrap();
+
+    let steps = crate::parser::parse(tokens);
+
+    assert_eq!(steps.len(), 2);
+    assert!(steps[0].is_err());
+    assert!(steps[1].is_ok());
+}
+
+#[test]
+fn test_recovery_skips_braces_outside_of_blocks() {
+    let tokens = scan_tokens("fun f( { }\nlet a = 1;").unwrap();
+
+    let steps = crate::parser::parse(tokens);
+
+    let errors = crate::parser::parsing_errors(&steps);
+
+    assert_eq!(errors.len(), 1, "{errors:?}");
+    assert_eq!(errors[0].span.line, 1);
+    assert!(steps[1].is_ok());
+}
+
+#[test]
+fn test_truncated_input_is_an_error() {
+    for source in [
+        "fun f(a",
+        "foo(1",
+        "class A { fun",
+        "let",
+        "a.",
+        "if a {",
+        "fun f(a b) {}",
+    ] {
+        let tokens = scan_tokens(source).unwrap();
+
+        let steps = crate::parser::parse(tokens);
+
+        assert!(
+            !crate::parser::parsing_errors(&steps).is_empty(),
+            "{source:?} should fail to parse"
+        );
+    }
+}
+
+#[test]
+fn test_missing_tokens_is_an_error() {
+    let tokens = scan_tokens("let").unwrap();
+
+    // Without the end of file token the parser runs out of tokens mid statement
+    let error = crate::parser::rules::declaration(&mut tokens[..1].iter().peekable()).unwrap_err();
+
+    assert_eq!(error.kind, ErrorKind::UnexpectedEndOfFile);
+}
+
+#[test]
+fn test_deep_nesting_is_an_error() {
+    // Unoptimized builds use far more stack per level than release builds, so
+    // parse with the main thread's stack instead of the smaller test thread's
+    let parse_deeply_nested = || {
+        for source in [
+            "(".repeat(1000),
+            "!".repeat(1000),
+            "{".repeat(1000) + &"}".repeat(1000),
+            "f(".repeat(1000),
+            "a = ".repeat(1000),
+        ] {
+            let tokens = scan_tokens(&source).unwrap();
+
+            let steps = crate::parser::parse(tokens);
+
+            let codes: Vec<&str> = crate::parser::parsing_errors(&steps)
+                .iter()
+                .map(|error| error.code())
+                .collect();
+
+            assert!(codes.contains(&"E0206"), "{codes:?}");
+        }
+    };
+
+    std::thread::Builder::new()
+        .stack_size(8 * 1024 * 1024)
+        .spawn(parse_deeply_nested)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_long_flat_chains_are_not_nesting() {
+    let terms = vec!["1"; 300].join(" + ");
+    let conditions = vec!["a"; 300].join(" or ");
+    let calls = "f".to_owned() + &"()".repeat(300) + &".p".repeat(300);
+
+    for source in [terms, conditions, calls] {
+        let steps = crate::parser::parse(scan_tokens(&format!("print({source});")).unwrap());
+
+        assert!(crate::parser::parsing_errors(&steps).is_empty());
+    }
+}
+
+#[test]
+fn test_steps_carry_their_location_and_id() {
+    let steps = crate::parser::parse(scan_tokens("let a = 1;\n{\n    a;\n}").unwrap());
+
+    let declaration = steps[0].as_ref().unwrap();
+    let block = steps[1].as_ref().unwrap();
+
+    let crate::parser::StepKind::Block(block_steps) = &block.kind else {
+        panic!("expected a block");
+    };
+    let read = block_steps[0].as_ref().unwrap();
+
+    assert_eq!(declaration.span, Span::new(4, 5, 1, 5));
+    assert_eq!(block.span, Span::new(11, 12, 2, 1));
+    assert_eq!(read.span, Span::new(17, 18, 3, 5));
+
+    assert_ne!(declaration.id, block.id);
+    assert_ne!(declaration.id, read.id);
+    assert_ne!(block.id, read.id);
+}
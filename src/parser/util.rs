@@ -1,38 +1,135 @@
-use std::mem;
+use std::{cell::Cell, mem};
 
 use crate::{
-    errors::ParsingError,
+    errors::{ErrorKind, LoxError},
+    span::Span,
+    symbol::Symbol,
     tokens::{Token, TokenType},
-    tree::expression::Expression,
+    tree::expression::{Expression, NodeId},
 };
 
 use super::{rules::expression, TokenIter};
 
-pub fn consume_expected_character(
+/// How deep groupings, unary operators, call arguments, assignments and blocks
+/// can be nested before parsing gives up, deeper trees would overflow the stack
+/// while being parsed, resolved or dropped
+///
+/// Chains of binary operators, calls and properties are parsed in a loop and
+/// don't count, however long they are
+pub const MAX_NESTING_DEPTH: usize = 256;
+
+thread_local! {
+    static NESTING_DEPTH: Cell<usize> = const { Cell::new(0) };
+    static NEXT_NODE_ID: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Restarts numbering nodes from zero, done at the start of every parse
+pub fn start_node_ids() {
+    NEXT_NODE_ID.set(0);
+}
+
+/// Gives out the next unused node ID
+pub fn next_node_id() -> NodeId {
+    let id = NEXT_NODE_ID.get();
+
+    NEXT_NODE_ID.set(id + 1);
+
+    NodeId(id)
+}
+
+/// Counts one level of nesting for as long as it's alive
+pub struct NestingGuard;
+
+impl NestingGuard {
+    pub fn enter(span: Span) -> Result<NestingGuard, LoxError> {
+        let depth = NESTING_DEPTH.get();
+
+        if depth >= MAX_NESTING_DEPTH {
+            return Err(LoxError::new(
+                ErrorKind::TooDeeplyNested {
+                    limit: MAX_NESTING_DEPTH,
+                },
+                span,
+            ));
+        }
+
+        NESTING_DEPTH.set(depth + 1);
+
+        Ok(NestingGuard)
+    }
+}
+
+impl Drop for NestingGuard {
+    fn drop(&mut self) {
+        NESTING_DEPTH.set(NESTING_DEPTH.get() - 1);
+    }
+}
+
+/// Looks at the next token without consuming it
+///
+/// The scanner always ends with an end of file token that is never consumed, so
+/// this only fails when given tokens that didn't come from the scanner
+pub fn peek_token<'a>(tokens: &mut TokenIter<'a>) -> Result<&'a Token<'a>, LoxError> {
+    tokens
+        .peek()
+        .copied()
+        .ok_or_else(|| LoxError::new(ErrorKind::UnexpectedEndOfFile, Span::default()))
+}
+
+/// Consumes an identifier, returning its span and name
+///
+/// `context` describes what the identifier names for the error, e.g. "class name"
+pub fn consume_identifier(
     tokens: &mut TokenIter,
-    expected_token_type: TokenType,
-) -> Result<Token, ParsingError> {
-    match tokens.next() {
-        Some(token)
-            if mem::discriminant(&token.token_type) == mem::discriminant(&expected_token_type) =>
-        {
-            Ok(token.clone())
+    context: &'static str,
+) -> Result<(Span, Symbol), LoxError> {
+    let token = peek_token(tokens)?;
+
+    match &token.token_type {
+        TokenType::Identifier(name) => {
+            tokens.next();
+
+            Ok((token.span, *name))
         }
-        Some(unrecognized_token) => Err(ParsingError {
-            message: format!(
-                "Expected {:?}, found \"{:?}\"",
-                expected_token_type, unrecognized_token.token_type
-            ),
-            line_number: unrecognized_token.line_number,
-        }),
-        None => Err(ParsingError {
-            message: format!("Expected {:?}, didn't find character", expected_token_type),
-            line_number: 0, // TODO: Find better way to get this line number
-        }),
+        unrecognized => Err(LoxError::new(
+            ErrorKind::ExpectedIdentifier {
+                context,
+                found: unrecognized.clone(),
+            },
+            token.span,
+        )),
+    }
+}
+
+pub fn consume_expected_character<'a>(
+    tokens: &mut TokenIter<'a>,
+    expected_token_type: TokenType,
+) -> Result<Token<'a>, LoxError> {
+    if let Some(token) = tokens.next_if(|token| {
+        mem::discriminant(&token.token_type) == mem::discriminant(&expected_token_type)
+    }) {
+        return Ok(token.clone());
     }
+
+    // Unexpected tokens are left in place so synchronizing can stop at them
+    Err(expected_token_error(
+        vec![expected_token_type],
+        peek_token(tokens)?,
+    ))
 }
 
-pub fn parse_call_arguments(tokens: &mut TokenIter) -> Result<Vec<Expression>, ParsingError> {
+/// Error for finding `found` where one of the `expected` token types should have been
+pub fn expected_token_error(expected: Vec<TokenType>, found: &Token) -> LoxError {
+    LoxError::new(
+        ErrorKind::ExpectedToken {
+            expected,
+            found: Some(found.token_type.clone()).filter(|found| *found != TokenType::Eof),
+        },
+        found.span,
+    )
+}
+
+pub fn parse_call_arguments(tokens: &mut TokenIter) -> Result<Vec<Expression>, LoxError> {
     consume_expected_character(tokens, TokenType::LeftParen)?;
 
     let mut arguments: Vec<Expression> = vec![];
@@ -45,23 +142,25 @@ pub fn parse_call_arguments(tokens: &mut TokenIter) -> Result<Vec<Expression>, P
     }
 
     loop {
-        match tokens.next() {
-            Some(delimiter) if delimiter.token_type == TokenType::Comma => {
+        let delimiter = peek_token(tokens)?;
+
+        match delimiter.token_type {
+            TokenType::Comma => {
+                tokens.next();
+
                 arguments.push(expression(tokens)?);
             }
-            Some(delimiter) if delimiter.token_type == TokenType::RightParen => {
+            TokenType::RightParen => {
+                tokens.next();
+
                 break;
             }
-            Some(unrecognized) => {
-                return Err(ParsingError {
-                    line_number: unrecognized.line_number,
-                    message: format!(
-                        "Expected either comma or parenthesis in function arguments, found {:?}",
-                        unrecognized.token_type
-                    ),
-                })
+            _ => {
+                return Err(expected_token_error(
+                    vec![TokenType::Comma, TokenType::RightParen],
+                    delimiter,
+                ))
             }
-            None => unimplemented!(),
         };
     }
 
@@ -0,0 +1,74 @@
+use std::io::{BufRead, BufReader, Write};
+
+use crate::{
+    interpreter::limits::Limits,
+    logging::{log_level, set_log_level, LogLevel},
+    with_interpreter_stack, Backend, RunStatus, Session,
+};
+
+/// Reads code from stdin and runs it a line at a time until the end of input
+///
+/// Every line sees the globals defined by the lines before it. Cancelling the
+/// limits' token only stops the line that's running
+pub fn repl(limits: Limits, backend: Backend) -> RunStatus {
+    // Separators and timings around every line only get in the way
+    if log_level() == LogLevel::Normal {
+        set_log_level(LogLevel::Quiet);
+    }
+
+    run_lines(
+        BufReader::new(std::io::stdin()),
+        std::io::stdout(),
+        limits,
+        backend,
+    )
+}
+
+/// Runs every line of `input` like `repl`, writing the prompts to `prompt`
+///
+/// Errors in a line don't stop the ones after it, so this only fails when the
+/// interpreter itself crashed
+pub fn run_lines(
+    input: impl BufRead + Send,
+    prompt: impl Write + Send,
+    limits: Limits,
+    backend: Backend,
+) -> RunStatus {
+    // The globals can't leave the thread they're made on, so every line runs on the same one
+    let finished = with_interpreter_stack(&limits, || {
+        let mut session = Session::new(vec![], limits.clone(), backend);
+
+        read_lines(input, prompt, |line| {
+            // Ctrl-C pressed while waiting at the prompt shouldn't stop the next line
+            if let Some(cancellation) = &limits.cancellation {
+                cancellation.reset();
+            }
+
+            session.run(line);
+        })
+    });
+
+    match finished {
+        Some(()) => RunStatus::Success,
+        None => RunStatus::RuntimeError,
+    }
+}
+
+/// Prompts for lines from `input`, passing on every one that isn't blank
+fn read_lines(mut input: impl BufRead, mut prompt: impl Write, mut run: impl FnMut(&str)) {
+    let mut line = String::new();
+
+    loop {
+        if write!(prompt, "> ").and_then(|_| prompt.flush()).is_err() {
+            return;
+        }
+
+        line.clear();
+
+        match input.read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) if line.trim().is_empty() => continue,
+            Ok(_) => run(&line),
+        }
+    }
+}
@@ -1,241 +1,353 @@
 mod scope_stack;
+mod tests;
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+};
 
-use scope_stack::ScopeStack;
+use scope_stack::{ClassKind, FunctionKind, ScopeStack, VariableKind};
 
 use crate::{
-    errors::ResolvingError,
+    errors::{ErrorKind, LoxError},
+    interpreter::{functions::native::BUILT_INS, is_literal_truthy},
     parser::{
         statements::{FunStatement, Statement},
-        ParsedStep, ParsingResult,
+        ParsingResult, StepKind,
+    },
+    span::Span,
+    symbol::Symbol,
+    tree::{
+        expression::{Expression, NodeId},
+        visit::{walk_expression, walk_statement, Visitor},
     },
-    tree::expression::{Expression, ExpressionVariable, Operation},
 };
 
-type ResolveResult = Result<(), ResolvingError>;
-pub type VariableMap = HashMap<ExpressionVariable, usize>;
+type ResolveResult = Result<(), Infallible>;
+/// Where each variable used by the program lives, `None` for variables that no
+/// scope declares, which are undefined once the program reaches them
+pub type VariableMap = HashMap<NodeId, Option<ResolvedVariable>>;
+
+/// Where a variable lives at runtime, `depth` environments up from the one it's
+/// used in and at `slot` within that environment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolvedVariable {
+    pub depth: usize,
+    pub slot: usize,
+}
 
-pub fn resolve(steps: Vec<ParsingResult>) -> Result<VariableMap, ResolvingError> {
-    let mut scopes = ScopeStack::new();
+/// Everything the resolver found out about a program without errors
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub variable_map: VariableMap,
+    /// Likely mistakes that don't stop the program from running, in source order
+    pub warnings: Vec<LoxError>,
+}
 
-    scopes.begin_scope();
+/// Resolves every step that parsed, returning all of the errors found in source
+/// order if there were any
+///
+/// Steps that failed to parse are skipped, their errors are left to the caller
+pub fn resolve(steps: &[ParsingResult]) -> Result<Resolution, Vec<LoxError>> {
+    Session::new().resolve(steps)
+}
 
-    resolve_globals(&mut scopes)?;
+/// Resolves programs one after another, each seeing the globals declared by the
+/// ones before it, like the lines of the REPL
+pub struct Session {
+    resolver: Resolver,
+}
 
-    resolve_steps(&mut scopes, steps)?;
+impl Session {
+    pub fn new() -> Self {
+        let mut resolver = Resolver {
+            scopes: ScopeStack::new(),
+            errors: vec![],
+        };
 
-    scopes.end_scope();
+        resolver.scopes.begin_scope();
 
-    Ok(scopes.locals)
-}
+        resolver.resolve_globals();
 
-fn resolve_globals(scopes: &mut ScopeStack) -> ResolveResult {
-    scopes.define("now".to_owned());
-    scopes.define("print".to_owned());
+        Session { resolver }
+    }
 
-    Ok(())
-}
+    /// Resolves the steps like `resolve`, none of the globals they declare are
+    /// kept when there are errors
+    pub fn resolve(&mut self, steps: &[ParsingResult]) -> Result<Resolution, Vec<LoxError>> {
+        let checkpoint = self.resolver.scopes.checkpoint();
 
-fn resolve_steps(scopes: &mut ScopeStack, steps: Vec<ParsingResult>) -> ResolveResult {
-    for step in steps {
-        resolve_step(scopes, step.unwrap())?;
-    }
+        let Ok(()) = self.resolver.visit_steps(steps);
 
-    Ok(())
-}
+        let scopes = &mut self.resolver.scopes;
 
-fn resolve_step(scope_stack: &mut ScopeStack, step: ParsedStep) -> ResolveResult {
-    match step {
-        ParsedStep::Expression(expr) => resolve_expression(scope_stack, expr)?,
-        ParsedStep::Statement(stmt) => resolve_statement(scope_stack, stmt)?,
-        ParsedStep::Block(block) => {
-            scope_stack.begin_scope();
+        if !self.resolver.errors.is_empty() {
+            scopes.rewind(checkpoint);
 
-            resolve_steps(scope_stack, block)?;
+            let mut errors = std::mem::take(&mut self.resolver.errors);
+            errors.sort_by_key(|error| error.span.start);
 
-            scope_stack.end_scope();
+            return Err(errors);
         }
+
+        scopes.resolve_late_globals();
+
+        let mut warnings = std::mem::take(&mut scopes.warnings);
+        warnings.sort_by_key(|warning| warning.span.start);
+
+        Ok(Resolution {
+            variable_map: std::mem::take(&mut scopes.locals),
+            warnings,
+        })
     }
 
-    Ok(())
+    /// Forgets the globals the last steps declared past the first `defined` of
+    /// every global, for when running them stopped before the rest were defined
+    pub fn keep_defined_globals(&mut self, defined: usize) {
+        self.resolver.scopes.forget_globals_from(defined);
+    }
 }
 
-fn resolve_expression(scope_stack: &mut ScopeStack, expr: Expression) -> ResolveResult {
-    match expr {
-        Expression::Literal(_) => (),
-        Expression::Operation(operation) => resolve_operation(scope_stack, operation)?,
-        Expression::Grouping(group) => resolve_expression(scope_stack, *group)?,
-        Expression::Variable(var) => {
-            if scope_stack.is_locally_declared(&var.identifier_name) {
-                return Err(ResolvingError {
-                    line_number: var.line_number,
-                    message: format!(
-                        "Can't read local variable ({}) in its own initializer",
-                        var.identifier_name
-                    ),
-                });
-            }
+impl Default for Session {
+    fn default() -> Self {
+        Session::new()
+    }
+}
 
-            scope_stack.encode_resolved_variable(var);
-        }
-        Expression::Assign(var, value) => {
-            resolve_expression(scope_stack, *value)?;
-            scope_stack.encode_resolved_variable(var);
-        }
-        Expression::Call(_, callee, arguments) => {
-            resolve_expression(scope_stack, *callee)?;
+struct Resolver {
+    scopes: ScopeStack,
+    /// Resolving carries on past errors, so that all of them get reported at once
+    errors: Vec<LoxError>,
+}
 
-            for arg in arguments {
-                resolve_expression(scope_stack, arg)?;
-            }
-        }
-        Expression::Get(_, expr, _) => {
-            resolve_expression(scope_stack, *expr)?;
-        }
-        Expression::Set(_, expr, _, value) => {
-            resolve_expression(scope_stack, *expr)?;
+impl Resolver {
+    fn error(&mut self, kind: ErrorKind, span: Span) {
+        self.errors.push(LoxError::new(kind, span));
+    }
 
-            resolve_expression(scope_stack, *value)?;
-        }
-        Expression::This => {
-            scope_stack.encode_resolved_variable(ExpressionVariable {
-                line_number: 0,
-                identifier_name: "this".to_string(),
-            });
+    /// Declares a variable, function or class, erroring instead when its scope
+    /// already has something by that name, built-ins included
+    ///
+    /// Returns whether it was declared
+    fn declare(&mut self, name: Symbol, span: Span) -> bool {
+        if self.scopes.is_locally_declared(name) || self.scopes.is_locally_defined(name) {
+            self.error(ErrorKind::AlreadyDeclared(name.to_string()), span);
+
+            return false;
         }
+
+        self.scopes.declare(name, span, VariableKind::Declaration);
+
+        true
     }
 
-    Ok(())
-}
+    fn resolve_globals(&mut self) {
+        for built_in in &BUILT_INS {
+            let name = Symbol::intern(built_in.name);
 
-fn resolve_operation(
-    scope_stack: &mut ScopeStack,
-    operation: Operation,
-) -> Result<(), ResolvingError> {
-    match operation {
-        Operation::Not(operation) | Operation::Negate(operation) => {
-            resolve_expression(scope_stack, *operation.operand)?
-        }
-        Operation::Equal(op) | Operation::NotEqual(op) => {
-            resolve_expression(scope_stack, *op.left)?;
-            resolve_expression(scope_stack, *op.right)?;
-        }
-        Operation::Less(op)
-        | Operation::LessEqual(op)
-        | Operation::Greater(op)
-        | Operation::GreaterEqual(op) => {
-            resolve_expression(scope_stack, *op.left)?;
-            resolve_expression(scope_stack, *op.right)?;
-        }
-        Operation::Plus(op) | Operation::Minus(op) => {
-            resolve_expression(scope_stack, *op.left)?;
-            resolve_expression(scope_stack, *op.right)?;
+            self.scopes
+                .declare(name, Span::default(), VariableKind::Declaration);
+            self.scopes.define(name);
         }
-        Operation::Divide(op) | Operation::Multiply(op) => {
-            resolve_expression(scope_stack, *op.left)?;
-            resolve_expression(scope_stack, *op.right)?;
-        }
-        Operation::And(op) | Operation::Or(op) => {
-            resolve_expression(scope_stack, *op.left)?;
-            resolve_expression(scope_stack, *op.right)?;
-        }
-    };
-    Ok(())
-}
+    }
 
-fn resolve_statement(scope_stack: &mut ScopeStack, stmt: Statement) -> ResolveResult {
-    match stmt {
-        Statement::Variable(name, expr) => {
-            if scope_stack.is_locally_declared(&name) || scope_stack.is_locally_defined(&name) {
-                return Err(ResolvingError {
-                    // TODO: Get this line number somehow
-                    line_number: 0,
-                    message: format!("Variable {name} already exists in this scope"),
-                });
+    /// Resolves a function or method's body, the name of functions is declared by
+    /// the caller since methods aren't variables
+    ///
+    /// Mirrors the environments a call creates, one for the parameters and one for
+    /// the body's block
+    fn resolve_function(
+        &mut self,
+        function_statement: &FunStatement,
+        kind: FunctionKind,
+    ) -> ResolveResult {
+        let enclosing_function = self.scopes.current_function;
+        self.scopes.current_function = kind;
+
+        self.scopes.begin_scope();
+
+        for param in &function_statement.parameters {
+            if self.scopes.is_locally_defined(param.name) {
+                self.error(
+                    ErrorKind::DuplicateParameter(param.name.to_string()),
+                    param.span,
+                );
+
+                continue;
             }
 
-            scope_stack.declare(name.clone());
+            self.scopes
+                .declare(param.name, param.span, VariableKind::Parameter);
+            self.scopes.define(param.name);
+        }
 
-            resolve_expression(scope_stack, expr)?;
+        // NOTE: The body is a block, which handles it's own scoping
+        self.visit_function(function_statement)?;
 
-            scope_stack.define(name);
-        }
-        Statement::If(stmt) => {
-            resolve_expression(scope_stack, stmt.condition)?;
-            resolve_step(scope_stack, *stmt.then_statement)?;
+        self.scopes.end_scope();
+
+        self.scopes.current_function = enclosing_function;
+
+        Ok(())
+    }
+}
+
+impl Visitor<'_> for Resolver {
+    type Error = Infallible;
+
+    fn visit_steps(&mut self, steps: &[ParsingResult]) -> ResolveResult {
+        let mut after_return = false;
+
+        for step in steps.iter().flatten() {
+            // Only the first unreachable step is reported, the rest follow from it
+            if after_return {
+                self.scopes.warn(ErrorKind::UnreachableCode, step.span);
 
-            if let Some(else_stmt) = stmt.else_statement {
-                resolve_step(scope_stack, *else_stmt)?;
+                after_return = false;
             }
-        }
-        Statement::While(while_statement) => {
-            resolve_expression(scope_stack, while_statement.condition)?;
-            resolve_step(scope_stack, *while_statement.body)?;
-        }
-        Statement::Fun(function_statement) => {
-            scope_stack.declare(function_statement.name.clone());
-            scope_stack.define(function_statement.name.clone());
 
-            resolve_function(scope_stack, function_statement)?;
-        }
-        Statement::Return(expr) => {
-            if expr.is_some() {
-                resolve_expression(scope_stack, expr.unwrap())?;
+            self.visit_step(step)?;
+
+            if let StepKind::Statement(Statement::Return(..)) = step.kind {
+                after_return = true;
             }
         }
-        Statement::Class(class) => {
-            scope_stack.declare(class.name.clone());
-            scope_stack.define(class.name);
 
-            for method in class.methods {
-                resolve_method(scope_stack, method)?;
+        Ok(())
+    }
+
+    fn visit_block(&mut self, steps: &[ParsingResult]) -> ResolveResult {
+        self.scopes.begin_scope();
+
+        self.visit_steps(steps)?;
+
+        self.scopes.end_scope();
+
+        Ok(())
+    }
+
+    fn visit_expression(&mut self, expr: &Expression) -> ResolveResult {
+        match expr {
+            Expression::Variable(var) => {
+                if self.scopes.is_locally_declared(var.identifier_name) {
+                    self.error(
+                        ErrorKind::ReadInOwnInitializer(var.identifier_name.to_string()),
+                        var.span,
+                    );
+                } else {
+                    self.scopes.encode_resolved_variable(var, true);
+                }
+            }
+            Expression::Assign(var, value) => {
+                self.visit_expression(value)?;
+                self.scopes.encode_resolved_variable(var, false);
             }
+            Expression::This(this) => {
+                if self.scopes.current_class == ClassKind::None {
+                    self.error(ErrorKind::ThisOutsideClass, this.span);
+                } else {
+                    self.scopes.encode_resolved_variable(this, true);
+                }
+            }
+            expr => walk_expression(self, expr)?,
         }
+
+        Ok(())
     }
 
-    Ok(())
-}
+    fn visit_statement(&mut self, stmt: &Statement) -> ResolveResult {
+        match stmt {
+            Statement::Variable(span, name, expr) => {
+                if !self.declare(*name, *span) {
+                    // The first declaration stays, but the value can still have errors
+                    return self.visit_expression(expr);
+                }
 
-fn resolve_function(
-    scope_stack: &mut ScopeStack,
-    function_statement: FunStatement,
-) -> ResolveResult {
-    scope_stack.declare(function_statement.name.clone());
+                self.visit_expression(expr)?;
 
-    scope_stack.define(function_statement.name);
+                self.scopes.define(*name);
+            }
+            Statement::If(if_statement) => {
+                if let Some(value) = constant_truthiness(&if_statement.condition) {
+                    self.scopes.warn(
+                        ErrorKind::ConstantCondition { value },
+                        if_statement.condition.span(),
+                    );
+                }
+
+                walk_statement(self, stmt)?;
+            }
+            Statement::Fun(function_statement) => {
+                // The body is resolved either way, it can still have errors
+                if self.declare(function_statement.name, function_statement.span) {
+                    self.scopes.define(function_statement.name);
+                }
 
-    for param in function_statement.parameters {
-        scope_stack.declare(param.clone());
-        scope_stack.define(param);
-    }
+                self.resolve_function(function_statement, FunctionKind::Function)?;
+            }
+            Statement::Return(span, expr) => {
+                if self.scopes.current_function == FunctionKind::None {
+                    self.error(ErrorKind::ReturnOutsideFunction, *span);
+                }
+
+                if let Some(expr) = expr {
+                    if self.scopes.current_function == FunctionKind::Initializer {
+                        self.error(ErrorKind::ReturnValueFromInitializer, *span);
+                    }
+
+                    self.visit_expression(expr)?;
+                }
+            }
+            Statement::Class(class) => {
+                if self.declare(class.name, class.span) {
+                    self.scopes.define(class.name);
+                }
 
-    scope_stack.begin_scope();
+                let enclosing_class = self.scopes.current_class;
+                self.scopes.current_class = ClassKind::Class;
 
-    // NOTE: The block generated by resolve_step handles it's own scoping
-    resolve_step(scope_stack, *function_statement.body)?;
+                // Mirrors the environment a method is bound in, holding only `this`
+                let this = Symbol::intern("this");
 
-    scope_stack.end_scope();
+                self.scopes.begin_scope();
+                self.scopes.declare(this, class.span, VariableKind::This);
+                self.scopes.define(this);
 
-    Ok(())
-}
+                let mut method_names = HashSet::new();
 
-fn resolve_method(scope_stack: &mut ScopeStack, method_statement: FunStatement) -> ResolveResult {
-    scope_stack.declare(method_statement.name.clone());
+                for method in &class.methods {
+                    if !method_names.insert(method.name) {
+                        self.error(
+                            ErrorKind::DuplicateMethod(method.name.to_string()),
+                            method.span,
+                        );
+                    }
 
-    scope_stack.define(method_statement.name);
+                    let kind = if method.name == "init" {
+                        FunctionKind::Initializer
+                    } else {
+                        FunctionKind::Method
+                    };
 
-    for param in method_statement.parameters {
-        scope_stack.declare(param.clone());
-        scope_stack.define(param);
-    }
+                    self.resolve_function(method, kind)?;
+                }
 
-    scope_stack.begin_scope();
+                self.scopes.end_scope();
 
-    // NOTE: The block generated by resolve_step handles it's own scoping
-    resolve_step(scope_stack, *method_statement.body)?;
+                self.scopes.current_class = enclosing_class;
+            }
+            Statement::While(_) => walk_statement(self, stmt)?,
+        }
 
-    scope_stack.end_scope();
+        Ok(())
+    }
+}
 
-    Ok(())
+/// Whether the condition is always truthy or always falsy, if it's a literal
+fn constant_truthiness(condition: &Expression) -> Option<bool> {
+    match condition {
+        Expression::Literal(_, literal) => Some(is_literal_truthy(literal)),
+        Expression::Grouping(_, grouped) => constant_truthiness(grouped),
+        _ => None,
+    }
 }
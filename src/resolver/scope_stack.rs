@@ -1,10 +1,66 @@
 use std::collections::HashMap;
 
-use crate::tree::expression::ExpressionVariable;
+use crate::{
+    errors::{ErrorKind, LoxError},
+    span::Span,
+    symbol::Symbol,
+    tree::expression::{ExpressionVariable, NodeId},
+};
+
+use super::{ResolvedVariable, VariableMap};
 
 pub struct ScopeStack {
-    pub locals: HashMap<ExpressionVariable, usize>,
+    pub locals: VariableMap,
+    /// Kind of the innermost function being resolved
+    pub current_function: FunctionKind,
+    /// Kind of the innermost class being resolved
+    pub current_class: ClassKind,
+    pub warnings: Vec<LoxError>,
     stack: Vec<Scope>,
+    /// Variables no scope declared yet when they were used, which could still be
+    /// globals declared further down, e.g. a function calling one defined after it
+    unresolved: Vec<UnresolvedVariable>,
+    /// Variables the last `resolve_late_globals` found a global for, along with its slot
+    late_resolved: Vec<(UnresolvedVariable, usize)>,
+}
+
+/// How far resolving had got, to go back to when the steps after it have errors
+pub struct Checkpoint {
+    global_count: usize,
+    unresolved_count: usize,
+}
+
+struct UnresolvedVariable {
+    id: NodeId,
+    name: Symbol,
+    /// How many environments up the global one is from where the variable is used
+    global_depth: usize,
+    is_read: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionKind {
+    None,
+    Function,
+    Method,
+    /// The `init` method of a class
+    Initializer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassKind {
+    None,
+    Class,
+}
+
+/// What introduced a variable, which decides the warning given when it's never read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariableKind {
+    /// Variables, functions and classes
+    Declaration,
+    Parameter,
+    /// The instance a method is bound to, which methods are free to ignore
+    This,
 }
 
 impl ScopeStack {
@@ -12,28 +68,66 @@ impl ScopeStack {
         ScopeStack {
             stack: Vec::with_capacity(20),
             locals: HashMap::default(),
+            current_function: FunctionKind::None,
+            current_class: ClassKind::None,
+            warnings: vec![],
+            unresolved: vec![],
+            late_resolved: vec![],
         }
     }
 
-    /// Mark a variable as existing but "not ready yet"
-    pub fn declare(&mut self, name: String) {
+    pub fn warn(&mut self, kind: ErrorKind, span: Span) {
+        self.warnings.push(LoxError::new(kind, span));
+    }
+
+    /// Mark a variable as existing but "not ready yet", giving it the next slot
+    /// of the scope
+    pub fn declare(&mut self, name: Symbol, span: Span, kind: VariableKind) {
+        let is_global = self.stack.len() <= 1;
+        let shadows = self
+            .stack
+            .iter()
+            .rev()
+            .skip(1)
+            .any(|scope| scope.variables.contains_key(&name));
+
+        if !is_global && shadows && kind != VariableKind::This {
+            self.warn(ErrorKind::ShadowedVariable(name.to_string()), span);
+        }
+
         if let Some(scope) = self.stack.last_mut() {
-            scope.insert(name, false);
+            let slot = scope.slot_count;
+
+            scope.slot_count += 1;
+            scope.variables.insert(
+                name,
+                ScopedVariable {
+                    slot,
+                    span,
+                    kind,
+                    defined: false,
+                    read: false,
+                },
+            );
         }
     }
 
     /// Mark a variable as ready
-    pub fn define(&mut self, name: String) {
-        if let Some(scope) = self.stack.last_mut() {
-            scope.insert(name, true);
+    pub fn define(&mut self, name: Symbol) {
+        if let Some(variable) = self
+            .stack
+            .last_mut()
+            .and_then(|scope| scope.variables.get_mut(&name))
+        {
+            variable.defined = true;
         }
     }
 
     /// Checks if a variable has been declared but not defined in it's local scope
-    pub fn is_locally_declared(&self, name: &String) -> bool {
-        if let Some(map) = self.stack.last() {
-            match map.get(name) {
-                Some(defined) => !defined,
+    pub fn is_locally_declared(&self, name: Symbol) -> bool {
+        if let Some(scope) = self.stack.last() {
+            match scope.variables.get(&name) {
+                Some(variable) => !variable.defined,
                 None => false,
             }
         } else {
@@ -41,18 +135,17 @@ impl ScopeStack {
         }
     }
 
-        /// Checks if a variable has been defined in it's local scope
-        pub fn is_locally_defined(&self, name: &String) -> bool {
-            if let Some(map) = self.stack.last() {
-                match map.get(name) {
-                    Some(defined) => *defined,
-                    None => false,
-                }
-            } else {
-                false
+    /// Checks if a variable has been defined in it's local scope
+    pub fn is_locally_defined(&self, name: Symbol) -> bool {
+        if let Some(scope) = self.stack.last() {
+            match scope.variables.get(&name) {
+                Some(variable) => variable.defined,
+                None => false,
             }
+        } else {
+            false
         }
-    
+    }
 
     pub fn begin_scope(&mut self) {
         let scope = Scope::default();
@@ -60,18 +153,144 @@ impl ScopeStack {
         self.stack.push(scope);
     }
 
+    /// Leaves the innermost scope, warning about any of its local variables that
+    /// were never read
     pub fn end_scope(&mut self) -> Option<Scope> {
-        self.stack.pop()
+        let scope = self.stack.pop()?;
+
+        // Globals are left alone, a script is often only partly written
+        if self.stack.is_empty() {
+            return Some(scope);
+        }
+
+        for (name, variable) in &scope.variables {
+            if variable.read || name.as_str().starts_with('_') {
+                continue;
+            }
+
+            let kind = match variable.kind {
+                VariableKind::Declaration => ErrorKind::UnusedVariable(name.to_string()),
+                VariableKind::Parameter => ErrorKind::UnusedParameter(name.to_string()),
+                VariableKind::This => continue,
+            };
+
+            self.warn(kind, variable.span);
+        }
+
+        Some(scope)
     }
 
-    pub fn encode_resolved_variable(&mut self, variable: ExpressionVariable) {
+    /// Records the nearest enclosing declaration of the variable, leaving it for
+    /// `resolve_late_globals` when there isn't one yet
+    ///
+    /// Only reads count towards a variable being used, assigning to it doesn't
+    pub fn encode_resolved_variable(&mut self, variable: &ExpressionVariable, is_read: bool) {
         // 0 depth means local-est scope
-        for (depth, scope) in Iterator::zip(0..self.stack.len(), self.stack.iter().rev()) {
-            if scope.contains_key(&variable.identifier_name) {
-                self.locals.insert(variable.clone(), depth);
+        for (depth, scope) in self.stack.iter_mut().rev().enumerate() {
+            if let Some(scoped_variable) = scope.variables.get_mut(&variable.identifier_name) {
+                scoped_variable.read |= is_read;
+
+                self.locals.insert(
+                    variable.id,
+                    Some(ResolvedVariable {
+                        depth,
+                        slot: scoped_variable.slot,
+                    }),
+                );
+
+                return;
+            }
+        }
+
+        self.unresolved.push(UnresolvedVariable {
+            id: variable.id,
+            name: variable.identifier_name,
+            global_depth: self.stack.len().saturating_sub(1),
+            is_read,
+        });
+    }
+
+    /// Resolves the variables that weren't declared when they were used to the
+    /// globals declared since, the rest are recorded as having no declaration so
+    /// running them reports the variable as undefined
+    ///
+    /// The rest are kept in case a later call finds them, has to be called while
+    /// the global scope is the only one
+    pub fn resolve_late_globals(&mut self) {
+        self.late_resolved.clear();
+
+        let Some(globals) = self.stack.first_mut() else {
+            return;
+        };
+
+        for variable in std::mem::take(&mut self.unresolved) {
+            let Some(global) = globals.variables.get_mut(&variable.name) else {
+                self.locals.insert(variable.id, None);
+                self.unresolved.push(variable);
+
+                continue;
+            };
+
+            global.read |= variable.is_read;
+
+            self.locals.insert(
+                variable.id,
+                Some(ResolvedVariable {
+                    depth: variable.global_depth,
+                    slot: global.slot,
+                }),
+            );
+            self.late_resolved.push((variable, global.slot));
+        }
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            global_count: self.stack.first().map_or(0, |globals| globals.slot_count),
+            unresolved_count: self.unresolved.len(),
+        }
+    }
+
+    /// Forgets everything resolved since the checkpoint, which has to be taken
+    /// while the global scope is the only one
+    pub fn rewind(&mut self, checkpoint: Checkpoint) {
+        self.unresolved.truncate(checkpoint.unresolved_count);
+        self.forget_globals_from(checkpoint.global_count);
+
+        self.locals.clear();
+        self.warnings.clear();
+    }
+
+    /// Forgets the globals from `slot` on, the variables the last
+    /// `resolve_late_globals` resolved to them go back to waiting for a global
+    pub fn forget_globals_from(&mut self, slot: usize) {
+        let Some(globals) = self.stack.first_mut() else {
+            return;
+        };
+
+        globals.variables.retain(|_, global| global.slot < slot);
+        globals.slot_count = globals.slot_count.min(slot);
+
+        for (variable, global_slot) in self.late_resolved.drain(..) {
+            if global_slot >= slot {
+                self.unresolved.push(variable);
             }
         }
     }
 }
 
-type Scope = HashMap<String, bool>;
+/// Variables of a single scope, each in the slot its environment will store it in
+#[derive(Default)]
+pub struct Scope {
+    variables: HashMap<Symbol, ScopedVariable>,
+    slot_count: usize,
+}
+
+struct ScopedVariable {
+    slot: usize,
+    /// Where the variable was declared
+    span: Span,
+    kind: VariableKind,
+    defined: bool,
+    read: bool,
+}
@@ -0,0 +1,255 @@
+#![cfg(test)]
+
+use crate::{
+    errors::{ErrorKind, LoxError},
+    parser::parse,
+    scanner::scan_tokens,
+    span::Span,
+    tree::{
+        expression::{Expression, NodeId},
+        visit::{walk_expression, Visitor},
+    },
+};
+
+use super::{resolve, ResolvedVariable, VariableMap};
+
+fn resolve_source(source: &str) -> VariableMap {
+    resolve(&parse(scan_tokens(source).unwrap()))
+        .unwrap()
+        .variable_map
+}
+
+/// Resolves the source, returning where each variable read resolved to in source order
+fn resolve_reads(source: &str) -> Vec<Option<ResolvedVariable>> {
+    let steps = parse(scan_tokens(source).unwrap());
+    let variable_map = resolve(&steps).unwrap().variable_map;
+
+    let mut reads = VariableReads { ids: vec![] };
+    let Ok(()) = reads.visit_steps(&steps);
+
+    reads
+        .ids
+        .iter()
+        .map(|id| variable_map.get(id).copied().flatten())
+        .collect()
+}
+
+struct VariableReads {
+    ids: Vec<NodeId>,
+}
+
+impl Visitor<'_> for VariableReads {
+    type Error = std::convert::Infallible;
+
+    fn visit_expression(&mut self, expression: &Expression) -> Result<(), Self::Error> {
+        if let Expression::Variable(variable) = expression {
+            self.ids.push(variable.id);
+        }
+
+        walk_expression(self, expression)
+    }
+}
+
+fn resolve_errors(source: &str) -> Vec<LoxError> {
+    resolve(&parse(scan_tokens(source).unwrap())).unwrap_err()
+}
+
+/// The only error found resolving the source
+fn resolve_error(source: &str) -> LoxError {
+    let mut errors = resolve_errors(source);
+
+    assert_eq!(errors.len(), 1, "{errors:?}");
+
+    errors.remove(0)
+}
+
+/// The natives take the first slots of the global scope
+const FIRST_GLOBAL_SLOT: usize = 5;
+
+#[test]
+fn test_uses_on_the_same_line_are_resolved_separately() {
+    let reads = resolve_reads("let a = 1; let b = a + a;");
+
+    let global_a = ResolvedVariable {
+        depth: 0,
+        slot: FIRST_GLOBAL_SLOT,
+    };
+
+    assert_eq!(reads, vec![Some(global_a), Some(global_a)]);
+}
+
+#[test]
+fn test_parameters_are_resolved_in_their_own_scope() {
+    let reads = resolve_reads("fun f(first, second) {\n    return second;\n}");
+
+    // The function's body is a block inside of the parameters' scope
+    assert_eq!(reads, vec![Some(ResolvedVariable { depth: 1, slot: 1 })]);
+}
+
+#[test]
+fn test_methods_are_not_declared_as_variables() {
+    let reads = resolve_reads("class A {\n    fun m() {}\n}\nlet m = 1;\nm;");
+
+    assert_eq!(
+        reads,
+        vec![Some(ResolvedVariable {
+            depth: 0,
+            slot: FIRST_GLOBAL_SLOT + 1,
+        })]
+    );
+}
+
+#[test]
+fn test_return_outside_function_errors() {
+    let error = resolve_error("let a = 1;\nreturn a;");
+
+    assert_eq!(error.kind, ErrorKind::ReturnOutsideFunction);
+    assert_eq!(error.span, Span::new(11, 17, 2, 1));
+}
+
+#[test]
+fn test_return_value_from_initializer_errors() {
+    let error = resolve_error("class A {\n    fun init() {\n        return 1;\n    }\n}");
+
+    assert_eq!(error.kind, ErrorKind::ReturnValueFromInitializer);
+    assert_eq!(error.span.line, 3);
+
+    // Stopping early without a value is allowed
+    resolve_source("class A {\n    fun init() {\n        return;\n    }\n}");
+}
+
+#[test]
+fn test_this_outside_class_errors() {
+    let error = resolve_error("fun f() {\n    return this;\n}");
+
+    assert_eq!(error.kind, ErrorKind::ThisOutsideClass);
+    assert_eq!(error.span, Span::new(21, 25, 2, 12));
+
+    // Functions declared inside of methods are still inside of the class
+    resolve_source("class A {\n    fun m() {\n        fun f() {\n            return this;\n        }\n    }\n}");
+}
+
+#[test]
+fn test_duplicate_parameter_errors() {
+    let error = resolve_error("fun f(a, b, a) {}");
+
+    assert_eq!(error.kind, ErrorKind::DuplicateParameter("a".to_owned()));
+    assert_eq!(error.span, Span::new(12, 13, 1, 13));
+}
+
+#[test]
+fn test_redeclared_functions_and_classes_error() {
+    for (source, name, line) in [
+        ("fun f() {}\nfun f() {}", "f", 2),
+        ("let f = 1;\nclass f {}", "f", 2),
+        ("class A {}\nfun A() {}", "A", 2),
+        ("fun print(a) {}", "print", 1),
+        ("class exit {}", "exit", 1),
+        ("fun g() {\n    fun h() {}\n    fun h() {}\n}", "h", 3),
+    ] {
+        let error = resolve_error(source);
+
+        assert_eq!(
+            error.kind,
+            ErrorKind::AlreadyDeclared(name.to_owned()),
+            "{source:?}"
+        );
+        assert_eq!(error.span.line, line, "{source:?}");
+    }
+
+    // A function can still shadow one from an enclosing scope
+    resolve_source("fun f() {}\nfun g() {\n    fun f() {}\n    f();\n}");
+}
+
+#[test]
+fn test_duplicate_method_errors() {
+    let error = resolve_error("class A {\n    fun m() {}\n    fun m() {}\n}");
+
+    assert_eq!(error.kind, ErrorKind::DuplicateMethod("m".to_owned()));
+    assert_eq!(error.span, Span::new(33, 34, 3, 9));
+}
+
+#[test]
+fn test_every_error_is_reported_in_source_order() {
+    let errors =
+        resolve_errors("fun f(a, a) {\n    return this;\n}\nreturn 1;\n{\n    let b = b;\n}");
+
+    let kinds: Vec<(ErrorKind, usize)> = errors
+        .into_iter()
+        .map(|error| (error.kind, error.span.line))
+        .collect();
+
+    assert_eq!(
+        kinds,
+        vec![
+            (ErrorKind::DuplicateParameter("a".to_owned()), 1),
+            (ErrorKind::ThisOutsideClass, 2),
+            (ErrorKind::ReturnOutsideFunction, 4),
+            (ErrorKind::ReadInOwnInitializer("b".to_owned()), 6),
+        ]
+    );
+}
+
+/// Kind and line of every warning, in source order
+fn resolve_warnings(source: &str) -> Vec<(ErrorKind, usize)> {
+    resolve(&parse(scan_tokens(source).unwrap()))
+        .unwrap()
+        .warnings
+        .into_iter()
+        .map(|warning| (warning.kind, warning.span.line))
+        .collect()
+}
+
+#[test]
+fn test_unused_locals_and_parameters_warn() {
+    assert_eq!(
+        resolve_warnings("fun f(used, unused) {\n    let never = used;\n}"),
+        vec![
+            (ErrorKind::UnusedParameter("unused".to_owned()), 1),
+            (ErrorKind::UnusedVariable("never".to_owned()), 2),
+        ]
+    );
+}
+
+#[test]
+fn test_assigned_but_never_read_local_warns() {
+    assert_eq!(
+        resolve_warnings("{\n    let a = 1;\n    a = 2;\n}"),
+        vec![(ErrorKind::UnusedVariable("a".to_owned()), 2)]
+    );
+}
+
+#[test]
+fn test_underscore_names_and_globals_dont_warn_when_unused() {
+    assert_eq!(
+        resolve_warnings("let global = 1;\nfun f(_parameter) {\n    let _local = 1;\n}"),
+        vec![]
+    );
+}
+
+#[test]
+fn test_code_after_return_warns_once() {
+    assert_eq!(
+        resolve_warnings("fun f() {\n    return 1;\n    print(1);\n    print(2);\n}\nf();"),
+        vec![(ErrorKind::UnreachableCode, 3)]
+    );
+}
+
+#[test]
+fn test_shadowing_warns() {
+    assert_eq!(
+        resolve_warnings("let a = 1;\n{\n    let a = 2;\n    print(a);\n}"),
+        vec![(ErrorKind::ShadowedVariable("a".to_owned()), 3)]
+    );
+}
+
+#[test]
+fn test_constant_if_condition_warns() {
+    assert_eq!(
+        resolve_warnings("if (0) {}\nif nil {} else {}\nif args {}"),
+        vec![
+            (ErrorKind::ConstantCondition { value: false }, 1),
+            (ErrorKind::ConstantCondition { value: false }, 2),
+        ]
+    );
+}
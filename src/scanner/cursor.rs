@@ -0,0 +1,87 @@
+use std::str::Chars;
+
+use crate::span::Span;
+
+/// Iterates over the characters of the source while keeping track of where it is
+#[derive(Clone)]
+pub struct Cursor<'a> {
+    source: &'a str,
+    characters: Chars<'a>,
+    line: usize,
+    column: usize,
+}
+
+/// Where the cursor was at some point, used to build the span of a token once
+/// its last character has been read
+#[derive(Debug, Clone, Copy)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl<'a> Cursor<'a> {
+    /// Cursor at `offset` into the source, on the line and column it's at
+    pub fn starting_at(source: &'a str, offset: usize) -> Self {
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+
+        Cursor {
+            source,
+            characters: source[offset..].chars(),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+
+    pub fn peek(&self) -> Option<char> {
+        self.characters.clone().next()
+    }
+
+    /// Looks at the character after the one returned by `peek`
+    pub fn peek_next(&self) -> Option<char> {
+        self.characters.clone().nth(1)
+    }
+
+    pub fn next_if(&mut self, predicate: impl FnOnce(char) -> bool) -> Option<char> {
+        match self.peek() {
+            Some(character) if predicate(character) => self.next(),
+            _ => None,
+        }
+    }
+
+    pub fn mark(&self) -> Position {
+        Position {
+            offset: self.source.len() - self.characters.as_str().len(),
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Span from `start` up to, but not including, the next character
+    pub fn span_from(&self, start: Position) -> Span {
+        Span::new(start.offset, self.mark().offset, start.line, start.column)
+    }
+
+    /// Source text from `start` up to, but not including, the next character
+    pub fn slice_from(&self, start: Position) -> &'a str {
+        &self.source[start.offset..self.mark().offset]
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let character = self.characters.next()?;
+
+        if character == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        Some(character)
+    }
+}
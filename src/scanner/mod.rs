@@ -1,12 +1,15 @@
+mod cursor;
 pub mod tests;
 mod util;
 
-use std::{char, iter::Peekable, str::Chars};
+use std::char;
 
+use cursor::Cursor;
 use util::is_valid_literal_character;
 
 use crate::{
-    errors::ScanningError,
+    errors::{ErrorKind, LoxError},
+    symbol::Symbol,
     tokens::{Token, TokenType},
 };
 
@@ -15,22 +18,38 @@ use crate::{
 ///
 /// Errors found returns all errors in the scanning process, even if there are
 /// multiple scanning issues
-pub fn scan_tokens(source: &str) -> Result<Vec<Token>, Vec<ScanningError>> {
+///
+/// The last token is always `TokenType::Eof`, spanning nothing at the end of the source
+pub fn scan_tokens(source: &str) -> Result<Vec<Token<'_>>, Vec<LoxError>> {
+    scan_tokens_from(source, 0)
+}
+
+/// Scans the source like `scan_tokens`, starting at the `start` byte offset
+///
+/// Spans stay relative to the whole source, so a source can be scanned a part
+/// at a time as it grows
+pub fn scan_tokens_from(source: &str, start: usize) -> Result<Vec<Token<'_>>, Vec<LoxError>> {
     let mut tokens: Vec<Token> = Vec::new();
-    let mut parsing_errors: Vec<ScanningError> = Vec::new();
+    let mut parsing_errors: Vec<LoxError> = Vec::new();
+
+    let mut characters = Cursor::starting_at(source, start);
 
-    let mut line = 1;
-    let mut characters: Peekable<Chars> = source.chars().peekable();
+    // Skip a shebang so scripts can be run directly, the newline is left to be scanned
+    if start == 0 && source.starts_with("#!") {
+        while characters.next_if(|character| character != '\n').is_some() {}
+    }
 
     loop {
-        let token_type_result = scan_token(&mut characters, &mut line);
+        let start = characters.mark();
+
+        let token_type_result = scan_token(&mut characters);
 
         match token_type_result {
             TokenScanResult::Token(token_type) => {
                 let token = Token {
-                    token_type: token_type.clone(),
-                    lexeme: "".to_string(),
-                    line_number: line,
+                    token_type,
+                    lexeme: characters.slice_from(start),
+                    span: characters.span_from(start),
                 };
 
                 tokens.push(token);
@@ -39,13 +58,16 @@ pub fn scan_tokens(source: &str) -> Result<Vec<Token>, Vec<ScanningError>> {
                 continue;
             }
             TokenScanResult::Err(err) => {
-                parsing_errors.push(ScanningError {
-                    line_number: line,
-                    message: err.message,
-                });
+                parsing_errors.push(err);
                 continue;
             }
             TokenScanResult::End => {
+                tokens.push(Token {
+                    token_type: TokenType::Eof,
+                    lexeme: "",
+                    span: characters.span_from(start),
+                });
+
                 break;
             }
         }
@@ -63,13 +85,15 @@ pub enum TokenScanResult {
     Token(TokenType),
     Whitespace,
     End,
-    Err(ScanningError),
+    Err(LoxError),
 }
 
 /// Progresses characters past the next token and returns it in TokenType enum form
 ///
-/// Can error with ScanningError on malformed literals or non-ascii characters
-fn scan_token(characters: &mut Peekable<Chars>, line: &mut usize) -> TokenScanResult {
+/// Can error with LoxError on malformed literals or non-ascii characters
+fn scan_token(characters: &mut Cursor) -> TokenScanResult {
+    let start = characters.mark();
+
     let Some(character) = characters.next() else {
         return TokenScanResult::End;
     };
@@ -125,7 +149,7 @@ fn scan_token(characters: &mut Peekable<Chars>, line: &mut usize) -> TokenScanRe
                 // When we see two slashes, ignore everything past it until we receive a newline
                 loop {
                     match characters.peek() {
-                        Some(char) if *char == '\n' => {
+                        Some('\n') => {
                             break;
                         }
                         Some(_) => {
@@ -146,16 +170,12 @@ fn scan_token(characters: &mut Peekable<Chars>, line: &mut usize) -> TokenScanRe
             loop {
                 match characters.next() {
                     Some('"') => break,
-                    Some('\n') => {
-                        *line += 1;
-                        contained_string.push('\n');
-                    }
                     Some(ch) => contained_string.push(ch),
                     None => {
-                        return Err(ScanningError {
-                            line_number: *line,
-                            message: "Closing quotation at end of string not found".to_string(),
-                        })
+                        return Err(LoxError::new(
+                            ErrorKind::UnterminatedString,
+                            characters.span_from(start),
+                        ))
                     }
                 }
             }
@@ -163,12 +183,7 @@ fn scan_token(characters: &mut Peekable<Chars>, line: &mut usize) -> TokenScanRe
             Token(TokenType::String(contained_string))
         }
 
-        '\n' => {
-            *line += 1;
-            Whitespace
-        }
-
-        ' ' | '\r' | '\t' => Whitespace,
+        ' ' | '\r' | '\t' | '\n' => Whitespace,
 
         number if number.is_ascii_digit() => {
             let mut contained_number_literal = String::from(number);
@@ -176,27 +191,21 @@ fn scan_token(characters: &mut Peekable<Chars>, line: &mut usize) -> TokenScanRe
             loop {
                 match characters.peek() {
                     Some(char) if char.is_ascii_digit() => {
-                        contained_number_literal.push(*char);
+                        contained_number_literal.push(char);
                         characters.next();
                     }
 
-                    Some(char) if *char == '.' => {
-                        // TODO: Figure out how to do this without cloning the iterator,
-                        // it will either be a little bit slower than not cloning
-                        // if it only clones the iterator logic, or VERY much slower
-                        // if it clones the iterator values with it, and i don't quite
-                        // know how to tell the difference
-                        let post_decimal_character: Option<char> = characters.clone().nth(1);
+                    Some('.') => {
+                        let post_decimal_character: Option<char> = characters.peek_next();
 
                         match post_decimal_character {
                             Some(unwrapped_post_decimal_character)
                                 if unwrapped_post_decimal_character.is_ascii_digit() =>
                             {
-                                let decimal_point = characters.next();
-
-                                contained_number_literal.push(decimal_point.unwrap());
+                                // The digit after the point is pushed by the next iteration
+                                characters.next();
 
-                                contained_number_literal.push(characters.next().unwrap());
+                                contained_number_literal.push('.');
                             }
                             _ => break,
                         }
@@ -208,37 +217,30 @@ fn scan_token(characters: &mut Peekable<Chars>, line: &mut usize) -> TokenScanRe
 
             match contained_number_literal.parse::<f64>() {
                 Ok(parsed) => Token(TokenType::Number(parsed)),
-                Result::Err(_) => {
-                    return Err(ScanningError {
-                        line_number: *line,
-                        message: "Failed to parse number".to_string(),
-                    })
-                }
+                Result::Err(_) => Err(LoxError::new(
+                    ErrorKind::InvalidNumber(contained_number_literal),
+                    characters.span_from(start),
+                )),
             }
         }
 
-        literal if literal.is_ascii_alphabetic() => {
-            let mut contained_literal = String::from(literal);
-
+        literal if literal.is_ascii_alphabetic() || literal == '_' => {
             while characters
-                .peek()
-                .is_some_and(|&char| is_valid_literal_character(&char))
-            {
-                let char = characters.next().unwrap();
-                contained_literal.push(char);
-            }
+                .next_if(|char| is_valid_literal_character(&char))
+                .is_some()
+            {}
 
-            match TokenType::from_literal(&contained_literal) {
+            let contained_literal = characters.slice_from(start);
+
+            match TokenType::from_literal(contained_literal) {
                 Some(keyword) => Token(keyword),
-                None => Token(TokenType::Identifier(contained_literal)),
+                None => Token(TokenType::Identifier(Symbol::intern(contained_literal))),
             }
         }
 
-        unrecognized_character => {
-            return Err(ScanningError {
-                line_number: *line,
-                message: format!("unrecognized character {}", unrecognized_character),
-            })
-        }
+        unrecognized_character => Err(LoxError::new(
+            ErrorKind::UnexpectedCharacter(unrecognized_character),
+            characters.span_from(start),
+        )),
     }
 }
@@ -1,27 +1,30 @@
 #![cfg(test)]
 
 use crate::{
-    scanner::{scan_token, scan_tokens, TokenScanResult},
+    errors::{ErrorKind, LoxError},
+    scanner::{cursor::Cursor, scan_token, scan_tokens, TokenScanResult},
+    span::Span,
+    symbol::Symbol,
     tokens::{Token, TokenType},
 };
 
 #[test]
 fn scan_single_token() {
-    let token = scan_token(&mut "{".chars().peekable(), &mut 0);
+    let token = scan_token(&mut Cursor::starting_at("{", 0));
 
     assert_eq!(token, TokenScanResult::Token(TokenType::LeftBrace))
 }
 
 #[test]
 fn scan_double_token() {
-    let token = scan_token(&mut "==".chars().peekable(), &mut 0);
+    let token = scan_token(&mut Cursor::starting_at("==", 0));
 
     assert_eq!(token, TokenScanResult::Token(TokenType::EqualEqual))
 }
 
 #[test]
 fn scan_single_number_literal() {
-    let token = scan_token(&mut "0".chars().peekable(), &mut 0);
+    let token = scan_token(&mut Cursor::starting_at("0", 0));
 
     assert_eq!(token, TokenScanResult::Token(TokenType::Number(0.0)))
 }
@@ -39,8 +42,8 @@ fn scan_multiple_tokens() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 1,
+                lexeme: "!",
+                span: Span::new(0, 1, 1, 1),
                 token_type: TokenType::Bang
             })
         )
@@ -50,8 +53,8 @@ fn scan_multiple_tokens() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 1,
+                lexeme: "==",
+                span: Span::new(2, 4, 1, 3),
                 token_type: TokenType::EqualEqual
             })
         )
@@ -61,8 +64,8 @@ fn scan_multiple_tokens() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 1,
+                lexeme: "\"hii\"",
+                span: Span::new(5, 10, 1, 6),
                 token_type: TokenType::String("hii".to_string())
             })
         )
@@ -72,8 +75,8 @@ fn scan_multiple_tokens() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 1,
+                lexeme: "5.2",
+                span: Span::new(11, 14, 1, 12),
                 token_type: TokenType::Number(5.2)
             })
         )
@@ -83,8 +86,8 @@ fn scan_multiple_tokens() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 1,
+                lexeme: "{",
+                span: Span::new(15, 16, 1, 16),
                 token_type: TokenType::LeftBrace
             })
         )
@@ -94,12 +97,23 @@ fn scan_multiple_tokens() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 1,
+                lexeme: "}",
+                span: Span::new(17, 18, 1, 18),
                 token_type: TokenType::RightBrace
             })
         )
     );
+
+    assert_eq!(
+        tokens.next(),
+        Some(
+            &(Token {
+                lexeme: "",
+                span: Span::new(29, 29, 1, 30),
+                token_type: TokenType::Eof
+            })
+        )
+    );
 }
 
 #[test]
@@ -122,8 +136,8 @@ fn scan_multiple_lines() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 2,
+                lexeme: "<",
+                span: Span::new(1, 2, 2, 1),
                 token_type: TokenType::Less
             })
         )
@@ -133,8 +147,8 @@ fn scan_multiple_lines() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 3,
+                lexeme: "!",
+                span: Span::new(3, 4, 3, 1),
                 token_type: TokenType::Bang
             })
         )
@@ -144,8 +158,8 @@ fn scan_multiple_lines() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 4,
+                lexeme: "(",
+                span: Span::new(39, 40, 4, 1),
                 token_type: TokenType::LeftParen
             })
         )
@@ -155,8 +169,8 @@ fn scan_multiple_lines() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 5,
+                lexeme: ")",
+                span: Span::new(41, 42, 5, 1),
                 token_type: TokenType::RightParen
             })
         )
@@ -176,8 +190,8 @@ fn scan_single_line_string() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 1,
+                lexeme: "\"inside string\"",
+                span: Span::new(0, 15, 1, 1),
                 token_type: TokenType::String("inside string".to_string()),
             })
         )
@@ -197,8 +211,8 @@ fn scan_multi_line_string() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 2,
+                lexeme: "\"inside\n string\"",
+                span: Span::new(0, 16, 1, 1),
                 token_type: TokenType::String("inside\n string".to_string()),
             })
         )
@@ -208,8 +222,8 @@ fn scan_multi_line_string() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 2,
+                lexeme: "}",
+                span: Span::new(16, 17, 2, 9),
                 token_type: TokenType::RightBrace,
             })
         )
@@ -229,8 +243,8 @@ fn scan_number_with_trailing_dot() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 1,
+                lexeme: "100",
+                span: Span::new(0, 3, 1, 1),
                 token_type: TokenType::Number(100.0),
             })
         )
@@ -240,8 +254,8 @@ fn scan_number_with_trailing_dot() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 1,
+                lexeme: ".",
+                span: Span::new(3, 4, 1, 4),
                 token_type: TokenType::Dot,
             })
         )
@@ -261,8 +275,8 @@ fn scan_decimal_number() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 1,
+                lexeme: "420.69",
+                span: Span::new(0, 6, 1, 1),
                 token_type: TokenType::Number(420.69),
             })
         )
@@ -282,8 +296,8 @@ fn scan_keywords() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 1,
+                lexeme: "return",
+                span: Span::new(0, 6, 1, 1),
                 token_type: TokenType::Return,
             })
         )
@@ -293,8 +307,8 @@ fn scan_keywords() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 1,
+                lexeme: "and",
+                span: Span::new(7, 10, 1, 8),
                 token_type: TokenType::And,
             })
         )
@@ -303,8 +317,8 @@ fn scan_keywords() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 1,
+                lexeme: "let",
+                span: Span::new(11, 14, 1, 12),
                 token_type: TokenType::Let,
             })
         )
@@ -324,8 +338,8 @@ fn scan_identifiers() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 1,
+                lexeme: "fun",
+                span: Span::new(0, 3, 1, 1),
                 token_type: TokenType::Fun,
             })
         )
@@ -335,9 +349,9 @@ fn scan_identifiers() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 1,
-                token_type: TokenType::Identifier("epic".to_string()),
+                lexeme: "epic",
+                span: Span::new(4, 8, 1, 5),
+                token_type: TokenType::Identifier(Symbol::intern("epic")),
             })
         )
     );
@@ -346,10 +360,65 @@ fn scan_identifiers() {
         tokens.next(),
         Some(
             &(Token {
-                lexeme: "".to_string(),
-                line_number: 1,
-                token_type: TokenType::Identifier("complex_char".to_string()),
+                lexeme: "complex_char",
+                span: Span::new(9, 21, 1, 10),
+                token_type: TokenType::Identifier(Symbol::intern("complex_char")),
             })
         )
     );
 }
+
+#[test]
+fn scan_identifier_starting_with_underscore() {
+    let tokens = scan_tokens("_unused").unwrap();
+
+    assert_eq!(
+        tokens[0].token_type,
+        TokenType::Identifier(Symbol::intern("_unused"))
+    );
+}
+
+#[test]
+fn scan_skips_shebang_line() {
+    let tokens = scan_tokens("#!/usr/bin/env rust-lox\nnil").unwrap();
+
+    assert_eq!(
+        tokens,
+        vec![
+            Token {
+                lexeme: "nil",
+                span: Span::new(24, 27, 2, 1),
+                token_type: TokenType::Nil
+            },
+            Token {
+                lexeme: "",
+                span: Span::new(27, 27, 2, 4),
+                token_type: TokenType::Eof
+            }
+        ]
+    );
+}
+
+#[test]
+fn scan_keeps_source_text_as_lexeme() {
+    let tokens = scan_tokens("1.50 1.5 \"a\"").unwrap();
+
+    let lexemes: Vec<&str> = tokens.iter().map(|token| token.lexeme).collect();
+
+    assert_eq!(lexemes, vec!["1.50", "1.5", "\"a\"", ""]);
+    assert_eq!(tokens[0].token_type, tokens[1].token_type);
+}
+
+#[test]
+fn scan_unterminated_string_error() {
+    let errors = scan_tokens("\"never closed").unwrap_err();
+
+    assert_eq!(
+        errors,
+        vec![LoxError::new(
+            ErrorKind::UnterminatedString,
+            Span::new(0, 13, 1, 1)
+        )]
+    );
+    assert_eq!(errors[0].code(), "E0102");
+}
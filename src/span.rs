@@ -0,0 +1,74 @@
+use core::fmt;
+use std::fmt::{Display, Formatter};
+
+/// Location of a piece of source code
+///
+/// `start` and `end` are byte offsets into the source with `end` being exclusive,
+/// `line` and `column` are where `start` is, both counting from 1
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Span {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    /// Creates a span covering both spans and everything between them
+    pub fn to(&self, other: Span) -> Span {
+        let (first, last) = if self.start <= other.start {
+            (self, &other)
+        } else {
+            (&other, self)
+        };
+
+        Span {
+            start: first.start,
+            end: first.end.max(last.end),
+            line: first.line,
+            column: first.column,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Span;
+
+    #[test]
+    fn spans_can_be_joined() {
+        let left = Span::new(4, 6, 1, 5);
+        let right = Span::new(10, 12, 2, 3);
+
+        assert_eq!(left.to(right), Span::new(4, 12, 1, 5));
+        assert_eq!(right.to(left), Span::new(4, 12, 1, 5));
+    }
+
+    #[test]
+    fn span_can_be_displayed() {
+        assert_eq!(Span::new(4, 6, 3, 2).to_string(), "3:2");
+    }
+}
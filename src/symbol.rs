@@ -0,0 +1,170 @@
+//! Interned names, so identifiers are stored and compared as integers rather
+//! than as strings cloned into every token, node and scope
+
+use core::fmt;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt::{Debug, Display, Formatter},
+    marker::PhantomData,
+    rc::Rc,
+};
+
+/// A name interned on the current thread
+///
+/// The same name always interns to the same symbol while an `Interner` on the
+/// thread keeps its names alive. Symbols are indices into the thread's names, so
+/// they compare and hash as integers and read the name back without locking
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol {
+    index: u32,
+    // Indices only mean something on the thread that interned them
+    _thread: PhantomData<Rc<()>>,
+}
+
+#[derive(Default)]
+struct Names {
+    symbols: HashMap<Rc<str>, Symbol>,
+    names: Vec<Rc<str>>,
+}
+
+thread_local! {
+    static NAMES: RefCell<Names> = RefCell::default();
+    /// How many interners on this thread are keeping its names alive
+    static INTERNERS: Cell<usize> = const { Cell::new(0) };
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        NAMES.with_borrow_mut(|names| {
+            if let Some(symbol) = names.symbols.get(name) {
+                return *symbol;
+            }
+
+            let symbol = Symbol {
+                index: u32::try_from(names.names.len()).expect("too many names to intern"),
+                _thread: PhantomData,
+            };
+
+            let name: Rc<str> = Rc::from(name);
+
+            names.names.push(name.clone());
+            names.symbols.insert(name, symbol);
+
+            symbol
+        })
+    }
+
+    pub fn as_str(self) -> Rc<str> {
+        self.with_name(Rc::clone)
+    }
+
+    fn with_name<T>(self, read: impl FnOnce(&Rc<str>) -> T) -> T {
+        NAMES.with_borrow(|names| {
+            read(
+                names
+                    .names
+                    .get(self.index as usize)
+                    .expect("symbol outlived the interner it was interned with"),
+            )
+        })
+    }
+}
+
+/// Keeps the names interned on this thread alive for as long as it is
+///
+/// Every run and session holds one, and the names are freed once the last one
+/// on the thread is dropped, so symbols mustn't outlive the interner they were
+/// interned under. Names interned while no interner is alive are freed along
+/// with the next one, or when the thread ends
+pub struct Interner {
+    _thread: PhantomData<Rc<()>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        INTERNERS.set(INTERNERS.get() + 1);
+
+        Interner {
+            _thread: PhantomData,
+        }
+    }
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        Interner::new()
+    }
+}
+
+impl Drop for Interner {
+    fn drop(&mut self) {
+        let remaining = INTERNERS.get() - 1;
+
+        INTERNERS.set(remaining);
+
+        if remaining == 0 {
+            NAMES.take();
+        }
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::intern(name)
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.with_name(|name| **name == **other)
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.with_name(|name| f.write_str(name))
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.with_name(|name| write!(f, "{name:?}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Interner, Symbol};
+
+    #[test]
+    fn interning_the_same_name_gives_the_same_symbol() {
+        let _interner = Interner::new();
+
+        let first = Symbol::intern("interned_name");
+
+        assert_eq!(first, Symbol::intern(&String::from("interned_name")));
+        assert_ne!(first, Symbol::intern("other_name"));
+        assert_eq!(&*first.as_str(), "interned_name");
+    }
+
+    #[test]
+    fn names_are_freed_with_the_last_interner() {
+        let outer = Interner::new();
+        let inner = Interner::new();
+
+        let first = Symbol::intern("first_name");
+
+        drop(inner);
+
+        assert_eq!(first.to_string(), "first_name");
+
+        drop(outer);
+
+        let _interner = Interner::new();
+
+        // The name's index is reused by whichever name is interned next
+        assert_eq!(Symbol::intern("second_name"), first);
+        assert_eq!(first.to_string(), "second_name");
+    }
+}
@@ -1,8 +1,197 @@
 #![cfg(test)]
 
-use crate::run;
+use crate::{
+    interpreter::limits::{CancellationToken, Limits},
+    repl::run_lines,
+    check, check_with_warnings, parser, resolver, run, run_with_args, run_with_backend,
+    run_with_limits, scanner, Backend, RunStatus, Session, WarningOptions,
+};
 
 #[test]
 fn test_empty_run() {
-    run("")
+    assert_eq!(run(""), RunStatus::Success)
+}
+
+#[test]
+fn test_scanning_error_is_compile_error() {
+    assert_eq!(run("\"unterminated"), RunStatus::CompileError)
+}
+
+#[test]
+fn test_parsing_error_is_compile_error() {
+    assert_eq!(run("let x = ;"), RunStatus::CompileError)
+}
+
+#[test]
+fn test_nested_parsing_error_is_compile_error() {
+    assert_eq!(run("fun f() { let x = ; }"), RunStatus::CompileError)
+}
+
+#[test]
+fn test_runtime_error_status() {
+    assert_eq!(run("1 + nil;"), RunStatus::RuntimeError)
+}
+
+#[test]
+fn test_runaway_recursion_is_runtime_error() {
+    assert_eq!(run("fun f() { return f(); } f();"), RunStatus::RuntimeError)
+}
+
+#[test]
+fn test_cancelled_run_is_interrupted() {
+    let cancellation = CancellationToken::new();
+    cancellation.cancel();
+
+    let limits = Limits {
+        cancellation: Some(cancellation),
+        ..Limits::default()
+    };
+
+    assert_eq!(
+        run_with_limits("while true {}", vec![], limits),
+        RunStatus::Interrupted
+    );
+    assert_eq!(RunStatus::Interrupted.exit_code(), 130);
+}
+
+#[test]
+fn test_vm_backend_status() {
+    let run_vm = |source| run_with_backend(source, vec![], Limits::default(), Backend::Vm);
+
+    assert_eq!(run_vm("print(1 + 2);"), RunStatus::Success);
+    assert_eq!(run_vm("1 + nil;"), RunStatus::RuntimeError);
+    assert_eq!(run_vm("exit(3);"), RunStatus::Exited(3));
+    assert_eq!("vm".parse(), Ok(Backend::Vm));
+}
+
+#[test]
+fn test_session_keeps_globals_between_runs() {
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let mut session = Session::new(vec![], Limits::default(), backend);
+
+        assert_eq!(session.run("let a = 1;"), RunStatus::Success);
+        assert_eq!(session.run("exit(a + 2);"), RunStatus::Exited(3));
+
+        // Declared globals that were never defined are forgotten, along with
+        // what they were resolved to
+        assert_eq!(session.run("fun f() { return later; }"), RunStatus::Success);
+        assert_eq!(session.run("f();"), RunStatus::RuntimeError);
+        assert_eq!(
+            session.run("let b = 2;\nlet later = 1 + nil;"),
+            RunStatus::RuntimeError
+        );
+        assert_eq!(session.run("let taken = 9;"), RunStatus::Success);
+        assert_eq!(session.run("let later = 4;"), RunStatus::Success);
+        assert_eq!(session.run("exit(f() + b);"), RunStatus::Exited(6));
+
+        assert_eq!(session.run("let c = 1;\nlet c = 2;"), RunStatus::CompileError);
+        assert_eq!(session.run("let c = 5;\nexit(c);"), RunStatus::Exited(5));
+    }
+}
+
+#[test]
+fn test_repl_errors_in_functions_from_earlier_lines() {
+    // The error is in the first line, which is shorter than the second and
+    // would be cut through the middle of a character when shown against it
+    let input = "fun f() { let q = 1; return q + nil; }\nlet s = \"éééééééééééééééééééééééééééééééé\"; f();\n";
+
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        assert_eq!(
+            run_lines(input.as_bytes(), std::io::sink(), Limits::default(), backend),
+            RunStatus::Success
+        );
+    }
+}
+
+#[test]
+fn test_warnings_only_fail_check_when_denied() {
+    let source = "fun f(unused) {}";
+
+    let mut options = WarningOptions {
+        deny: true,
+        ..WarningOptions::default()
+    };
+
+    assert_eq!(check(source), RunStatus::Success);
+    assert_eq!(check_with_warnings(source, &options), RunStatus::CompileError);
+
+    assert_eq!(options.allow("W0302"), Ok(()));
+    assert_eq!(check_with_warnings(source, &options), RunStatus::Success);
+    assert!(options.allow("W9999").is_err());
+}
+
+#[test]
+fn test_long_flat_expressions_run() {
+    let sum = vec!["1"; 70].join(" + ");
+    let conditions = vec!["false"; 70].join(" or ");
+
+    assert_eq!(run(&format!("print({sum});")), RunStatus::Success);
+    assert_eq!(run(&format!("print({conditions});")), RunStatus::Success);
+}
+
+#[test]
+fn test_exit_status() {
+    assert_eq!(run("exit(3); print(\"unreachable\");"), RunStatus::Exited(3));
+    assert_eq!(RunStatus::Exited(3).exit_code(), 3);
+}
+
+#[test]
+fn test_exit_codes_outside_a_byte_error() {
+    assert_eq!(run("exit(255);"), RunStatus::Exited(255));
+    assert_eq!(run("exit(256);"), RunStatus::RuntimeError);
+    assert_eq!(run("exit(0 - 1);"), RunStatus::RuntimeError);
+    assert_eq!(
+        run_with_backend("exit(256);", vec![], Limits::default(), Backend::Vm),
+        RunStatus::RuntimeError
+    );
+}
+
+#[test]
+fn test_script_arguments() {
+    assert_eq!(
+        run_with_args(
+            "let first = args.get(0);\nif first == \"first\" { exit(args.length); }",
+            vec!["first".to_owned(), "second".to_owned()]
+        ),
+        RunStatus::Exited(2)
+    );
+}
+
+/// Cheap deterministic generator so failures can be reproduced from the seed
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+
+    *state
+}
+
+/// Same pipeline as the `fuzz/` harness, over sources stitched together from
+/// fragments of real programs and random bytes
+#[test]
+fn test_malformed_input_never_panics() {
+    const FRAGMENTS: [&str; 24] = [
+        "let", "fun", "class", "if", "else", "while", "return", "this", "(", ")", "{", "}", ",",
+        ".", ";", "=", "+", "!", "==", "a", "1.5", "\"s\"", "nil", " ",
+    ];
+
+    let mut state = 0x2545_f491_4f6c_dd1d;
+
+    for _ in 0..2000 {
+        let mut source = String::new();
+
+        for _ in 0..xorshift(&mut state) % 40 {
+            let choice = xorshift(&mut state) as usize;
+
+            if choice.is_multiple_of(5) {
+                source.push(char::from((choice >> 8) as u8));
+            } else {
+                source += FRAGMENTS[(choice >> 8) % FRAGMENTS.len()];
+            }
+        }
+
+        if let Ok(tokens) = scanner::scan_tokens(&source) {
+            let _ = resolver::resolve(&parser::parse(tokens));
+        }
+    }
 }
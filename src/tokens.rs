@@ -1,60 +1,57 @@
 use core::fmt;
-use std::{
-    collections::HashMap,
-    fmt::{Debug, Display, Formatter},
-};
+use std::fmt::{Debug, Display, Formatter};
+
+use crate::{span::Span, symbol::Symbol};
 
 #[derive(PartialEq, Clone)]
-pub struct Token {
+pub struct Token<'a> {
     pub token_type: TokenType,
-    pub lexeme: String,
-    pub line_number: usize,
+    /// The exact text in the source that was scanned into this token
+    pub lexeme: &'a str,
+    pub span: Span,
     // TODO: Object literal
 }
 
-impl Debug for Token {
+impl Debug for Token<'_> {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(
             f,
-            "{:?} @ line={} : {:?}",
-            self.token_type, self.line_number, self.lexeme
+            "{:?} @ line={} column={} : {:?}",
+            self.token_type, self.span.line, self.span.column, self.lexeme
         )
     }
 }
 
-impl Display for Token {
+impl Display for Token<'_> {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(
             f,
-            "{:?} @ line={} : {:?}",
-            self.token_type, self.line_number, self.lexeme
+            "{:?} @ line={} column={} : {:?}",
+            self.token_type, self.span.line, self.span.column, self.lexeme
         )
     }
 }
 
 impl TokenType {
     pub fn from_literal(literal: &str) -> Option<TokenType> {
-        // TODO: Stop this HashMap from being built every time we run TokenType
-        let mut keyword_lookup: HashMap<&str, TokenType> = HashMap::new();
-
-        keyword_lookup.insert("class", TokenType::Class);
-        keyword_lookup.insert("else", TokenType::Else);
-        keyword_lookup.insert("false", TokenType::False);
-        keyword_lookup.insert("fun", TokenType::Fun);
-        keyword_lookup.insert("for", TokenType::For);
-        keyword_lookup.insert("if", TokenType::If);
-        keyword_lookup.insert("nil", TokenType::Nil);
-        keyword_lookup.insert("or", TokenType::Or);
-        keyword_lookup.insert("and", TokenType::And);
-        keyword_lookup.insert("return", TokenType::Return);
-        keyword_lookup.insert("super", TokenType::Super);
-        keyword_lookup.insert("this", TokenType::This);
-        keyword_lookup.insert("true", TokenType::True);
-        keyword_lookup.insert("let", TokenType::Let);
-        keyword_lookup.insert("while", TokenType::While);
-        keyword_lookup.insert("and", TokenType::And);
-
-        return keyword_lookup.get(literal).cloned();
+        match literal {
+            "and" => Some(TokenType::And),
+            "class" => Some(TokenType::Class),
+            "else" => Some(TokenType::Else),
+            "false" => Some(TokenType::False),
+            "fun" => Some(TokenType::Fun),
+            "for" => Some(TokenType::For),
+            "if" => Some(TokenType::If),
+            "let" => Some(TokenType::Let),
+            "nil" => Some(TokenType::Nil),
+            "or" => Some(TokenType::Or),
+            "return" => Some(TokenType::Return),
+            "super" => Some(TokenType::Super),
+            "this" => Some(TokenType::This),
+            "true" => Some(TokenType::True),
+            "while" => Some(TokenType::While),
+            _ => None,
+        }
     }
 }
 
@@ -84,7 +81,7 @@ pub enum TokenType {
     LessEqual,
 
     // Literals.
-    Identifier(String),
+    Identifier(Symbol),
     String(String),
     Number(f64),
 
@@ -105,20 +102,22 @@ pub enum TokenType {
     True,
     Let,
     While,
+
+    Eof,
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::tokens::Token;
+    use crate::{span::Span, tokens::Token};
 
     #[test]
     fn token_can_be_displayed() {
         let token = Token {
-            lexeme: "for".to_owned(),
+            lexeme: "for",
             token_type: crate::tokens::TokenType::For,
-            line_number: 1,
+            span: Span::new(4, 7, 1, 5),
         };
 
-        assert_eq!(token.to_string(), "For @ line=1 : \"for\"");
+        assert_eq!(token.to_string(), "For @ line=1 column=5 : \"for\"");
     }
 }
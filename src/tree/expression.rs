@@ -1,25 +1,33 @@
 use core::fmt;
 use std::fmt::{Display, Formatter};
 
-use crate::interpreter::functions::Reference;
+use crate::{interpreter::functions::Reference, span::Span, symbol::Symbol};
 
+/// Each node carries the span of the token it was parsed around, e.g. the
+/// operator of an operation or the opening parenthesis of a call
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expression {
-    Literal(ExpressionLiteral),
+    Literal(Span, ExpressionLiteral),
     Operation(Operation),
-    Grouping(Box<Expression>),
+    Grouping(Span, Box<Expression>),
     Variable(ExpressionVariable),
     Assign(ExpressionVariable, Box<Expression>),
-    Call(usize, Box<Expression>, Vec<Expression>),
-    Get(usize, Box<Expression>, String),
-    Set(usize, Box<Expression>, String, Box<Expression>),
-    This,
+    Call(Span, Box<Expression>, Vec<Expression>),
+    Get(Span, Box<Expression>, Symbol),
+    Set(Span, Box<Expression>, Symbol, Box<Expression>),
+    /// `this` inside a method, resolved like a variable named `this`
+    This(ExpressionVariable),
 }
 
+/// Identifies a node of the tree, unique within the steps of a single parse
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub struct NodeId(pub usize);
+
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
 pub struct ExpressionVariable {
-    pub line_number: usize,
-    pub identifier_name: String,
+    pub id: NodeId,
+    pub span: Span,
+    pub identifier_name: Symbol,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -32,6 +40,83 @@ pub enum ExpressionLiteral {
     Reference(Reference),
 }
 
+impl Expression {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Literal(span, _)
+            | Expression::Grouping(span, _)
+            | Expression::Call(span, _, _)
+            | Expression::Get(span, _, _)
+            | Expression::Set(span, _, _, _) => *span,
+            Expression::Variable(variable)
+            | Expression::Assign(variable, _)
+            | Expression::This(variable) => variable.span,
+            Expression::Operation(operation) => operation.span(),
+        }
+    }
+}
+
+impl Operation {
+    pub fn span(&self) -> Span {
+        match self {
+            Operation::Negate(operation) | Operation::Not(operation) => operation.span,
+            Operation::Equal(operation) | Operation::NotEqual(operation) => operation.span,
+            Operation::Less(operation)
+            | Operation::LessEqual(operation)
+            | Operation::Greater(operation)
+            | Operation::GreaterEqual(operation) => operation.span,
+            Operation::Plus(operation) | Operation::Minus(operation) => operation.span,
+            Operation::Multiply(operation) | Operation::Divide(operation) => operation.span,
+            Operation::And(operation) | Operation::Or(operation) => operation.span,
+        }
+    }
+
+    /// The expressions the operation is applied to, left to right
+    pub fn operands(&self) -> Vec<&Expression> {
+        match self {
+            Operation::Negate(unary) | Operation::Not(unary) => vec![&unary.operand],
+            Operation::Equal(binary) | Operation::NotEqual(binary) => {
+                vec![&binary.left, &binary.right]
+            }
+            Operation::Less(binary)
+            | Operation::LessEqual(binary)
+            | Operation::Greater(binary)
+            | Operation::GreaterEqual(binary) => vec![&binary.left, &binary.right],
+            Operation::Plus(binary) | Operation::Minus(binary) => {
+                vec![&binary.left, &binary.right]
+            }
+            Operation::Multiply(binary) | Operation::Divide(binary) => {
+                vec![&binary.left, &binary.right]
+            }
+            Operation::And(logical) | Operation::Or(logical) => {
+                vec![&logical.left, &logical.right]
+            }
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Expression> {
+        match self {
+            Operation::Negate(unary) | Operation::Not(unary) => vec![&mut unary.operand],
+            Operation::Equal(binary) | Operation::NotEqual(binary) => {
+                vec![&mut binary.left, &mut binary.right]
+            }
+            Operation::Less(binary)
+            | Operation::LessEqual(binary)
+            | Operation::Greater(binary)
+            | Operation::GreaterEqual(binary) => vec![&mut binary.left, &mut binary.right],
+            Operation::Plus(binary) | Operation::Minus(binary) => {
+                vec![&mut binary.left, &mut binary.right]
+            }
+            Operation::Multiply(binary) | Operation::Divide(binary) => {
+                vec![&mut binary.left, &mut binary.right]
+            }
+            Operation::And(logical) | Operation::Or(logical) => {
+                vec![&mut logical.left, &mut logical.right]
+            }
+        }
+    }
+}
+
 impl ExpressionLiteral {
     pub fn is_same_type(&self, other: &ExpressionLiteral) -> bool {
         match self {
@@ -50,41 +135,41 @@ impl ExpressionLiteral {
 pub struct EqualityOperation {
     pub left: Box<Expression>,
     pub right: Box<Expression>,
-    pub line_number: usize,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct ComparisonOperation {
     pub left: Box<Expression>,
     pub right: Box<Expression>,
-    pub line_number: usize,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct FactorOperation {
     pub left: Box<Expression>,
     pub right: Box<Expression>,
-    pub line_number: usize,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct TermOperation {
     pub left: Box<Expression>,
     pub right: Box<Expression>,
-    pub line_number: usize,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct UnaryOperation {
     pub operand: Box<Expression>,
-    pub line_number: usize,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct LogicalOperation {
     pub left: Box<Expression>,
     pub right: Box<Expression>,
-    pub line_number: usize,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -111,14 +196,14 @@ pub enum Operation {
 impl Display for Expression {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
-            Expression::Literal(literal) => write!(f, "{}", *literal),
+            Expression::Literal(_span, literal) => write!(f, "{}", *literal),
             Expression::Operation(operation) => write!(f, "{}", *operation),
-            Expression::Grouping(expression) => write!(f, "( {} )", *expression),
+            Expression::Grouping(_span, expression) => write!(f, "( {} )", *expression),
             Expression::Variable(name) => write!(f, "( *{} )", name.identifier_name),
             Expression::Assign(name, value) => {
                 write!(f, "( {} <-- {} )", name.identifier_name, value)
             }
-            Expression::Call(_line_number, callee, arguments) => write!(
+            Expression::Call(_span, callee, arguments) => write!(
                 f,
                 "( {callee} <-call-with- ( {} ))",
                 arguments
@@ -128,13 +213,13 @@ impl Display for Expression {
                         + ", "
                         + &cur_argument)
             ),
-            Expression::Get(_line_number, expression, identifier) => {
+            Expression::Get(_span, expression, identifier) => {
                 write!(f, "( {}.{} )", *expression, identifier)
             }
-            Expression::Set(_line_number, expression, identifier, value) => {
+            Expression::Set(_span, expression, identifier, value) => {
                 write!(f, "( {}.{} <-- {} )", *expression, identifier, *value)
             }
-            Expression::This => write!(f, "( this )"),
+            Expression::This(_) => write!(f, "( this )"),
         }
     }
 }
@@ -149,7 +234,11 @@ impl Display for ExpressionLiteral {
             ExpressionLiteral::Nil => write!(f, "nil"),
             ExpressionLiteral::Reference(reference) => match reference {
                 Reference::CallableReference(callable_reference) => {
-                    write!(f, "@Callable<Arity = {}>", callable_reference.arity)
+                    write!(
+                        f,
+                        "@Callable<Name = \"{}\", Arity = {}>",
+                        callable_reference.name, callable_reference.arity
+                    )
                 }
                 Reference::ClassReference(class_reference) => {
                     write!(f, "@Class<Name = \"{}\">", class_reference.name)
@@ -157,6 +246,17 @@ impl Display for ExpressionLiteral {
                 Reference::InstanceReference(instance_reference) => {
                     write!(f, "@Instance<Name = \"{}\">", instance_reference.class.name)
                 }
+                Reference::ListReference(list_reference) => write!(
+                    f,
+                    "[{}]",
+                    list_reference
+                        .items
+                        .borrow()
+                        .iter()
+                        .map(|item| item.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
             },
         }
     }
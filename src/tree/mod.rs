@@ -1,2 +1,3 @@
 pub mod expression;
 pub mod tests;
+pub mod visit;
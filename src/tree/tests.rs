@@ -1,26 +1,39 @@
 #![cfg(test)]
-use crate::tree::expression::{
-    EqualityOperation, Expression, ExpressionLiteral, ExpressionVariable, FactorOperation,
-    Operation, TermOperation, UnaryOperation,
+use crate::{
+    parser::{parse, ParsingResult},
+    scanner::scan_tokens,
+    span::Span,
+    symbol::Symbol,
+    tree::{
+        expression::{
+            EqualityOperation, Expression, ExpressionLiteral, ExpressionVariable, FactorOperation,
+            NodeId, Operation, TermOperation, UnaryOperation,
+        },
+        visit::{walk_expression, walk_expression_mut, MutVisitor, Visitor},
+    },
 };
 
 #[test]
 fn number_literal_can_be_pretty_printed() {
-    let expression = Expression::Literal(ExpressionLiteral::Number(420.6969));
+    let expression = Expression::Literal(Span::default(), ExpressionLiteral::Number(420.6969));
     assert_eq!(expression.to_string(), "420.70");
 }
 
 #[test]
 fn string_literal_can_be_pretty_printed() {
-    let expression = Expression::Literal(ExpressionLiteral::String("Test String".to_string()));
+    let expression = Expression::Literal(
+        Span::default(),
+        ExpressionLiteral::String("Test String".to_string()),
+    );
     assert_eq!(expression.to_string(), "\"Test String\"");
 }
 
 #[test]
 fn variable_reference_can_be_pretty_printed() {
     let expression = Expression::Variable(ExpressionVariable {
-        line_number: 0,
-        identifier_name: "epic".to_string(),
+        id: NodeId::default(),
+        span: Span::default(),
+        identifier_name: Symbol::intern("epic"),
     });
     assert_eq!(expression.to_string(), "( *epic )");
 }
@@ -28,11 +41,15 @@ fn variable_reference_can_be_pretty_printed() {
 #[test]
 fn simple_operation_can_be_pretty_printed() {
     let expression = Expression::Operation(Operation::Plus(TermOperation {
-        left: Box::from(Expression::Literal(ExpressionLiteral::Number(
-            10020030.3456,
-        ))),
-        right: Box::from(Expression::Literal(ExpressionLiteral::Number(5.2))),
-        line_number: 0,
+        left: Box::from(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(10020030.3456),
+        )),
+        right: Box::from(Expression::Literal(
+            Span::default(),
+            ExpressionLiteral::Number(5.2),
+        )),
+        span: Span::default(),
     }));
 
     assert_eq!(expression.to_string(), "( + 10020030.35 5.20 )");
@@ -42,31 +59,44 @@ fn simple_operation_can_be_pretty_printed() {
 fn deeply_nested_expression_can_be_pretty_printed() {
     let expression = Expression::Operation(Operation::Multiply(FactorOperation {
         left: Box::new(Expression::Operation(Operation::Divide(FactorOperation {
-            left: Box::new(Expression::Literal(ExpressionLiteral::Number(
-                10020030.3456,
-            ))),
+            left: Box::new(Expression::Literal(
+                Span::default(),
+                ExpressionLiteral::Number(10020030.3456),
+            )),
             right: Box::new(Expression::Operation(Operation::Plus(TermOperation {
-                left: Box::new(Expression::Literal(ExpressionLiteral::True)),
+                left: Box::new(Expression::Literal(
+                    Span::default(),
+                    ExpressionLiteral::True,
+                )),
                 right: Box::new(Expression::Operation(Operation::Negate(UnaryOperation {
-                    operand: Box::new(Expression::Literal(ExpressionLiteral::Number(120341.2332))),
-                    line_number: 1,
+                    operand: Box::new(Expression::Literal(
+                        Span::default(),
+                        ExpressionLiteral::Number(120341.2332),
+                    )),
+                    span: Span::default(),
                 }))),
-                line_number: 1,
+                span: Span::default(),
             }))),
 
-            line_number: 0,
+            span: Span::default(),
         }))),
 
         right: Box::new(Expression::Operation(Operation::Equal(EqualityOperation {
-            left: Box::new(Expression::Literal(ExpressionLiteral::True)),
+            left: Box::new(Expression::Literal(
+                Span::default(),
+                ExpressionLiteral::True,
+            )),
             right: Box::new(Expression::Operation(Operation::Negate(UnaryOperation {
-                operand: Box::new(Expression::Literal(ExpressionLiteral::Number(120341.2332))),
-                line_number: 0,
+                operand: Box::new(Expression::Literal(
+                    Span::default(),
+                    ExpressionLiteral::Number(120341.2332),
+                )),
+                span: Span::default(),
             }))),
-            line_number: 0,
+            span: Span::default(),
         }))),
 
-        line_number: 0,
+        span: Span::default(),
     }));
 
     assert_eq!(
@@ -74,3 +104,92 @@ fn deeply_nested_expression_can_be_pretty_printed() {
         "( * ( / 10020030.35 ( + true ( - 120341.23 ) ) ) ( == true ( - 120341.23 ) ) )"
     );
 }
+
+fn parse_source(source: &str) -> Vec<ParsingResult> {
+    parse(scan_tokens(source).unwrap())
+}
+
+/// Collects the name of every variable read, in the order they're visited
+struct VariableReads {
+    names: Vec<Symbol>,
+}
+
+impl Visitor<'_> for VariableReads {
+    type Error = ();
+
+    fn visit_expression(&mut self, expression: &Expression) -> Result<(), ()> {
+        if let Expression::Variable(variable) = expression {
+            self.names.push(variable.identifier_name);
+        }
+
+        walk_expression(self, expression)
+    }
+}
+
+#[test]
+fn visitor_reaches_every_nested_expression() {
+    let steps = parse_source(
+        "let a = b + (c);\nif d { e(f); } else { while g { h.i = j; } }\nfun k(l) { return m; }\nclass N { fun o() { p; } }",
+    );
+
+    let mut reads = VariableReads { names: vec![] };
+
+    assert_eq!(reads.visit_steps(&steps), Ok(()));
+    assert_eq!(
+        reads.names,
+        vec!["b", "c", "d", "e", "f", "g", "h", "j", "m", "p"]
+    );
+}
+
+/// Stops at the first call it finds
+struct FirstCall;
+
+impl Visitor<'_> for FirstCall {
+    type Error = Span;
+
+    fn visit_expression(&mut self, expression: &Expression) -> Result<(), Span> {
+        if let Expression::Call(span, _, _) = expression {
+            return Err(*span);
+        }
+
+        walk_expression(self, expression)
+    }
+}
+
+#[test]
+fn visitor_stops_at_the_first_error() {
+    let steps = parse_source("let a = 1;\nprint(a);\nprint(a);");
+
+    let span = FirstCall.visit_steps(&steps).unwrap_err();
+
+    assert_eq!(span, Span::new(16, 17, 2, 6));
+}
+
+/// Prefixes every variable read
+struct Rename;
+
+impl MutVisitor for Rename {
+    fn visit_expression(&mut self, expression: &mut Expression) {
+        if let Expression::Variable(variable) = expression {
+            variable.identifier_name =
+                Symbol::intern(&format!("renamed_{}", variable.identifier_name));
+        }
+
+        walk_expression_mut(self, expression)
+    }
+}
+
+#[test]
+fn mut_visitor_rewrites_nodes_in_place() {
+    let mut steps = parse_source("fun f(x) { return x + y; }");
+
+    Rename.visit_steps(&mut steps);
+
+    assert_eq!(
+        steps[0].as_ref().unwrap().to_string(),
+        parse_source("fun f(x) { return renamed_x + renamed_y; }")[0]
+            .as_ref()
+            .unwrap()
+            .to_string()
+    );
+}
@@ -0,0 +1,261 @@
+//! Traversal of the parsed tree, so that a pass only has to handle the nodes it
+//! cares about
+//!
+//! Every method of `Visitor` and `MutVisitor` defaults to the matching `walk`
+//! function, which visits each child of the node in source order. Overriding a
+//! method and calling the `walk` function from it keeps the traversal going
+//! below that node, leaving it out skips the node's children.
+
+use std::rc::Rc;
+
+use crate::{
+    errors::LoxError,
+    parser::{
+        statements::{FunStatement, Statement},
+        ParsedStep, ParsingResult, StepKind,
+    },
+    tree::expression::Expression,
+};
+
+/// Visits the tree by reference, stopping at the first error returned
+///
+/// `'ast` is the lifetime of the tree, letting visitors hold on to the nodes they visit
+pub trait Visitor<'ast>: Sized {
+    type Error;
+
+    fn visit_steps(&mut self, steps: &'ast [ParsingResult]) -> Result<(), Self::Error> {
+        walk_steps(self, steps)
+    }
+
+    /// Called for steps that failed to parse, which have no children
+    fn visit_parsing_error(&mut self, _error: &'ast LoxError) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_step(&mut self, step: &'ast ParsedStep) -> Result<(), Self::Error> {
+        walk_step(self, step)
+    }
+
+    /// Called with the steps of a block, including the bodies of functions,
+    /// loops and `if` statements
+    fn visit_block(&mut self, steps: &'ast [ParsingResult]) -> Result<(), Self::Error> {
+        self.visit_steps(steps)
+    }
+
+    fn visit_statement(&mut self, statement: &'ast Statement) -> Result<(), Self::Error> {
+        walk_statement(self, statement)
+    }
+
+    /// Called for both functions and class methods
+    fn visit_function(&mut self, function: &'ast FunStatement) -> Result<(), Self::Error> {
+        walk_function(self, function)
+    }
+
+    fn visit_expression(&mut self, expression: &'ast Expression) -> Result<(), Self::Error> {
+        walk_expression(self, expression)
+    }
+}
+
+pub fn walk_steps<'ast, V: Visitor<'ast>>(
+    visitor: &mut V,
+    steps: &'ast [ParsingResult],
+) -> Result<(), V::Error> {
+    for step in steps {
+        match step {
+            Ok(step) => visitor.visit_step(step)?,
+            Err(error) => visitor.visit_parsing_error(error)?,
+        }
+    }
+
+    Ok(())
+}
+
+pub fn walk_step<'ast, V: Visitor<'ast>>(
+    visitor: &mut V,
+    step: &'ast ParsedStep,
+) -> Result<(), V::Error> {
+    match &step.kind {
+        StepKind::Expression(expression) => visitor.visit_expression(expression),
+        StepKind::Statement(statement) => visitor.visit_statement(statement),
+        StepKind::Block(steps) => visitor.visit_block(steps),
+    }
+}
+
+pub fn walk_statement<'ast, V: Visitor<'ast>>(
+    visitor: &mut V,
+    statement: &'ast Statement,
+) -> Result<(), V::Error> {
+    match statement {
+        Statement::Variable(_, _, value) => visitor.visit_expression(value),
+        Statement::If(if_statement) => {
+            visitor.visit_expression(&if_statement.condition)?;
+            visitor.visit_step(&if_statement.then_statement)?;
+
+            match &if_statement.else_statement {
+                Some(else_statement) => visitor.visit_step(else_statement),
+                None => Ok(()),
+            }
+        }
+        Statement::While(while_statement) => {
+            visitor.visit_expression(&while_statement.condition)?;
+            visitor.visit_step(&while_statement.body)
+        }
+        Statement::Fun(function) => visitor.visit_function(function),
+        Statement::Return(_, value) => match value {
+            Some(value) => visitor.visit_expression(value),
+            None => Ok(()),
+        },
+        Statement::Class(class) => {
+            for method in &class.methods {
+                visitor.visit_function(method)?;
+            }
+
+            Ok(())
+        }
+    }
+}
+
+pub fn walk_function<'ast, V: Visitor<'ast>>(
+    visitor: &mut V,
+    function: &'ast FunStatement,
+) -> Result<(), V::Error> {
+    visitor.visit_step(&function.body)
+}
+
+pub fn walk_expression<'ast, V: Visitor<'ast>>(
+    visitor: &mut V,
+    expression: &'ast Expression,
+) -> Result<(), V::Error> {
+    match expression {
+        Expression::Literal(_, _) | Expression::Variable(_) | Expression::This(_) => Ok(()),
+        Expression::Operation(operation) => {
+            for operand in operation.operands() {
+                visitor.visit_expression(operand)?;
+            }
+
+            Ok(())
+        }
+        Expression::Grouping(_, grouped) => visitor.visit_expression(grouped),
+        Expression::Assign(_, value) => visitor.visit_expression(value),
+        Expression::Call(_, callee, arguments) => {
+            visitor.visit_expression(callee)?;
+
+            for argument in arguments {
+                visitor.visit_expression(argument)?;
+            }
+
+            Ok(())
+        }
+        Expression::Get(_, object, _) => visitor.visit_expression(object),
+        Expression::Set(_, object, _, value) => {
+            visitor.visit_expression(object)?;
+            visitor.visit_expression(value)
+        }
+    }
+}
+
+/// Visits the tree mutably, letting passes rewrite nodes in place
+///
+/// `visit_steps` gets the whole list, so steps can also be removed or replaced
+pub trait MutVisitor: Sized {
+    fn visit_steps(&mut self, steps: &mut Vec<ParsingResult>) {
+        walk_steps_mut(self, steps)
+    }
+
+    fn visit_step(&mut self, step: &mut ParsedStep) {
+        walk_step_mut(self, step)
+    }
+
+    /// Called with the steps of a block, including the bodies of functions,
+    /// loops and `if` statements
+    fn visit_block(&mut self, steps: &mut Vec<ParsingResult>) {
+        self.visit_steps(steps)
+    }
+
+    fn visit_statement(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement)
+    }
+
+    /// Called for both functions and class methods
+    fn visit_function(&mut self, function: &mut FunStatement) {
+        walk_function_mut(self, function)
+    }
+
+    fn visit_expression(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression)
+    }
+}
+
+/// Visits every step that parsed, steps that failed to parse are left alone
+pub fn walk_steps_mut<V: MutVisitor>(visitor: &mut V, steps: &mut [ParsingResult]) {
+    for step in steps.iter_mut().flatten() {
+        visitor.visit_step(step);
+    }
+}
+
+pub fn walk_step_mut<V: MutVisitor>(visitor: &mut V, step: &mut ParsedStep) {
+    match &mut step.kind {
+        StepKind::Expression(expression) => visitor.visit_expression(expression),
+        StepKind::Statement(statement) => visitor.visit_statement(statement),
+        StepKind::Block(steps) => visitor.visit_block(steps),
+    }
+}
+
+/// Functions shared with anything else are copied before being changed, see `Rc::make_mut`
+pub fn walk_statement_mut<V: MutVisitor>(visitor: &mut V, statement: &mut Statement) {
+    match statement {
+        Statement::Variable(_, _, value) => visitor.visit_expression(value),
+        Statement::If(if_statement) => {
+            visitor.visit_expression(&mut if_statement.condition);
+            visitor.visit_step(&mut if_statement.then_statement);
+
+            if let Some(else_statement) = &mut if_statement.else_statement {
+                visitor.visit_step(else_statement);
+            }
+        }
+        Statement::While(while_statement) => {
+            visitor.visit_expression(&mut while_statement.condition);
+            visitor.visit_step(&mut while_statement.body);
+        }
+        Statement::Fun(function) => visitor.visit_function(Rc::make_mut(function)),
+        Statement::Return(_, value) => {
+            if let Some(value) = value {
+                visitor.visit_expression(value);
+            }
+        }
+        Statement::Class(class) => {
+            for method in &mut class.methods {
+                visitor.visit_function(Rc::make_mut(method));
+            }
+        }
+    }
+}
+
+pub fn walk_function_mut<V: MutVisitor>(visitor: &mut V, function: &mut FunStatement) {
+    visitor.visit_step(&mut function.body)
+}
+
+pub fn walk_expression_mut<V: MutVisitor>(visitor: &mut V, expression: &mut Expression) {
+    match expression {
+        Expression::Literal(_, _) | Expression::Variable(_) | Expression::This(_) => {}
+        Expression::Operation(operation) => {
+            for operand in operation.operands_mut() {
+                visitor.visit_expression(operand);
+            }
+        }
+        Expression::Grouping(_, grouped) => visitor.visit_expression(grouped),
+        Expression::Assign(_, value) => visitor.visit_expression(value),
+        Expression::Call(_, callee, arguments) => {
+            visitor.visit_expression(callee);
+
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+        Expression::Get(_, object, _) => visitor.visit_expression(object),
+        Expression::Set(_, object, _, value) => {
+            visitor.visit_expression(object);
+            visitor.visit_expression(value);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use core::fmt;
+use std::{
+    fmt::{Display, Formatter},
+    rc::Rc,
+};
+
+use crate::span::Span;
+
+use super::value::{Function, Value};
+
+/// A single instruction, operands are indexes into the chunk's tables, the
+/// current call's stack slots or the list of globals
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpCode {
+    /// Pushes a value from the constants table
+    Constant(u32),
+    Nil,
+    True,
+    False,
+    Pop,
+
+    GetLocal(u32),
+    SetLocal(u32),
+    GetUpvalue(u32),
+    SetUpvalue(u32),
+    GetGlobal(u32),
+    SetGlobal(u32),
+    DefineGlobal(u32),
+    /// The operand is the property's name in the constants table
+    GetProperty(u32),
+    SetProperty(u32),
+
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+
+    /// Jumps to the instruction at the operand
+    Jump(u32),
+    /// Jumps when the value on top of the stack is falsy, leaving it there
+    JumpIfFalse(u32),
+    /// Jumps back to the start of a loop
+    Loop(u32),
+    /// Counts a statement or an iteration of a loop towards the step limit
+    Step,
+
+    /// Calls the value underneath the operand's number of arguments
+    Call(u32),
+    /// Creates a closure of the chunk's function at the operand
+    Closure(u32),
+    /// Moves the local on top of the stack into the closures that captured it
+    CloseUpvalue,
+    Return,
+
+    /// The operand is the class's name in the constants table
+    Class(u32),
+    /// Adds the closure on top of the stack to the class underneath it
+    Method(u32),
+}
+
+/// Compiled code of a single function
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub constants: Vec<Value>,
+    /// Functions declared inside this one
+    pub functions: Vec<Rc<Function>>,
+    /// Span of each run of instructions compiled from the same place, as the
+    /// index of the run's first instruction
+    spans: Vec<(usize, Span)>,
+}
+
+impl Chunk {
+    /// Appends the instruction, returning its index
+    pub fn write(&mut self, op_code: OpCode, span: Span) -> usize {
+        if self
+            .spans
+            .last()
+            .is_none_or(|(_, last_span)| *last_span != span)
+        {
+            self.spans.push((self.code.len(), span));
+        }
+
+        self.code.push(op_code);
+
+        self.code.len() - 1
+    }
+
+    pub fn add_constant(&mut self, value: Value) -> u32 {
+        self.constants.push(value);
+
+        (self.constants.len() - 1) as u32
+    }
+
+    pub fn add_function(&mut self, function: Function) -> u32 {
+        self.functions.push(Rc::new(function));
+
+        (self.functions.len() - 1) as u32
+    }
+
+    /// Where the instruction at `offset` was compiled from
+    pub fn span_at(&self, offset: usize) -> Span {
+        let run = self
+            .spans
+            .partition_point(|(first_offset, _)| *first_offset <= offset);
+
+        match run {
+            0 => Span::default(),
+            run => self.spans[run - 1].1,
+        }
+    }
+}
+
+/// Disassembles the function, followed by every function declared inside it
+impl Display for Function {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "== {} ==", self.name)?;
+
+        let mut last_line = None;
+
+        for (offset, op_code) in self.chunk.code.iter().enumerate() {
+            let line = self.chunk.span_at(offset).line;
+
+            if last_line == Some(line) {
+                write!(f, "{offset:04}    | ")?;
+            } else {
+                write!(f, "{offset:04} {line:4} ")?;
+            }
+
+            last_line = Some(line);
+
+            match op_code {
+                OpCode::Constant(index)
+                | OpCode::GetProperty(index)
+                | OpCode::SetProperty(index)
+                | OpCode::Class(index)
+                | OpCode::Method(index) => {
+                    writeln!(f, "{op_code:?} {}", self.chunk.constants[*index as usize])?
+                }
+                OpCode::Closure(index) => writeln!(
+                    f,
+                    "{op_code:?} {}",
+                    self.chunk.functions[*index as usize].name
+                )?,
+                op_code => writeln!(f, "{op_code:?}")?,
+            }
+        }
+
+        for function in &self.chunk.functions {
+            write!(f, "{function}")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,637 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    errors::LoxError,
+    parser::{
+        statements::{ClassStatement, FunStatement, IfStatement, Statement, WhileStatement},
+        ParsedStep, ParsingResult, StepKind,
+    },
+    resolver::{ResolvedVariable, VariableMap},
+    span::Span,
+    symbol::Symbol,
+    tree::expression::{Expression, ExpressionLiteral, ExpressionVariable, Operation},
+};
+
+use super::{
+    chunk::{Chunk, OpCode},
+    value::{Function, UpvalueSource, Value},
+};
+
+/// Everything the virtual machine needs to run a program, along with its globals
+pub struct Program {
+    /// The top level of the program, run like a function with no parameters
+    pub script: Rc<Function>,
+}
+
+/// Slot of every global, kept between programs compiled one after another so
+/// they share the globals
+#[derive(Default)]
+pub struct Globals {
+    slots: HashMap<Symbol, u32>,
+    /// Name of every global by its slot
+    pub names: Vec<Rc<str>>,
+}
+
+impl Globals {
+    /// Slot of the global, giving it the next one the first time it's used
+    pub fn slot(&mut self, name: Symbol) -> u32 {
+        if let Some(slot) = self.slots.get(&name) {
+            return *slot;
+        }
+
+        let slot = self.names.len() as u32;
+
+        self.slots.insert(name, slot);
+        self.names.push(name.as_str());
+
+        slot
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    /// A class's `init` method, which always returns the instance
+    Initializer,
+}
+
+struct Local {
+    depth: usize,
+    /// Whether a closure captured it, so it has to be moved off the stack when it goes out of scope
+    captured: bool,
+}
+
+/// Function being compiled, along with where its variables live
+struct FunctionState {
+    function: Function,
+    kind: FunctionKind,
+    /// Stack slots of the call, slot 0 holds the function itself or `this` for methods
+    locals: Vec<Local>,
+    scope_depth: usize,
+}
+
+/// One of the scopes the resolver gave variables slots in, mirrored so that its
+/// depths and slots can be turned into where the variables live on the stack
+struct Scope {
+    /// Index of the function whose stack holds the scope's variables, `None` for the globals
+    function: Option<usize>,
+    /// Stack slot of the scope's first variable
+    first_local: u32,
+}
+
+enum VariableSlot {
+    Local(u32),
+    Upvalue(u32),
+    Global(u32),
+}
+
+struct Compiler<'a> {
+    /// Innermost function last
+    functions: Vec<FunctionState>,
+    /// Innermost scope last, starting with the globals
+    scopes: Vec<Scope>,
+    variable_map: &'a VariableMap,
+    globals: &'a mut Globals,
+}
+
+/// Compiles the steps of a program into bytecode
+///
+/// Variables are found on the stack, in upvalues or in the globals through the
+/// scopes `variable_map` resolved them to, new globals are added to `globals`
+pub fn compile(
+    steps: &[ParsingResult],
+    variable_map: &VariableMap,
+    globals: &mut Globals,
+) -> Result<Program, LoxError> {
+    let mut compiler = Compiler {
+        functions: vec![],
+        scopes: vec![Scope {
+            function: None,
+            first_local: 0,
+        }],
+        variable_map,
+        globals,
+    };
+
+    compiler.begin_function("script".to_owned(), FunctionKind::Script);
+
+    for step in steps {
+        compiler.step(step.as_ref().map_err(Clone::clone)?)?;
+    }
+
+    let script = compiler.end_function(Span::default());
+
+    Ok(Program {
+        script: Rc::new(script),
+    })
+}
+
+impl Compiler<'_> {
+    fn state(&mut self) -> &mut FunctionState {
+        self.functions
+            .last_mut()
+            .expect("there's always a function being compiled")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state().function.chunk
+    }
+
+    fn emit(&mut self, op_code: OpCode, span: Span) -> usize {
+        self.chunk().write(op_code, span)
+    }
+
+    fn emit_constant(&mut self, value: Value, span: Span) {
+        let constant = self.chunk().add_constant(value);
+
+        self.emit(OpCode::Constant(constant), span);
+    }
+
+    fn name_constant(&mut self, name: Symbol) -> u32 {
+        self.chunk().add_constant(Value::String(name.as_str()))
+    }
+
+    /// Index the next instruction will be written at
+    fn next_offset(&mut self) -> u32 {
+        self.chunk().code.len() as u32
+    }
+
+    /// Points the jump at `offset` to the next instruction written
+    fn patch_jump(&mut self, offset: usize) {
+        let target = self.next_offset();
+
+        match &mut self.chunk().code[offset] {
+            OpCode::Jump(jump_target) | OpCode::JumpIfFalse(jump_target) => *jump_target = target,
+            op_code => unreachable!("{op_code:?} isn't a jump"),
+        }
+    }
+
+    fn global_slot(&mut self, name: Symbol) -> u32 {
+        self.globals.slot(name)
+    }
+
+    fn begin_function(&mut self, name: String, kind: FunctionKind) {
+        self.functions.push(FunctionState {
+            function: Function {
+                name,
+                ..Function::default()
+            },
+            kind,
+            locals: vec![Local {
+                depth: 0,
+                captured: false,
+            }],
+            scope_depth: 0,
+        });
+
+        // Methods hold `this` in slot 0, in a scope of its own like the resolver's
+        if let FunctionKind::Method | FunctionKind::Initializer = kind {
+            self.scopes.push(Scope {
+                function: Some(self.functions.len() - 1),
+                first_local: 0,
+            });
+        }
+    }
+
+    /// Finishes the innermost function with an implicit return
+    fn end_function(&mut self, span: Span) -> Function {
+        self.emit_implicit_return(span);
+
+        self.functions
+            .pop()
+            .expect("there's always a function being compiled")
+            .function
+    }
+
+    fn emit_implicit_return(&mut self, span: Span) {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit(OpCode::GetLocal(0), span);
+        } else {
+            self.emit(OpCode::Nil, span);
+        }
+
+        self.emit(OpCode::Return, span);
+    }
+
+    fn begin_scope(&mut self) {
+        let function = self.functions.len() - 1;
+        let state = self.state();
+
+        state.scope_depth += 1;
+
+        let first_local = state.locals.len() as u32;
+
+        self.scopes.push(Scope {
+            function: Some(function),
+            first_local,
+        });
+    }
+
+    fn end_scope(&mut self, span: Span) {
+        self.scopes.pop();
+
+        let state = self.state();
+
+        state.scope_depth -= 1;
+
+        let depth = state.scope_depth;
+        let mut pops = vec![];
+
+        while let Some(local) = state.locals.pop_if(|local| local.depth > depth) {
+            pops.push(if local.captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            });
+        }
+
+        for op_code in pops {
+            self.emit(op_code, span);
+        }
+    }
+
+    fn is_global_scope(&mut self) -> bool {
+        let state = self.state();
+
+        state.kind == FunctionKind::Script && state.scope_depth == 0
+    }
+
+    fn add_local(&mut self) {
+        let state = self.state();
+
+        state.locals.push(Local {
+            depth: state.scope_depth,
+            captured: false,
+        });
+    }
+
+    /// Stores the value on top of the stack in a new variable
+    fn define_variable(&mut self, name: Symbol, span: Span) {
+        if self.is_global_scope() {
+            let slot = self.global_slot(name);
+
+            self.emit(OpCode::DefineGlobal(slot), span);
+        } else {
+            // The value is already in the local's stack slot
+            self.add_local();
+        }
+    }
+
+    /// Where the variable lives, found through the scope the resolver resolved it to
+    fn resolve_variable(&mut self, variable: &ExpressionVariable) -> VariableSlot {
+        let resolved = self.variable_map.get(&variable.id).copied().flatten();
+
+        let Some(ResolvedVariable { depth, slot }) = resolved else {
+            // No scope declares it yet, but a global declared later still can
+            return VariableSlot::Global(self.global_slot(variable.identifier_name));
+        };
+
+        let scope = &self.scopes[self.scopes.len() - 1 - depth];
+        let innermost = self.functions.len() - 1;
+
+        match scope.function {
+            Some(function) if function == innermost => {
+                VariableSlot::Local(scope.first_local + slot as u32)
+            }
+            Some(function) => {
+                let slot = scope.first_local + slot as u32;
+
+                VariableSlot::Upvalue(self.capture(function, slot, innermost))
+            }
+            None => VariableSlot::Global(self.global_slot(variable.identifier_name)),
+        }
+    }
+
+    /// Captures the local at `slot` of an enclosing function in every function
+    /// from there down to `innermost`, returning its upvalue in `innermost`
+    fn capture(&mut self, function: usize, slot: u32, innermost: usize) -> u32 {
+        self.functions[function].locals[slot as usize].captured = true;
+
+        let mut upvalue = self.add_upvalue(function + 1, UpvalueSource::Local(slot));
+
+        for inner in function + 2..=innermost {
+            upvalue = self.add_upvalue(inner, UpvalueSource::Upvalue(upvalue));
+        }
+
+        upvalue
+    }
+
+    fn add_upvalue(&mut self, function: usize, source: UpvalueSource) -> u32 {
+        let upvalues = &mut self.functions[function].function.upvalues;
+
+        if let Some(index) = upvalues.iter().position(|upvalue| *upvalue == source) {
+            return index as u32;
+        }
+
+        upvalues.push(source);
+
+        (upvalues.len() - 1) as u32
+    }
+
+    fn steps(&mut self, steps: &[ParsingResult]) -> Result<(), LoxError> {
+        for step in steps {
+            self.step(step.as_ref().map_err(Clone::clone)?)?;
+        }
+
+        Ok(())
+    }
+
+    fn step(&mut self, step: &ParsedStep) -> Result<(), LoxError> {
+        match &step.kind {
+            StepKind::Expression(expression) => {
+                self.emit(OpCode::Step, step.span);
+                self.expression(expression);
+                self.emit(OpCode::Pop, step.span);
+            }
+            StepKind::Statement(statement) => {
+                self.emit(OpCode::Step, step.span);
+                self.statement(statement)?;
+            }
+            StepKind::Block(steps) => {
+                self.begin_scope();
+                self.steps(steps)?;
+                self.end_scope(step.span);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), LoxError> {
+        match statement {
+            Statement::Variable(span, name, value) => {
+                self.expression(value);
+                self.define_variable(*name, *span);
+            }
+            Statement::If(IfStatement {
+                span,
+                condition,
+                then_statement,
+                else_statement,
+            }) => {
+                self.expression(condition);
+
+                let else_jump = self.emit(OpCode::JumpIfFalse(0), *span);
+
+                self.emit(OpCode::Pop, *span);
+                self.step(then_statement)?;
+
+                let end_jump = self.emit(OpCode::Jump(0), *span);
+
+                self.patch_jump(else_jump);
+                self.emit(OpCode::Pop, *span);
+
+                if let Some(else_statement) = else_statement {
+                    self.step(else_statement)?;
+                }
+
+                self.patch_jump(end_jump);
+            }
+            Statement::While(WhileStatement {
+                span,
+                condition,
+                body,
+            }) => {
+                let loop_start = self.next_offset();
+
+                self.expression(condition);
+
+                let exit_jump = self.emit(OpCode::JumpIfFalse(0), *span);
+
+                self.emit(OpCode::Pop, *span);
+                // Counted separately from the body, which could be empty
+                self.emit(OpCode::Step, *span);
+                self.step(body)?;
+                self.emit(OpCode::Loop(loop_start), *span);
+
+                self.patch_jump(exit_jump);
+                self.emit(OpCode::Pop, *span);
+            }
+            Statement::Fun(function) => {
+                if self.is_global_scope() {
+                    self.function(function, function.name.to_string(), FunctionKind::Function)?;
+                    self.define_variable(function.name, function.span);
+                } else {
+                    // Declared before the body is compiled so the function can call itself
+                    self.add_local();
+                    self.function(function, function.name.to_string(), FunctionKind::Function)?;
+                }
+            }
+            Statement::Return(span, value) => {
+                match (value, self.state().kind) {
+                    (Some(value), _) => self.expression(value),
+                    (None, FunctionKind::Initializer) => {
+                        self.emit(OpCode::GetLocal(0), *span);
+                    }
+                    (None, _) => {
+                        self.emit(OpCode::Nil, *span);
+                    }
+                }
+
+                self.emit(OpCode::Return, *span);
+            }
+            Statement::Class(class) => self.class(class)?,
+        }
+
+        Ok(())
+    }
+
+    /// Compiles the function and leaves a closure of it on the stack
+    fn function(
+        &mut self,
+        function: &FunStatement,
+        name: String,
+        kind: FunctionKind,
+    ) -> Result<(), LoxError> {
+        let enclosing_scopes = self.scopes.len();
+
+        self.begin_function(name, kind);
+        self.begin_scope();
+
+        self.state().function.arity = function.parameters.len();
+
+        for _ in &function.parameters {
+            self.add_local();
+        }
+
+        self.step(&function.body)?;
+
+        let compiled = self.end_function(function.span);
+
+        // The scopes of the parameters, and of `this` for methods, end with the call
+        self.scopes.truncate(enclosing_scopes);
+
+        let index = self.chunk().add_function(compiled);
+
+        self.emit(OpCode::Closure(index), function.span);
+
+        Ok(())
+    }
+
+    fn class(&mut self, class: &ClassStatement) -> Result<(), LoxError> {
+        let name = self.name_constant(class.name);
+
+        let is_global = self.is_global_scope();
+
+        self.emit(OpCode::Class(name), class.span);
+        self.define_variable(class.name, class.span);
+
+        // The class is put back on the stack for the methods to be added to
+        let class_variable = if is_global {
+            OpCode::GetGlobal(self.global_slot(class.name))
+        } else {
+            OpCode::GetLocal(self.state().locals.len() as u32 - 1)
+        };
+
+        self.emit(class_variable, class.span);
+
+        for method in &class.methods {
+            let kind = if method.name == "init" {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+
+            self.function(method, format!("{}.{}", class.name, method.name), kind)?;
+
+            let name = self.name_constant(method.name);
+
+            self.emit(OpCode::Method(name), method.span);
+        }
+
+        self.emit(OpCode::Pop, class.span);
+
+        Ok(())
+    }
+
+    fn variable(&mut self, variable: &ExpressionVariable) {
+        let op_code = match self.resolve_variable(variable) {
+            VariableSlot::Local(slot) => OpCode::GetLocal(slot),
+            VariableSlot::Upvalue(upvalue) => OpCode::GetUpvalue(upvalue),
+            VariableSlot::Global(slot) => OpCode::GetGlobal(slot),
+        };
+
+        self.emit(op_code, variable.span);
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Literal(span, literal) => match literal {
+                ExpressionLiteral::Number(number) => {
+                    self.emit_constant(Value::Number(*number), *span)
+                }
+                ExpressionLiteral::String(string) => {
+                    self.emit_constant(Value::String(string.as_str().into()), *span)
+                }
+                ExpressionLiteral::True => {
+                    self.emit(OpCode::True, *span);
+                }
+                ExpressionLiteral::False => {
+                    self.emit(OpCode::False, *span);
+                }
+                ExpressionLiteral::Nil => {
+                    self.emit(OpCode::Nil, *span);
+                }
+                ExpressionLiteral::Reference(_) => {
+                    unreachable!("the parser never produces reference literals")
+                }
+            },
+            Expression::Operation(operation) => self.operation(operation),
+            Expression::Grouping(_span, grouped_expression) => self.expression(grouped_expression),
+            Expression::Variable(variable) => self.variable(variable),
+            Expression::Assign(variable, value) => {
+                self.expression(value);
+
+                let op_code = match self.resolve_variable(variable) {
+                    VariableSlot::Local(slot) => OpCode::SetLocal(slot),
+                    VariableSlot::Upvalue(upvalue) => OpCode::SetUpvalue(upvalue),
+                    VariableSlot::Global(slot) => OpCode::SetGlobal(slot),
+                };
+
+                self.emit(op_code, variable.span);
+            }
+            Expression::Call(span, callee, arguments) => {
+                self.expression(callee);
+
+                for argument in arguments {
+                    self.expression(argument);
+                }
+
+                self.emit(OpCode::Call(arguments.len() as u32), *span);
+            }
+            Expression::Get(span, object, name) => {
+                self.expression(object);
+
+                let name = self.name_constant(*name);
+
+                self.emit(OpCode::GetProperty(name), *span);
+            }
+            Expression::Set(span, object, name, value) => {
+                self.expression(object);
+                self.expression(value);
+
+                let name = self.name_constant(*name);
+
+                self.emit(OpCode::SetProperty(name), *span);
+            }
+            Expression::This(this) => self.variable(this),
+        }
+    }
+
+    fn operation(&mut self, operation: &Operation) {
+        let (left, right, op_code, span) = match operation {
+            Operation::Negate(unary) | Operation::Not(unary) => {
+                self.expression(&unary.operand);
+
+                let op_code = match operation {
+                    Operation::Negate(_) => OpCode::Negate,
+                    _ => OpCode::Not,
+                };
+
+                self.emit(op_code, unary.span);
+
+                return;
+            }
+            Operation::And(logical) => {
+                self.expression(&logical.left);
+
+                let end_jump = self.emit(OpCode::JumpIfFalse(0), logical.span);
+
+                self.emit(OpCode::Pop, logical.span);
+                self.expression(&logical.right);
+                self.patch_jump(end_jump);
+
+                return;
+            }
+            Operation::Or(logical) => {
+                self.expression(&logical.left);
+
+                let else_jump = self.emit(OpCode::JumpIfFalse(0), logical.span);
+                let end_jump = self.emit(OpCode::Jump(0), logical.span);
+
+                self.patch_jump(else_jump);
+                self.emit(OpCode::Pop, logical.span);
+                self.expression(&logical.right);
+                self.patch_jump(end_jump);
+
+                return;
+            }
+            Operation::Equal(op) => (&op.left, &op.right, OpCode::Equal, op.span),
+            Operation::NotEqual(op) => (&op.left, &op.right, OpCode::NotEqual, op.span),
+            Operation::Less(op) => (&op.left, &op.right, OpCode::Less, op.span),
+            Operation::LessEqual(op) => (&op.left, &op.right, OpCode::LessEqual, op.span),
+            Operation::Greater(op) => (&op.left, &op.right, OpCode::Greater, op.span),
+            Operation::GreaterEqual(op) => (&op.left, &op.right, OpCode::GreaterEqual, op.span),
+            Operation::Plus(op) => (&op.left, &op.right, OpCode::Add, op.span),
+            Operation::Minus(op) => (&op.left, &op.right, OpCode::Subtract, op.span),
+            Operation::Multiply(op) => (&op.left, &op.right, OpCode::Multiply, op.span),
+            Operation::Divide(op) => (&op.left, &op.right, OpCode::Divide, op.span),
+        };
+
+        self.expression(left);
+        self.expression(right);
+        self.emit(op_code, span);
+    }
+}
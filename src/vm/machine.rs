@@ -0,0 +1,555 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{
+    errors::{ErrorKind, LoxError, StackFrame},
+    interpreter::budget,
+    span::Span,
+};
+
+use super::{
+    chunk::OpCode,
+    compiler::Program,
+    native::list_property,
+    value::{
+        BoundFunction, BoundMethod, Class, Closure, Instance, NativeFunction, Upvalue,
+        UpvalueSource, Value,
+    },
+};
+
+/// A call in progress
+struct CallFrame {
+    closure: Rc<Closure>,
+    /// Index of the next instruction to run
+    ip: usize,
+    /// Stack slot of the function being called, its locals follow it
+    base: usize,
+    call_span: Span,
+}
+
+pub struct Vm<'g> {
+    stack: Vec<Value>,
+    /// The call currently running
+    frame: CallFrame,
+    /// Calls waiting on the current one to return, the script's frame first
+    callers: Vec<CallFrame>,
+    /// Value of every global by its slot, `None` until it's defined
+    globals: &'g mut Vec<Option<Value>>,
+    global_names: &'g [Rc<str>],
+    /// Upvalues still pointing at the stack, so closures created later share them
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    max_call_depth: usize,
+}
+
+impl<'g> Vm<'g> {
+    /// Sets up the machine to run the program with the globals of the ones run
+    /// before it, `global_names` has the name of every slot the program uses
+    pub fn new(
+        program: Program,
+        globals: &'g mut Vec<Option<Value>>,
+        global_names: &'g [Rc<str>],
+        max_call_depth: usize,
+    ) -> Self {
+        globals.resize(global_names.len(), None);
+
+        let script = Rc::new(Closure {
+            function: program.script,
+            upvalues: vec![],
+        });
+
+        Vm {
+            stack: vec![Value::Closure(script.clone())],
+            frame: CallFrame {
+                closure: script,
+                ip: 0,
+                base: 0,
+                call_span: Span::default(),
+            },
+            callers: vec![],
+            globals,
+            global_names,
+            open_upvalues: vec![],
+            max_call_depth,
+        }
+    }
+
+    pub fn run(&mut self) -> Result<(), LoxError> {
+        loop {
+            let op_code = self.frame.closure.function.chunk.code[self.frame.ip];
+
+            self.frame.ip += 1;
+
+            match op_code {
+                OpCode::Constant(constant) => {
+                    let value =
+                        self.frame.closure.function.chunk.constants[constant as usize].clone();
+
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Bool(true)),
+                OpCode::False => self.stack.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+
+                OpCode::GetLocal(slot) => {
+                    let value = self.stack[self.frame.base + slot as usize].clone();
+
+                    self.stack.push(value);
+                }
+                OpCode::SetLocal(slot) => {
+                    let value = self.peek(0).clone();
+
+                    self.stack[self.frame.base + slot as usize] = value;
+                }
+                OpCode::GetUpvalue(upvalue) => {
+                    let value = match &*self.frame.closure.upvalues[upvalue as usize].borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue(upvalue) => {
+                    let value = self.peek(0).clone();
+
+                    match &mut *self.frame.closure.upvalues[upvalue as usize].borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetGlobal(slot) => match &self.globals[slot as usize] {
+                    Some(value) => self.stack.push(value.clone()),
+                    None => return Err(self.undefined_global(slot)),
+                },
+                OpCode::SetGlobal(slot) => {
+                    if self.globals[slot as usize].is_none() {
+                        return Err(self.undefined_global(slot));
+                    }
+
+                    self.globals[slot as usize] = Some(self.peek(0).clone());
+                }
+                OpCode::DefineGlobal(slot) => {
+                    if self.globals[slot as usize].is_some() {
+                        let name = self.global_names[slot as usize].to_string();
+
+                        return Err(self.error(ErrorKind::AlreadyDefined(name)));
+                    }
+
+                    self.globals[slot as usize] = Some(self.pop());
+                }
+                OpCode::GetProperty(name) => {
+                    let name = self.constant_name(name);
+                    let object = self.pop();
+
+                    let property = match &object {
+                        Value::Instance(instance) => {
+                            let field = instance.fields.borrow().get(&name).cloned();
+
+                            field.or_else(|| {
+                                let method = instance.class.methods.borrow().get(&name).cloned();
+
+                                method.map(|method| {
+                                    Value::BoundMethod(Rc::new(BoundMethod {
+                                        receiver: object.clone(),
+                                        method: BoundFunction::Closure(method),
+                                    }))
+                                })
+                            })
+                        }
+                        Value::List(items) => list_property(items, &name),
+                        object => {
+                            return Err(
+                                self.error(ErrorKind::PropertyOnNonInstance(object.to_string()))
+                            )
+                        }
+                    };
+
+                    match property {
+                        Some(property) => self.stack.push(property),
+                        None => {
+                            return Err(self.error(ErrorKind::UndefinedProperty(name.to_string())))
+                        }
+                    }
+                }
+                OpCode::SetProperty(name) => {
+                    let name = self.constant_name(name);
+                    let value = self.pop();
+                    let object = self.pop();
+
+                    match object {
+                        Value::Instance(instance) => {
+                            instance.fields.borrow_mut().insert(name, value.clone());
+
+                            self.stack.push(value);
+                        }
+                        object => {
+                            return Err(
+                                self.error(ErrorKind::PropertyOnNonInstance(object.to_string()))
+                            )
+                        }
+                    }
+                }
+
+                OpCode::Equal | OpCode::NotEqual => {
+                    let right = self.pop();
+                    let left = self.pop();
+
+                    if !left.is_same_type(&right) {
+                        return Err(self.invalid_operands("compare", left, right));
+                    }
+
+                    let equal = left == right;
+
+                    self.stack.push(Value::Bool(if op_code == OpCode::Equal {
+                        equal
+                    } else {
+                        !equal
+                    }));
+                }
+                OpCode::Greater | OpCode::GreaterEqual | OpCode::Less | OpCode::LessEqual => {
+                    let right = self.pop();
+                    let left = self.pop();
+
+                    let (Value::Number(left_number), Value::Number(right_number)) = (&left, &right)
+                    else {
+                        return Err(self.invalid_operands("compare", left, right));
+                    };
+
+                    self.stack.push(Value::Bool(match op_code {
+                        OpCode::Greater => left_number > right_number,
+                        OpCode::GreaterEqual => left_number >= right_number,
+                        OpCode::Less => left_number < right_number,
+                        _ => left_number <= right_number,
+                    }));
+                }
+                OpCode::Add => {
+                    let right = self.pop();
+                    let left = self.pop();
+
+                    let sum = match (&left, &right) {
+                        (Value::Number(left_number), Value::Number(right_number)) => {
+                            Value::Number(left_number + right_number)
+                        }
+                        (Value::String(left_string), Value::String(right_string)) => {
+                            Value::String(format!("{left_string}{right_string}").into())
+                        }
+                        _ => return Err(self.invalid_operands("add", left, right)),
+                    };
+
+                    self.stack.push(sum);
+                }
+                OpCode::Subtract | OpCode::Multiply | OpCode::Divide => {
+                    let right = self.pop();
+                    let left = self.pop();
+
+                    let (operation, arithmetic): (&'static str, fn(f64, f64) -> f64) = match op_code
+                    {
+                        OpCode::Subtract => ("subtract", |left, right| left - right),
+                        OpCode::Multiply => ("multiply", |left, right| left * right),
+                        _ => ("divide", |left, right| left / right),
+                    };
+
+                    let (Value::Number(left_number), Value::Number(right_number)) = (&left, &right)
+                    else {
+                        return Err(self.invalid_operands(operation, left, right));
+                    };
+
+                    self.stack
+                        .push(Value::Number(arithmetic(*left_number, *right_number)));
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+
+                    self.stack.push(Value::Bool(!value.is_truthy()));
+                }
+                OpCode::Negate => match self.pop() {
+                    Value::Number(number) => self.stack.push(Value::Number(-number)),
+                    operand => {
+                        return Err(self.error(ErrorKind::InvalidOperand {
+                            operation: "negate",
+                            operand: operand.to_string(),
+                        }))
+                    }
+                },
+
+                OpCode::Jump(target) => self.frame.ip = target as usize,
+                OpCode::JumpIfFalse(target) => {
+                    if !self.peek(0).is_truthy() {
+                        self.frame.ip = target as usize;
+                    }
+                }
+                OpCode::Loop(target) => self.frame.ip = target as usize,
+                OpCode::Step => {
+                    budget::step(self.current_span())
+                        .map_err(|error| self.with_backtrace(error))?;
+                }
+
+                OpCode::Call(argument_count) => self.call(argument_count as usize)?,
+                OpCode::Closure(function) => {
+                    let function =
+                        self.frame.closure.function.chunk.functions[function as usize].clone();
+
+                    let upvalues = function
+                        .upvalues
+                        .iter()
+                        .map(|source| match source {
+                            UpvalueSource::Local(slot) => {
+                                self.capture_upvalue(self.frame.base + *slot as usize)
+                            }
+                            UpvalueSource::Upvalue(upvalue) => {
+                                self.frame.closure.upvalues[*upvalue as usize].clone()
+                            }
+                        })
+                        .collect();
+
+                    self.stack
+                        .push(Value::Closure(Rc::new(Closure { function, upvalues })));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let returned = self.pop();
+
+                    self.close_upvalues(self.frame.base);
+                    self.stack.truncate(self.frame.base);
+
+                    match self.callers.pop() {
+                        Some(caller) => self.frame = caller,
+                        // The script itself returned
+                        None => return Ok(()),
+                    }
+
+                    self.stack.push(returned);
+                }
+
+                OpCode::Class(name) => {
+                    let name = self.constant_name(name);
+
+                    self.stack.push(Value::Class(Rc::new(Class {
+                        name,
+                        methods: RefCell::new(HashMap::new()),
+                    })));
+                }
+                OpCode::Method(name) => {
+                    let name = self.constant_name(name);
+
+                    let (Value::Closure(method), Value::Class(class)) = (self.pop(), self.peek(0))
+                    else {
+                        unreachable!("methods are only compiled inside classes")
+                    };
+
+                    class.methods.borrow_mut().insert(name, method);
+                }
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack
+            .pop()
+            .expect("the compiler keeps the stack balanced")
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn constant_name(&self, constant: u32) -> Rc<str> {
+        match &self.frame.closure.function.chunk.constants[constant as usize] {
+            Value::String(name) => name.clone(),
+            constant => unreachable!("names are compiled as strings, found {constant}"),
+        }
+    }
+
+    fn call(&mut self, argument_count: usize) -> Result<(), LoxError> {
+        let callee_slot = self.stack.len() - argument_count - 1;
+
+        match self.stack[callee_slot].clone() {
+            Value::Closure(closure) => self.call_closure(closure, argument_count),
+            Value::BoundMethod(bound_method) => {
+                self.stack[callee_slot] = bound_method.receiver.clone();
+
+                match &bound_method.method {
+                    BoundFunction::Closure(closure) => {
+                        self.call_closure(closure.clone(), argument_count)
+                    }
+                    BoundFunction::Native(native) => self.call_native(native, argument_count, true),
+                }
+            }
+            Value::Native(native) => self.call_native(native, argument_count, false),
+            Value::Class(class) => {
+                let initializer = class.methods.borrow().get("init").cloned();
+
+                self.stack[callee_slot] = Value::Instance(Rc::new(Instance {
+                    class,
+                    fields: RefCell::new(HashMap::new()),
+                }));
+
+                match initializer {
+                    Some(initializer) => self.call_closure(initializer, argument_count),
+                    None if argument_count == 0 => Ok(()),
+                    None => Err(self.error(ErrorKind::ArityMismatch {
+                        expected: 0,
+                        found: argument_count,
+                    })),
+                }
+            }
+            callee => Err(self.error(ErrorKind::NotCallable(callee.to_string()))),
+        }
+    }
+
+    /// Checks the arity, budget and call depth in the same order as the tree walker
+    fn enter_call(&self, arity: usize, argument_count: usize) -> Result<(), LoxError> {
+        if arity != argument_count {
+            return Err(self.error(ErrorKind::ArityMismatch {
+                expected: arity,
+                found: argument_count,
+            }));
+        }
+
+        budget::step(self.current_span()).map_err(|error| self.with_backtrace(error))?;
+
+        // The script's own frame isn't a call
+        if self.callers.len() >= self.max_call_depth {
+            return Err(self.error(ErrorKind::StackOverflow {
+                limit: self.max_call_depth,
+            }));
+        }
+
+        Ok(())
+    }
+
+    fn call_closure(
+        &mut self,
+        closure: Rc<Closure>,
+        argument_count: usize,
+    ) -> Result<(), LoxError> {
+        self.enter_call(closure.function.arity, argument_count)?;
+
+        let frame = CallFrame {
+            closure,
+            ip: 0,
+            base: self.stack.len() - argument_count - 1,
+            call_span: self.current_span(),
+        };
+
+        self.callers.push(std::mem::replace(&mut self.frame, frame));
+
+        Ok(())
+    }
+
+    /// Calls the native, replacing the callee and its arguments with the result
+    ///
+    /// Bound natives are given the receiver in the callee's slot as their first argument
+    fn call_native(
+        &mut self,
+        native: &'static NativeFunction,
+        argument_count: usize,
+        bound: bool,
+    ) -> Result<(), LoxError> {
+        self.enter_call(native.arity, argument_count)?;
+
+        let span = self.current_span();
+        let callee_slot = self.stack.len() - argument_count - 1;
+        let first_argument = if bound { callee_slot } else { callee_slot + 1 };
+
+        let arguments = self.stack.split_off(first_argument);
+
+        let result = (native.function)(span, &arguments).map_err(|error| {
+            let mut backtrace = vec![StackFrame {
+                function: native.name.to_owned(),
+                call_span: span,
+            }];
+
+            backtrace.extend(self.backtrace());
+
+            error.with_backtrace(backtrace)
+        })?;
+
+        self.stack.truncate(callee_slot);
+        self.stack.push(result);
+
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let open = self.open_upvalues.iter().find(
+            |upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open_slot) if open_slot == slot),
+        );
+
+        if let Some(upvalue) = open {
+            return upvalue.clone();
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+
+        self.open_upvalues.push(upvalue.clone());
+
+        upvalue
+    }
+
+    /// Moves every variable from `first_slot` upwards off the stack and into the closures that captured it
+    fn close_upvalues(&mut self, first_slot: usize) {
+        let stack = &self.stack;
+
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= first_slot => {
+                    *upvalue = Upvalue::Closed(stack[slot].clone());
+
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    fn current_span(&self) -> Span {
+        self.frame
+            .closure
+            .function
+            .chunk
+            .span_at(self.frame.ip.saturating_sub(1))
+    }
+
+    /// Calls currently in progress, innermost first
+    fn backtrace(&self) -> Vec<StackFrame> {
+        std::iter::once(&self.frame)
+            .chain(self.callers.iter().rev())
+            // The script's frame is always last and isn't a call
+            .take(self.callers.len())
+            .map(|frame| StackFrame {
+                function: frame.closure.function.name.clone(),
+                call_span: frame.call_span,
+            })
+            .collect()
+    }
+
+    fn with_backtrace(&self, error: LoxError) -> LoxError {
+        error.with_backtrace(self.backtrace())
+    }
+
+    /// Error raised by the instruction that's running
+    fn error(&self, kind: ErrorKind) -> LoxError {
+        self.with_backtrace(LoxError::new(kind, self.current_span()))
+    }
+
+    fn undefined_global(&self, slot: u32) -> LoxError {
+        self.error(ErrorKind::UndefinedVariable(
+            self.global_names[slot as usize].to_string(),
+        ))
+    }
+
+    fn invalid_operands(&self, operation: &'static str, left: Value, right: Value) -> LoxError {
+        self.error(ErrorKind::InvalidOperands {
+            operation,
+            left: left.to_string(),
+            right: right.to_string(),
+        })
+    }
+}
@@ -0,0 +1,86 @@
+//! Second backend, which compiles the program to bytecode and runs it on a
+//! stack based virtual machine instead of walking the tree
+//!
+//! It prints the same output, raises the same errors and counts the same steps
+//! towards the step limit as the tree walker
+
+pub mod chunk;
+mod compiler;
+mod machine;
+pub(crate) mod native;
+mod tests;
+pub mod value;
+
+use compiler::Globals;
+use machine::Vm;
+use value::Value;
+
+use crate::{
+    errors::LoxError,
+    interpreter::{budget, functions::native::BUILT_INS, limits::Limits},
+    parser::ParsingResult,
+    resolver::VariableMap,
+    symbol::Symbol,
+};
+
+/// Compiles and runs the steps of a program, the bytecode equivalent of `interpreter::interpret`
+pub fn interpret(
+    variable_map: VariableMap,
+    steps: &[ParsingResult],
+    script_arguments: Vec<String>,
+    limits: Limits,
+) -> Result<(), LoxError> {
+    Session::new(script_arguments).run(variable_map, steps, limits)
+}
+
+/// Globals kept between programs run one after another, like the lines of the REPL
+pub struct Session {
+    globals: Globals,
+    /// Value of every global by its slot, `None` until it's defined
+    values: Vec<Option<Value>>,
+}
+
+impl Session {
+    /// Starts off with only the built-ins defined, in the first global slots
+    pub fn new(script_arguments: Vec<String>) -> Self {
+        let mut globals = Globals::default();
+
+        let values = BUILT_INS
+            .iter()
+            .map(|built_in| {
+                globals.slot(Symbol::intern(built_in.name));
+
+                Some((built_in.vm)(&script_arguments))
+            })
+            .collect();
+
+        Session { globals, values }
+    }
+
+    /// Compiles and runs steps resolved after the ones run before them, with the same resolver
+    pub fn run(
+        &mut self,
+        variable_map: VariableMap,
+        steps: &[ParsingResult],
+        limits: Limits,
+    ) -> Result<(), LoxError> {
+        let program = compiler::compile(steps, &variable_map, &mut self.globals)?;
+
+        report_trace!("Compiled bytecode\n{}", program.script);
+
+        budget::start(&limits);
+
+        Vm::new(
+            program,
+            &mut self.values,
+            &self.globals.names,
+            limits.max_call_depth,
+        )
+        .run()
+    }
+
+    /// How many globals have been defined, including the built-ins
+    pub fn global_count(&self) -> usize {
+        self.values.iter().flatten().count()
+    }
+}
@@ -0,0 +1,106 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    errors::{ErrorKind, LoxError},
+    interpreter::functions::{
+        list_index,
+        native::{exit_error, read_env, seconds_since_epoch, write_line},
+    },
+};
+
+use super::value::{BoundFunction, BoundMethod, NativeFunction, Value};
+
+pub static NOW: NativeFunction = NativeFunction {
+    name: "now",
+    arity: 0,
+    function: |_span, _args| Ok(Value::Number(seconds_since_epoch())),
+};
+
+pub static PRINT: NativeFunction = NativeFunction {
+    name: "print",
+    arity: 1,
+    function: |span, args| match args.first() {
+        Some(value) => {
+            write_line(span, &value.to_string())?;
+
+            Ok(Value::Nil)
+        }
+        None => Err(LoxError::new(
+            ErrorKind::InvalidArgument {
+                function: "print",
+                expected: "a value to print",
+            },
+            span,
+        )),
+    },
+};
+
+pub static ENV: NativeFunction = NativeFunction {
+    name: "env",
+    arity: 1,
+    function: |span, args| {
+        let name = match args.first() {
+            Some(Value::String(name)) => Some(name.as_ref()),
+            _ => None,
+        };
+
+        Ok(match read_env(span, name)? {
+            Some(value) => Value::String(value.into()),
+            None => Value::Nil,
+        })
+    },
+};
+
+pub static EXIT: NativeFunction = NativeFunction {
+    name: "exit",
+    arity: 1,
+    function: |span, args| {
+        let code = match args.first() {
+            Some(Value::Number(code)) => Some(*code),
+            _ => None,
+        };
+
+        Err(exit_error(span, code))
+    },
+};
+
+/// Bound to a list, which it's given as its first argument
+pub static LIST_GET: NativeFunction = NativeFunction {
+    name: "get",
+    arity: 1,
+    function: |span, args| match args {
+        [Value::List(items), index] => {
+            let index = match index {
+                Value::Number(index) => Some(*index),
+                _ => None,
+            };
+
+            let items = items.borrow();
+
+            Ok(items[list_index(span, index, items.len())?].clone())
+        }
+        _ => unreachable!("get is only ever bound to lists"),
+    },
+};
+
+/// List of the script's arguments, the value of the `args` built-in
+pub fn list(script_arguments: &[String]) -> Value {
+    let items = script_arguments
+        .iter()
+        .map(|argument| Value::String(argument.as_str().into()))
+        .collect();
+
+    Value::List(Rc::new(RefCell::new(items)))
+}
+
+/// Value of the property of a list, which are the same as the tree walker's lists
+pub fn list_property(items: &Rc<RefCell<Vec<Value>>>, name: &str) -> Option<Value> {
+    match name {
+        "length" => Some(Value::Number(items.borrow().len() as f64)),
+        "get" => Some(Value::BoundMethod(Rc::new(BoundMethod {
+            receiver: Value::List(items.clone()),
+            method: BoundFunction::Native(&LIST_GET),
+        }))),
+        _ => None,
+    }
+}
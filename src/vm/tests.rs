@@ -0,0 +1,94 @@
+#![cfg(test)]
+
+use crate::{
+    conformance::run_on, errors::ErrorKind, interpreter::limits::Limits, span::Span, Backend,
+};
+
+use super::chunk::{Chunk, OpCode};
+
+#[test]
+fn test_recursion_with_parameters() {
+    let (result, output) = run_on(
+        "fun fib(n) {\n    if n < 2 {\n        return n;\n    }\n    return fib(n - 1) + fib(n - 2);\n}\nprint(fib(20));",
+        Backend::Vm,
+        Limits::default(),
+    );
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "6765.00\n");
+}
+
+#[test]
+fn test_closures_share_captured_variables() {
+    let (result, output) = run_on(
+        "let get = nil;\nlet set = nil;\nfun make(initial) {\n    let value = initial;\n    fun getter() {\n        return value;\n    }\n    fun setter(new) {\n        value = new;\n    }\n    get = getter;\n    set = setter;\n}\nmake(\"before\");\nset(\"after\");\nprint(get());",
+        Backend::Vm,
+        Limits::default(),
+    );
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "\"after\"\n");
+}
+
+#[test]
+fn test_loop_iterations_capture_their_own_variables() {
+    let (result, output) = run_on(
+        "let first = nil;\nlet i = 0;\nwhile i < 3 {\n    let j = i;\n    fun show() {\n        print(j);\n    }\n    if i == 0 {\n        first = show;\n    }\n    i = i + 1;\n}\nfirst();",
+        Backend::Vm,
+        Limits::default(),
+    );
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "0.00\n");
+}
+
+#[test]
+fn test_runaway_recursion_is_stack_overflow() {
+    let (result, _) = run_on(
+        "fun f() {\n    return f();\n}\nf();",
+        Backend::Vm,
+        Limits {
+            max_call_depth: 10,
+            ..Limits::default()
+        },
+    );
+
+    let error = result.unwrap_err();
+
+    assert_eq!(error.kind, ErrorKind::StackOverflow { limit: 10 });
+    assert_eq!(error.backtrace.len(), 10);
+    assert_eq!(error.backtrace[9].call_span.line, 4);
+}
+
+#[test]
+fn test_step_limit_stops_infinite_loop() {
+    let (result, _) = run_on(
+        "while true {}",
+        Backend::Vm,
+        Limits {
+            max_steps: Some(100),
+            ..Limits::default()
+        },
+    );
+
+    assert_eq!(
+        result.unwrap_err().kind,
+        ErrorKind::StepLimitExceeded { limit: 100 }
+    );
+}
+
+#[test]
+fn test_chunk_finds_span_of_each_instruction() {
+    let first = Span::new(0, 1, 1, 1);
+    let second = Span::new(4, 5, 2, 1);
+
+    let mut chunk = Chunk::default();
+
+    chunk.write(OpCode::Nil, first);
+    chunk.write(OpCode::Pop, first);
+    chunk.write(OpCode::True, second);
+
+    assert_eq!(chunk.span_at(0), first);
+    assert_eq!(chunk.span_at(1), first);
+    assert_eq!(chunk.span_at(2), second);
+}
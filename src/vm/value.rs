@@ -0,0 +1,171 @@
+use core::fmt;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Debug, Display, Formatter},
+    rc::Rc,
+};
+
+use crate::{errors::LoxError, span::Span};
+
+use super::chunk::Chunk;
+
+/// A value on the virtual machine's stack
+///
+/// Displays exactly like the tree walker's `ExpressionLiteral`, so both
+/// backends print the same output
+#[derive(Clone)]
+pub enum Value {
+    Number(f64),
+    String(Rc<str>),
+    Bool(bool),
+    Nil,
+    Closure(Rc<Closure>),
+    Native(&'static NativeFunction),
+    Class(Rc<Class>),
+    Instance(Rc<Instance>),
+    BoundMethod(Rc<BoundMethod>),
+    List(Rc<RefCell<Vec<Value>>>),
+}
+
+/// A function as compiled, closures of it are created every time its declaration runs
+#[derive(Debug, Default)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub chunk: Chunk,
+    /// Where each of the closure's upvalues is captured from
+    pub upvalues: Vec<UpvalueSource>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpvalueSource {
+    /// A stack slot of the function the closure is created in
+    Local(u32),
+    /// One of the upvalues of the function the closure is created in
+    Upvalue(u32),
+}
+
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+/// A variable captured by a closure
+#[derive(Debug)]
+pub enum Upvalue {
+    /// Still on the stack, at the absolute slot
+    Open(usize),
+    /// Moved off the stack once the variable went out of scope
+    Closed(Value),
+}
+
+pub struct NativeFunction {
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: fn(Span, &[Value]) -> Result<Value, LoxError>,
+}
+
+pub struct Class {
+    pub name: Rc<str>,
+    pub methods: RefCell<HashMap<Rc<str>, Rc<Closure>>>,
+}
+
+pub struct Instance {
+    pub class: Rc<Class>,
+    pub fields: RefCell<HashMap<Rc<str>, Value>>,
+}
+
+/// A method taken off its instance, which becomes `this` when it's called
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: BoundFunction,
+}
+
+pub enum BoundFunction {
+    Closure(Rc<Closure>),
+    /// Natives bound to a value are given it as their first argument
+    Native(&'static NativeFunction),
+}
+
+impl Value {
+    /// Whether values of the two types can be compared, values that are only
+    /// ever equal to themselves can't be
+    pub fn is_same_type(&self, other: &Value) -> bool {
+        matches!(
+            (self, other),
+            (Value::Number(_), Value::Number(_))
+                | (Value::String(_), Value::String(_))
+                | (Value::Bool(_), Value::Bool(_))
+                | (Value::Nil, Value::Nil)
+        )
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Number(number) => *number != 0.0,
+            Value::String(string) => !string.is_empty(),
+            Value::Bool(bool) => *bool,
+            Value::Nil => false,
+            _ => true,
+        }
+    }
+}
+
+/// Only compares values of the same type, see `is_same_type`
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Number(left), Value::Number(right)) => left == right,
+            (Value::String(left), Value::String(right)) => left == right,
+            (Value::Bool(left), Value::Bool(right)) => left == right,
+            (Value::Nil, Value::Nil) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{:.2}", number),
+            Value::String(string) => write!(f, "\"{}\"", string),
+            Value::Bool(bool) => write!(f, "{}", bool),
+            Value::Nil => write!(f, "nil"),
+            Value::Closure(closure) => {
+                write_callable(f, &closure.function.name, closure.function.arity)
+            }
+            Value::Native(native) => write_callable(f, native.name, native.arity),
+            Value::Class(class) => write!(f, "@Class<Name = \"{}\">", class.name),
+            Value::Instance(instance) => {
+                write!(f, "@Instance<Name = \"{}\">", instance.class.name)
+            }
+            Value::BoundMethod(bound_method) => match &bound_method.method {
+                BoundFunction::Closure(closure) => {
+                    write_callable(f, &closure.function.name, closure.function.arity)
+                }
+                BoundFunction::Native(native) => write_callable(f, native.name, native.arity),
+            },
+            Value::List(items) => write!(
+                f,
+                "[{}]",
+                items
+                    .borrow()
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+fn write_callable(f: &mut Formatter, name: &str, arity: usize) -> fmt::Result {
+    write!(f, "@Callable<Name = \"{}\", Arity = {}>", name, arity)
+}
+
+impl Debug for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
@@ -0,0 +1,41 @@
+//! Entry points exported to the web playground, each returns the exit code the
+//! binary would have exited with
+
+use std::time::Duration;
+
+use wasm_bindgen::prelude::*;
+
+use crate::interpreter::limits::Limits;
+
+#[wasm_bindgen(js_name = run)]
+pub fn run_js(source: &str) -> i32 {
+    crate::run(source).exit_code()
+}
+
+/// Runs the source, stopping it once it has run `max_steps` statements, calls and
+/// loop iterations or for `timeout_ms` milliseconds, either can be left undefined
+#[wasm_bindgen(js_name = run_with_limits)]
+pub fn run_with_limits_js(source: &str, max_steps: Option<f64>, timeout_ms: Option<f64>) -> i32 {
+    let limits = Limits {
+        max_steps: max_steps.map(|max_steps| max_steps as u64),
+        timeout: timeout_ms.map(|timeout_ms| Duration::from_secs_f64(timeout_ms.max(0.0) / 1000.0)),
+        ..Limits::default()
+    };
+
+    crate::run_with_limits(source, vec![], limits).exit_code()
+}
+
+#[wasm_bindgen(js_name = check)]
+pub fn check_js(source: &str) -> i32 {
+    crate::check(source).exit_code()
+}
+
+#[wasm_bindgen(js_name = dump_tokens)]
+pub fn dump_tokens_js(source: &str) -> i32 {
+    crate::dump_tokens(source).exit_code()
+}
+
+#[wasm_bindgen(js_name = dump_ast)]
+pub fn dump_ast_js(source: &str) -> i32 {
+    crate::dump_ast(source).exit_code()
+}
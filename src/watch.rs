@@ -0,0 +1,71 @@
+use std::{
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{interpreter::limits::Limits, run_with_backend, Backend};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Runs the file, then polls it and re-runs it every time it's saved
+///
+/// Lox doesn't have imports yet, so the file itself is the only thing watched.
+/// Never returns, the process has to be killed to stop watching
+pub fn watch(
+    file_name: &str,
+    script_arguments: Vec<String>,
+    limits: Limits,
+    backend: Backend,
+) -> ! {
+    let watched_files: Vec<PathBuf> = vec![PathBuf::from(file_name)];
+
+    let mut last_modified = modification_times(&watched_files);
+    let mut run_count: usize = 0;
+
+    loop {
+        run_count += 1;
+
+        report!("\n==== run #{} of {} ====", run_count, file_name);
+
+        match std::fs::read_to_string(file_name) {
+            Ok(source) => {
+                let starting_time = Instant::now();
+
+                // Every run starts from a fresh environment, nothing is kept between runs
+                let status =
+                    run_with_backend(&source, script_arguments.clone(), limits.clone(), backend);
+
+                report!(
+                    "==== finished in {:.2}ms with exit code {}, waiting for changes ====",
+                    starting_time.elapsed().as_millis_f64(),
+                    status.exit_code()
+                );
+            }
+            Err(err) => {
+                report_error!("Couldn't read {}: {}", file_name, err);
+            }
+        }
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+
+            let modified = modification_times(&watched_files);
+
+            if modified != last_modified {
+                last_modified = modified;
+
+                break;
+            }
+        }
+    }
+}
+
+/// Missing files are recorded as `None` so that deleting and recreating a file
+/// counts as a change
+fn modification_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| std::fs::metadata(file).and_then(|metadata| metadata.modified()).ok())
+        .collect()
+}
@@ -8,7 +8,10 @@ import { create } from "zustand";
 import { persist } from "zustand/middleware";
 import "./App.css";
 import { useLogResults } from "./store";
-import init, { run } from "./wasm/rust-lox";
+import init, { LogLevel, run_with_limits, set_log_level } from "./wasm/rust-lox";
+
+// Programs run on the page's only thread, stop them before the tab freezes for good
+const RUN_TIMEOUT_MS = 5000;
 
 const useCodeState = create<{
   code: string;
@@ -46,6 +49,7 @@ function App() {
   const [isLoaded, setIsLoaded] = useState(false);
 
   const { log, clear } = useLogResults();
+  const [logLevel, setLogLevel] = useState<LogLevel>(LogLevel.Normal);
 
   useEffect(() => {
     init().then(() => {
@@ -117,7 +121,8 @@ function App() {
             disabled={!isLoaded}
             className="border bg-slate-800 border-black rounded-lg aspect-square grid place-content-center"
             onClick={() => {
-              run(code);
+              set_log_level(logLevel);
+              run_with_limits(code, undefined, RUN_TIMEOUT_MS);
             }}
           >
             <IconPlayerPlay height={"100%"} />
@@ -130,6 +135,16 @@ function App() {
           >
             Clear
           </button>
+          <select
+            className="border bg-slate-800 border-black rounded-lg p-1"
+            value={logLevel}
+            onChange={(e) => setLogLevel(Number(e.currentTarget.value))}
+          >
+            <option value={LogLevel.Quiet}>Quiet</option>
+            <option value={LogLevel.Normal}>Normal</option>
+            <option value={LogLevel.Verbose}>Verbose</option>
+            <option value={LogLevel.Trace}>Trace</option>
+          </select>
         </div>
         <div className="resize-none h-full bg-slate-800 border border-black rounded-sm whitespace-pre-wrap p-2 overflow-y-scroll">
           {log.join("\n")}